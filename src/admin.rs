@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/**
+ * Someone allowed to use the admin endpoints, by sending `Authorization: Bearer <token>`.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminUser {
+    pub name: String,
+    pub token: String,
+}
+
+impl AdminUser {
+    /// Check the token in a time that does not depend on how much of it is right. Both are hashed first, so it does not depend on its length either.
+    pub fn has_token(&self, token: &str) -> bool {
+        let expected = Sha256::digest(self.token.as_bytes());
+        let given = Sha256::digest(token.as_bytes());
+        expected
+            .iter()
+            .zip(given.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+    }
+}
+
+/**
+ * An action requested by an admin, to be performed by the update thread (which own the fetched data).
+ */
+#[derive(Debug, Clone)]
+pub enum AdminCommand {
+    /// Accept an update that was held back by the mass-deletion guard
+    AcceptPendingUpdate { source: String },
+    /// Load `overrides.json` again, and rebuild all the categories with it
+    ReloadOverrides,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_the_whole_token() {
+        let admin = AdminUser {
+            name: "admin".to_string(),
+            token: "secret-token".to_string(),
+        };
+        assert!(admin.has_token("secret-token"));
+        assert!(!admin.has_token("secret-tokeN"));
+        assert!(!admin.has_token("secret"));
+        assert!(!admin.has_token(""));
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+use depiction_map::{
+    Config, JsonGitBackend, SqliteBackend, StorageBackend, StorageConfig, copy_storage,
+};
+use env_logger::Env;
+use log::info;
+use safe_join::SafeJoin;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    JsonGit,
+    Sqlite,
+}
+
+/// Copy the current data of every source from one storage backend to another.
+/// The history is not copied, and the feeds stay as files in the save folder (whatever the backend).
+#[derive(Parser, Debug)]
+pub struct Opts {
+    /// The ressource directory, used to read the git remote and SQLite file name from config.json
+    ressource_path: PathBuf,
+    save_path: PathBuf,
+    #[arg(long, value_enum)]
+    from: Backend,
+    #[arg(long, value_enum)]
+    to: Backend,
+}
+
+fn open(
+    backend: Backend,
+    config: &Config,
+    save_path: &Path,
+) -> anyhow::Result<Box<dyn StorageBackend>> {
+    Ok(match backend {
+        Backend::JsonGit => Box::new(JsonGitBackend::new(
+            save_path.to_path_buf(),
+            config.remote.clone(),
+        )?),
+        Backend::Sqlite => {
+            let file = match &config.storage {
+                StorageConfig::Sqlite { file } => file.clone(),
+                StorageConfig::JsonGit => "storage.sqlite".to_string(),
+            };
+            let path = save_path.safe_join(&file).with_context(|| {
+                format!("Error joining directory {save_path:?} and file name {file:?}")
+            })?;
+            Box::new(SqliteBackend::new(&path)?)
+        }
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let opts = Opts::parse();
+    if opts.from == opts.to {
+        anyhow::bail!("The source and destination backends are the same");
+    }
+    let config = Config::load(&opts.ressource_path.join("config.json"))?;
+
+    let from = open(opts.from, &config, &opts.save_path)?;
+    let to = open(opts.to, &config, &opts.save_path)?;
+    let count = copy_storage(from.as_ref(), to.as_ref())?;
+    info!(
+        "Copied {count} sources. The feeds stay as files in {:?}, as with both backends. Set the storage in config.json to use the new backend.",
+        opts.save_path
+    );
+    Ok(())
+}
@@ -0,0 +1,358 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{Context, bail};
+use percent_encoding::percent_decode_str;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::{MapEntryImageSource, USER_AGENT};
+
+pub const COMMONS_API_URL: &str = "https://commons.wikimedia.org/w/api.php";
+
+/// Maximum number of titles the API accepts in one query
+const TITLES_PER_REQUEST: usize = 50;
+
+/// Return the (url-encoded) file name of a Wikimedia Commons image url
+pub fn commons_file_name(image_url: &str) -> Option<String> {
+    let url = Url::parse(image_url).ok()?;
+    let is_commons = match url.domain()? {
+        "commons.wikimedia.org" => url.path().starts_with("/wiki/Special:FilePath/"),
+        "upload.wikimedia.org" => url.path().starts_with("/wikipedia/commons/"),
+        _ => false,
+    };
+    if !is_commons {
+        return None;
+    }
+    url.path_segments()?
+        .next_back()
+        .filter(|file| !file.is_empty())
+        .map(|file| file.to_string())
+}
+
+/// Return the title of the page of a Wikimedia Commons image, like `File:Red dragon.jpg`
+pub fn commons_title(image_url: &str) -> Option<String> {
+    let file = commons_file_name(image_url)?;
+    let file = percent_decode_str(&file).decode_utf8().ok()?;
+    Some(format!("File:{}", file.replace('_', " ")))
+}
+
+/// Remove the HTML tags (the author is often a link to their user page) and decode the common entities
+pub(crate) fn strip_html(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/**
+ * The author and license of a Wikimedia Commons image. Every field is `None` for files that do not exist (anymore).
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommonsAttribution {
+    pub author: Option<String>,
+    /// Short name of the license, like `CC BY-SA 4.0`
+    pub license: Option<String>,
+    pub license_url: Option<String>,
+}
+
+impl CommonsAttribution {
+    /// The text crediting the image, like `Jane Doe, CC BY-SA 4.0`
+    pub fn credit_text(&self) -> Option<String> {
+        match (&self.author, &self.license) {
+            (Some(author), Some(license)) => Some(format!("{author}, {license}")),
+            (Some(author), None) => Some(author.clone()),
+            (None, Some(license)) => Some(license.clone()),
+            (None, None) => None,
+        }
+    }
+
+    /// Replace the credit of the image, keeping the previous one if nothing is known
+    pub fn apply(&self, image: &mut MapEntryImageSource) {
+        if let Some(credit_text) = self.credit_text() {
+            image.credit_text = Some(credit_text);
+        }
+        if self.license_url.is_some() {
+            image.license_url = self.license_url.clone();
+        }
+    }
+}
+
+/// Perform a GET request to the MediaWiki API, and parse its JSON answer. Fail if it is an error.
+pub(crate) fn get_json(client: &Client, url: &Url) -> anyhow::Result<Value> {
+    let response = client
+        .get(url.clone())
+        .send()
+        .with_context(|| format!("Querying {url}"))?;
+    if !response.status().is_success() {
+        bail!(
+            "Request failed with status code {}, the url being {}",
+            response.status(),
+            url
+        );
+    }
+    let text = response
+        .text()
+        .with_context(|| format!("Could not decode encoding of {url}"))?;
+    let answer: Value = serde_json::from_str(&text)
+        .with_context(|| format!("Could not parse answer from {url}"))?;
+    if let Some(error) = answer.get("error") {
+        bail!("The API answered with an error to {url}: {error}");
+    }
+    Ok(answer)
+}
+
+pub(crate) fn extmetadata_value(extmetadata: &Value, field: &str) -> Option<String> {
+    let value = extmetadata.get(field)?.get("value")?.as_str()?;
+    let value = strip_html(value);
+    if value.is_empty() { None } else { Some(value) }
+}
+
+/// Parse the answer of an `imageinfo` query (with `formatversion=2`), returning the attribution of each file by title. The titles are also present as they were requested, if they were normalized.
+pub fn parse_imageinfo_response(
+    response: &Value,
+) -> anyhow::Result<HashMap<String, CommonsAttribution>> {
+    let query = response
+        .get("query")
+        .context("No \"query\" in the answer of Commons")?;
+    let mut result = HashMap::new();
+    for page in query
+        .get("pages")
+        .and_then(|pages| pages.as_array())
+        .context("No \"pages\" in the answer of Commons")?
+    {
+        let Some(title) = page.get("title").and_then(|title| title.as_str()) else {
+            continue;
+        };
+        let Some(extmetadata) = page
+            .get("imageinfo")
+            .and_then(|imageinfo| imageinfo.get(0))
+            .and_then(|imageinfo| imageinfo.get("extmetadata"))
+        else {
+            continue;
+        };
+        result.insert(
+            title.to_string(),
+            CommonsAttribution {
+                author: extmetadata_value(extmetadata, "Artist"),
+                license: extmetadata_value(extmetadata, "LicenseShortName"),
+                license_url: extmetadata_value(extmetadata, "LicenseUrl"),
+            },
+        );
+    }
+
+    if let Some(normalized) = query.get("normalized").and_then(|n| n.as_array()) {
+        for normalization in normalized {
+            let from = normalization.get("from").and_then(|from| from.as_str());
+            let to = normalization.get("to").and_then(|to| to.as_str());
+            if let (Some(from), Some(to)) = (from, to)
+                && let Some(attribution) = result.get(to).cloned()
+            {
+                result.insert(from.to_string(), attribution);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// The result of [`CommonsMetadataFetcher::fetch`]
+#[derive(Debug, Default)]
+pub struct FetchedAttributions {
+    /// The attribution of the files that exist, by title. Those that do not exist are absent.
+    pub attributions: HashMap<String, CommonsAttribution>,
+    /// The titles that were queried, the other ones are to be queried again later
+    pub queried: Vec<String>,
+    /// The error that stopped the queries, if any
+    pub error: Option<anyhow::Error>,
+}
+
+/**
+ * Query the `extmetadata` of Wikimedia Commons files, for their author and license.
+ */
+pub struct CommonsMetadataFetcher {
+    client: Client,
+    api_url: String,
+}
+
+impl CommonsMetadataFetcher {
+    pub fn new(api_url: String) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(Duration::from_secs(60))
+                .build()?,
+            api_url,
+        })
+    }
+
+    /// Query the attribution of the given files, by chunks. If a query fails, the results of the previous ones are still returned, with the error.
+    pub fn fetch(&self, titles: &[String]) -> FetchedAttributions {
+        let mut result = FetchedAttributions::default();
+        for chunk in titles.chunks(TITLES_PER_REQUEST) {
+            match self.fetch_chunk(chunk) {
+                Ok(attributions) => {
+                    result.attributions.extend(attributions);
+                    result.queried.extend_from_slice(chunk);
+                }
+                Err(err) => {
+                    result.error = Some(err);
+                    break;
+                }
+            }
+        }
+        result
+    }
+
+    fn fetch_chunk(
+        &self,
+        titles: &[String],
+    ) -> anyhow::Result<HashMap<String, CommonsAttribution>> {
+        let mut url_to_query = Url::parse(&self.api_url)?;
+        url_to_query
+            .query_pairs_mut()
+            .append_pair("action", "query")
+            .append_pair("format", "json")
+            .append_pair("formatversion", "2")
+            .append_pair("prop", "imageinfo")
+            .append_pair("iiprop", "extmetadata")
+            .append_pair("iiextmetadatafilter", "Artist|LicenseShortName|LicenseUrl")
+            .append_pair("titles", &titles.join("|"));
+
+        let answer = get_json(&self.client, &url_to_query)?;
+        parse_imageinfo_response(&answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_imageinfo() {
+        let response = json!({
+            "batchcomplete": true,
+            "query": {
+                "normalized": [
+                    { "fromencoded": false, "from": "File:Red_dragon.jpg", "to": "File:Red dragon.jpg" }
+                ],
+                "pages": [
+                    {
+                        "ns": 6,
+                        "title": "File:Red dragon.jpg",
+                        "imageinfo": [{
+                            "extmetadata": {
+                                "Artist": {
+                                    "value": "<a href=\"//commons.wikimedia.org/wiki/User:Jane\" title=\"User:Jane\">Jane &amp; John</a>\n",
+                                    "source": "commons-desc-page"
+                                },
+                                "LicenseShortName": { "value": "CC BY-SA 4.0" },
+                                "LicenseUrl": { "value": "https://creativecommons.org/licenses/by-sa/4.0" }
+                            }
+                        }]
+                    },
+                    { "ns": 6, "title": "File:Missing.jpg", "missing": true },
+                    {
+                        "ns": 6,
+                        "title": "File:Public domain.png",
+                        "imageinfo": [{
+                            "extmetadata": {
+                                "Artist": { "value": "" },
+                                "LicenseShortName": { "value": "Public domain" }
+                            }
+                        }]
+                    }
+                ]
+            }
+        });
+        let result = parse_imageinfo_response(&response).unwrap();
+        let expected = CommonsAttribution {
+            author: Some("Jane & John".to_string()),
+            license: Some("CC BY-SA 4.0".to_string()),
+            license_url: Some("https://creativecommons.org/licenses/by-sa/4.0".to_string()),
+        };
+        assert_eq!(result.get("File:Red dragon.jpg"), Some(&expected));
+        assert_eq!(result.get("File:Red_dragon.jpg"), Some(&expected));
+        assert!(!result.contains_key("File:Missing.jpg"));
+        let public_domain = &result["File:Public domain.png"];
+        assert_eq!(public_domain.author, None);
+        assert_eq!(
+            public_domain.credit_text().as_deref(),
+            Some("Public domain")
+        );
+
+        assert!(parse_imageinfo_response(&json!({ "batchcomplete": true })).is_err());
+    }
+
+    #[test]
+    fn keeps_results_before_a_failed_query() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_url = format!("http://{}/w/api.php", listener.local_addr().unwrap());
+        // The first query succeeds, the next ones fail
+        thread::spawn(move || {
+            for (count, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                let answer = if count == 0 {
+                    let body = json!({
+                        "query": {
+                            "pages": [{
+                                "title": "File:0.jpg",
+                                "imageinfo": [{
+                                    "extmetadata": { "Artist": { "value": "Jane" } }
+                                }]
+                            }]
+                        }
+                    })
+                    .to_string();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                } else {
+                    "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_string()
+                };
+                stream.write_all(answer.as_bytes()).unwrap();
+            }
+        });
+
+        let titles: Vec<String> = (0..TITLES_PER_REQUEST * 2 + 1)
+            .map(|i| format!("File:{i}.jpg"))
+            .collect();
+        let fetched = CommonsMetadataFetcher::new(api_url).unwrap().fetch(&titles);
+        assert_eq!(fetched.queried, titles[..TITLES_PER_REQUEST]);
+        assert_eq!(
+            fetched.attributions["File:0.jpg"].author.as_deref(),
+            Some("Jane")
+        );
+        assert!(fetched.error.is_some());
+    }
+}
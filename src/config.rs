@@ -0,0 +1,367 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+use url::Url;
+
+use crate::{
+    AdminUser, CommonsCategoryConfig, EntryDiff, RefreshSchedule, RemoteConfig, RemoteThumbnails,
+    ScheduleConfig, SourceConfig, StorageConfig, ThumbnailConfig, WebhookTarget,
+};
+
+/**
+ * Deployment specific settings, read from `config.json` in the ressource directory. Every section is optional.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// How the fetched data is stored
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Where to push the storage git repository to (only with the json_git storage)
+    #[serde(default)]
+    pub remote: Option<RemoteConfig>,
+    /// Where to post a notification when the entries of a source change
+    #[serde(default)]
+    pub webhooks: Vec<WebhookTarget>,
+    #[serde(default)]
+    pub mass_deletion_guard: MassDeletionGuard,
+    /// Who can use the admin endpoints
+    #[serde(default)]
+    pub admins: Vec<AdminUser>,
+    #[serde(default)]
+    pub thumbnails: ThumbnailConfig,
+    /// Refresh schedule of the sources, by storage file name (like `osm_dragon.json`), replacing their default one
+    #[serde(default)]
+    pub schedules: BTreeMap<String, ScheduleConfig>,
+    /// Datasets to import, in addition to the hardcoded sources
+    #[serde(default)]
+    pub sources: Vec<SourceConfig>,
+    /// Where the map is served, like `https://dragons.example.org/`, to make the links of the feeds absolute
+    #[serde(default)]
+    pub public_url: Option<String>,
+    /// The geotagged dragons of Wikimedia Commons, only fetched if set, as walking the category tree makes many requests
+    #[serde(default)]
+    pub commons_dragon: Option<CommonsCategoryConfig>,
+}
+
+fn default_max_removed_percent() -> f64 {
+    50.0
+}
+
+fn default_min_entries() -> usize {
+    10
+}
+
+/**
+ * Refuse updates that remove too many entries at once, which is more likely to be a broken query or a truncated answer than a real change.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MassDeletionGuard {
+    /// Maximum percentage of the entries an update can remove without being held back. 100 disable the guard.
+    #[serde(default = "default_max_removed_percent")]
+    pub max_removed_percent: f64,
+    /// Sources with fewer entries than this are never held back, as removing a few of their entries is likely a real change
+    #[serde(default = "default_min_entries")]
+    pub min_entries: usize,
+}
+
+impl Default for MassDeletionGuard {
+    fn default() -> Self {
+        Self {
+            max_removed_percent: default_max_removed_percent(),
+            min_entries: default_min_entries(),
+        }
+    }
+}
+
+impl MassDeletionGuard {
+    fn check(&self) -> anyhow::Result<()> {
+        let max_removed_percent = self.max_removed_percent;
+        if !(0.0..=100.0).contains(&max_removed_percent) {
+            bail!("max_removed_percent should be between 0 and 100, not {max_removed_percent}");
+        }
+        Ok(())
+    }
+
+    /// Whether an update removing the entries of `diff`, from a source that had `previous_count` entries, should be held back. Removing exactly `max_removed_percent` is allowed.
+    pub fn is_triggered_by(&self, diff: &EntryDiff, previous_count: usize) -> bool {
+        if previous_count == 0 || previous_count < self.min_entries {
+            return false;
+        }
+        let removed_percent = diff.removed.len() as f64 * 100.0 / previous_count as f64;
+        removed_percent > self.max_removed_percent
+    }
+}
+
+impl Config {
+    /// Load the configuration at `path`, or use the default one if the file does not exist
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let f = File::open(path).with_context(|| format!("Opening config file {path:?}"))?;
+        let config: Self =
+            serde_json::from_reader(f).with_context(|| format!("Parsing config file {path:?}"))?;
+        // Refused now, as it would silently disable the guard or hold back every update
+        config
+            .mass_deletion_guard
+            .check()
+            .with_context(|| format!("In the config file {path:?}"))?;
+        Ok(config)
+    }
+
+    /// Return the settings that can be parsed but would not work as intended
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let mut tokens = HashSet::new();
+        for admin in &self.admins {
+            if admin.token.is_empty() {
+                problems.push(format!("The admin {:?} has an empty token", admin.name));
+            } else if !tokens.insert(&admin.token) {
+                problems.push(format!(
+                    "The admin {:?} has the same token as another admin",
+                    admin.name
+                ));
+            }
+        }
+
+        for webhook in &self.webhooks {
+            if let Err(err) = Url::parse(&webhook.url) {
+                problems.push(format!("Invalid webhook url {:?}: {err}", webhook.url));
+            }
+        }
+
+        if let Some(public_url) = &self.public_url {
+            match Url::parse(public_url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => (),
+                Ok(_) => problems.push(format!("The public url {public_url:?} is not http(s)")),
+                Err(err) => problems.push(format!("Invalid public url {public_url:?}: {err}")),
+            }
+        }
+
+        if self.remote.is_some() && !matches!(self.storage, StorageConfig::JsonGit) {
+            problems.push("The remote is only used with the json_git storage".to_string());
+        }
+
+        if let Err(err) = self.mass_deletion_guard.check() {
+            problems.push(format!("{err:#}"));
+        }
+
+        if self.thumbnails.width == 0 {
+            problems.push("The width of the thumbnails should not be 0".to_string());
+        }
+        if let RemoteThumbnails::CommonsPattern { pattern } = &self.thumbnails.remote
+            && !pattern.contains("{file}")
+        {
+            problems.push(format!(
+                "The Commons thumbnail pattern {pattern:?} does not contain {{file}}"
+            ));
+        }
+
+        for (source, schedule) in &self.schedules {
+            if let Err(err) = schedule.apply(RefreshSchedule::every(Duration::from_secs(3600))) {
+                problems.push(format!("Invalid schedule for {source:?}: {err:#}"));
+            }
+        }
+
+        if let Some(commons_dragon) = &self.commons_dragon {
+            problems.extend(commons_dragon.problems());
+        }
+
+        let mut namespaces = HashSet::new();
+        for source in &self.sources {
+            problems.extend(source.problems());
+            let storage_file_name = source.storage_file_name();
+            if source.refresh_interval_secs.is_some()
+                && self
+                    .schedules
+                    .get(&storage_file_name)
+                    .is_some_and(|schedule| schedule.interval_secs.is_some())
+            {
+                problems.push(format!(
+                    "The source {:?} has both a refresh_interval_secs and an interval_secs in the schedule of {storage_file_name:?}, only the latter is used",
+                    source.namespace
+                ));
+            }
+            if !namespaces.insert(&source.namespace) {
+                problems.push(format!(
+                    "Several sources have the namespace {:?}",
+                    source.namespace
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// The url where the map is served, ending with a `/` so urls can be joined to it
+    pub fn public_url(&self) -> Option<Url> {
+        let mut url = Url::parse(self.public_url.as_ref()?).ok()?;
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        Some(url)
+    }
+
+    /// The refresh schedule of the source stored in `storage_file_name`, `default` if it is not configured
+    pub fn schedule_for(
+        &self,
+        storage_file_name: &str,
+        default: RefreshSchedule,
+    ) -> anyhow::Result<RefreshSchedule> {
+        match self.schedules.get(storage_file_name) {
+            Some(schedule) => schedule
+                .apply(default)
+                .with_context(|| format!("Invalid schedule for {storage_file_name:?}")),
+            None => Ok(default),
+        }
+    }
+
+    /// Return the admin with the given token, if any. Every admin is checked, so the time taken does not tell which one matched.
+    pub fn find_admin(&self, token: &str) -> Option<&AdminUser> {
+        let mut found = None;
+        for admin in &self.admins {
+            if admin.has_token(token) && found.is_none() {
+                found = Some(admin);
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use serde_json::{Value, json};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::MapEntry;
+
+    fn removing(count: usize) -> EntryDiff {
+        let entry: MapEntry = serde_json::from_value(json!({
+            "pos": null,
+            "name": "dragon",
+            "location_name": null,
+            "image": null,
+            "source_url": null,
+            "source_text": "test",
+            "is_in_exhibit": false,
+            "nature": null,
+            "element_ids": [],
+        }))
+        .unwrap();
+        EntryDiff {
+            is_initial: false,
+            added: Vec::new(),
+            removed: vec![entry; count],
+            changed: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn mass_deletion_threshold() {
+        let guard = MassDeletionGuard {
+            max_removed_percent: 30.0,
+            min_entries: 10,
+        };
+        // Nothing to protect
+        assert!(!guard.is_triggered_by(&removing(0), 0));
+        // Exactly at the percentage is allowed
+        assert!(!guard.is_triggered_by(&removing(3), 10));
+        assert!(guard.is_triggered_by(&removing(4), 10));
+        assert!(guard.is_triggered_by(&removing(31), 100));
+        // Small sources are never held back
+        assert!(!guard.is_triggered_by(&removing(9), 9));
+        assert!(guard.is_triggered_by(&removing(10), 10));
+
+        let disabled = MassDeletionGuard {
+            max_removed_percent: 100.0,
+            min_entries: 0,
+        };
+        assert!(!disabled.is_triggered_by(&removing(10), 10));
+        let strict = MassDeletionGuard {
+            max_removed_percent: 0.0,
+            min_entries: 0,
+        };
+        assert!(strict.is_triggered_by(&removing(1), 1));
+        assert!(!strict.is_triggered_by(&removing(0), 1));
+    }
+
+    #[test]
+    fn refuses_an_invalid_guard_when_loading() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("config.json");
+        write(
+            &path,
+            r#"{ "mass_deletion_guard": { "max_removed_percent": 150 } }"#,
+        )
+        .unwrap();
+        assert!(Config::load(&path).is_err());
+        write(
+            &path,
+            r#"{ "mass_deletion_guard": { "max_removed_percent": 30 } }"#,
+        )
+        .unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.mass_deletion_guard.max_removed_percent, 30.0);
+        assert_eq!(config.mass_deletion_guard.min_entries, 10);
+    }
+
+    #[test]
+    fn public_url() {
+        let config = |public_url: &str| Config {
+            public_url: Some(public_url.to_string()),
+            ..Config::default()
+        };
+        assert_eq!(
+            config("https://dragons.example.org/map")
+                .public_url()
+                .unwrap()
+                .as_str(),
+            "https://dragons.example.org/map/"
+        );
+        assert!(config("https://dragons.example.org/").problems().is_empty());
+        assert_eq!(config("javascript:alert(1)").problems().len(), 1);
+        assert_eq!(config("not an url").problems().len(), 1);
+    }
+
+    #[test]
+    fn refresh_interval_set_twice() {
+        let config = |source_interval: Option<u64>, schedule: Value| -> Config {
+            serde_json::from_value(json!({
+                "sources": [{
+                    "namespace": "brittany",
+                    "title": "Dragons of Brittany",
+                    "type": "file",
+                    "location": "brittany.csv",
+                    "format": "csv",
+                    "refresh_interval_secs": source_interval,
+                }],
+                "schedules": { "external_brittany.json": schedule },
+            }))
+            .unwrap()
+        };
+        assert!(
+            config(Some(3600), json!({ "hours": "0-5" }))
+                .problems()
+                .is_empty()
+        );
+        assert!(
+            config(None, json!({ "interval_secs": 600 }))
+                .problems()
+                .is_empty()
+        );
+        let problems = config(Some(3600), json!({ "interval_secs": 600 })).problems();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("refresh_interval_secs"));
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    fs::{File, canonicalize, create_dir_all, rename},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use anyhow::{Context, bail};
+use git2::Repository;
+use log::info;
+use pathdiff::diff_paths;
+
+use crate::{DepictionCategory, ElementId, OverrideEntry, Overrides, make_commit_as};
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif"];
+
+/// Whether the name can be used for an uploaded image: a plain file name with an image extension
+pub fn is_valid_image_name(name: &str) -> bool {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    let extension_allowed = name.rsplit_once('.').is_some_and(|(_, extension)| {
+        IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+    });
+    valid_chars && !name.starts_with('.') && extension_allowed
+}
+
+/// Write the file through a temporary file, so it’s never left half-written
+fn write_atomically(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let mut temp_path = path.to_path_buf();
+    temp_path.set_file_name(format!(
+        ".{}.tmp",
+        path.file_name()
+            .with_context(|| format!("Can’t write {path:?} due to issue determining file path"))?
+            .to_string_lossy()
+    ));
+    {
+        let mut f = File::create(&temp_path)
+            .with_context(|| format!("Could not create/truncate file at {temp_path:?}"))?;
+        f.write_all(content)
+            .with_context(|| format!("Could not write to {temp_path:?}"))?;
+    }
+    rename(&temp_path, path).with_context(|| format!("Could not replace {path:?}"))?;
+    Ok(())
+}
+
+/**
+ * Edit the overrides and images of the ressource directory on behalf of an admin, commiting each change with the admin as the author.
+ *
+ * The ressource directory must be the root of a git repository, that is created with the first change if there is none.
+ */
+pub struct Curator {
+    ressource_path: PathBuf,
+    overrides_path: PathBuf,
+    images_path: PathBuf,
+    /// `None` until the repository is created. Also make sure only one edit happens at a time.
+    repo: Mutex<Option<Repository>>,
+}
+
+impl Curator {
+    pub fn new(ressource_path: &Path) -> anyhow::Result<Self> {
+        // Not discovered, as the ressource directory may be in another repository, like the one of this program
+        let repo = match Repository::open(ressource_path) {
+            Ok(repo) => Some(repo),
+            // Only created when commiting, so nothing is written if nothing is edited
+            Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+            Err(err) => {
+                return Err(Into::<anyhow::Error>::into(err))
+                    .with_context(|| format!("Opening the repo of {ressource_path:?}"));
+            }
+        };
+        if repo.as_ref().is_some_and(|repo| repo.workdir().is_none()) {
+            bail!("The repository of {ressource_path:?} is bare");
+        }
+
+        Ok(Self {
+            ressource_path: ressource_path.to_path_buf(),
+            overrides_path: ressource_path.join("overrides.json"),
+            images_path: ressource_path.join("images"),
+            repo: Mutex::new(repo),
+        })
+    }
+
+    fn lock_repo(&self) -> anyhow::Result<MutexGuard<'_, Option<Repository>>> {
+        match self.repo.lock() {
+            Ok(r) => Ok(r),
+            Err(err) => bail!("Failed to get repo: {:?}", err),
+        }
+    }
+
+    fn commit(
+        &self,
+        repo: &mut Option<Repository>,
+        path: &Path,
+        message: &str,
+        editor: &str,
+    ) -> anyhow::Result<()> {
+        let repo = match repo {
+            Some(repo) => repo,
+            None => {
+                let ressource_path = &self.ressource_path;
+                info!("Creating new ressource repo in {ressource_path:?}");
+                repo.insert(Repository::init(ressource_path).with_context(|| {
+                    format!("Creating new ressource repo in {ressource_path:?}")
+                })?)
+            }
+        };
+        let workdir = repo.workdir().context("The ressource repo is bare")?;
+        let relative_path = diff_paths(canonicalize(path)?, canonicalize(workdir)?)
+            .context("Could not diff paths for indexing with git")?;
+        make_commit_as(repo, &relative_path, message, Some(editor))
+            .with_context(|| format!("Commiting {relative_path:?}"))
+    }
+
+    /// Replace the override of the element, either global or for the given category, or remove it if `None`. The file is read again first, to keep the changes made by hand.
+    pub fn set_override(
+        &self,
+        element_id: &ElementId,
+        category: Option<&DepictionCategory>,
+        override_entry: Option<OverrideEntry>,
+        editor: &str,
+    ) -> anyhow::Result<()> {
+        let mut repo = self.lock_repo()?;
+        let mut overrides = Overrides::load(&self.overrides_path)?;
+        let scope = match category {
+            Some(category) => format!(" in {}", category.0),
+            None => String::new(),
+        };
+        let message = match override_entry {
+            Some(_) => format!("Override {element_id}{scope}"),
+            None => format!("Remove the override of {element_id}{scope}"),
+        };
+        overrides.set_override(element_id, category, override_entry);
+
+        let mut content = serde_json::to_vec_pretty(&overrides)?;
+        content.push(b'\n');
+        write_atomically(&self.overrides_path, &content)?;
+        self.commit(&mut repo, &self.overrides_path, &message, editor)?;
+        info!("{editor} changed the override of {element_id}{scope}");
+        Ok(())
+    }
+
+    pub fn image_exists(&self, name: &str) -> bool {
+        self.images_path.join(name).exists()
+    }
+
+    /// Add an image, that can then be used as a `local_image`. Fail if the name is invalid or already used.
+    pub fn add_image(&self, name: &str, content: &[u8], editor: &str) -> anyhow::Result<()> {
+        if !is_valid_image_name(name) {
+            bail!("Invalid image name {name:?}");
+        }
+        let mut repo = self.lock_repo()?;
+        let path = self.images_path.join(name);
+        if path.exists() {
+            bail!("There is already an image named {name:?}");
+        }
+        create_dir_all(&self.images_path)
+            .with_context(|| format!("Could not create dir at {:?}", self.images_path))?;
+        write_atomically(&path, content)?;
+        self.commit(&mut repo, &path, &format!("Add image {name}"), editor)?;
+        info!("{editor} uploaded the image {name}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn uses_its_own_repository() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let ressource_path = dir.join("ressources");
+        create_dir_all(&ressource_path).unwrap();
+        Repository::init(dir).unwrap();
+
+        let curator = Curator::new(&ressource_path).unwrap();
+        curator.add_image("dragon.png", b"png", "admin").unwrap();
+
+        let repo = Repository::open(&ressource_path).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert!(
+            head.tree()
+                .unwrap()
+                .get_path(Path::new("images/dragon.png"))
+                .is_ok()
+        );
+        assert!(Repository::open(dir).unwrap().head().is_err());
+    }
+
+    #[test]
+    fn creates_the_repository_when_first_editing() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+
+        let curator = Curator::new(dir).unwrap();
+        assert!(!dir.join(".git").exists());
+        curator.add_image("dragon.png", b"png", "admin").unwrap();
+        let repo = Repository::open(dir).unwrap();
+        assert!(repo.head().unwrap().peel_to_commit().is_ok());
+    }
+}
@@ -1,25 +1,49 @@
 use log::error;
 use std::{
+    collections::{BTreeSet, HashSet},
     path::PathBuf,
-    sync::Arc,
+    sync::{
+        Arc, Mutex,
+        mpsc::{Receiver, Sender, channel},
+    },
     thread::{self, JoinHandle, sleep},
     time::Duration,
 };
 
+use actix_web::web::Bytes;
 use anyhow::Context;
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use log::{info, warn};
-use tai_time::TaiTime;
 
-use crate::{DisplayDataSet, DisplayDataSetEntry, FetchedDataSet};
+use crate::{
+    AdminCommand, Curator, DepictionCategory, DisplayDataSet, DisplayDataSetEntry, FetchedDataSet,
+    FileCommit, MapEntry, SystemTimeSource, TimeSource, UpdateOutcome,
+    fetched_data_set::FetchDataExtra,
+};
 
 pub struct DepictAppData {
     pub display_data_set: Arc<DisplayDataSet>,
     pub ressource_path: PathBuf,
+    /// Edit the ressource directory for the admins. Only set if there are admins.
+    pub curator: Option<Curator>,
+    pub time_source: Arc<SystemTimeSource>,
+    pub extra: Arc<FetchDataExtra>,
+    /// The name of every source
+    pub sources: BTreeSet<String>,
+    /// Set when serving the stored data without ever updating it
+    pub offline: bool,
+    /// Send actions to be performed by the update thread
+    pub admin_commands: Sender<AdminCommand>,
+    admin_receiver: Mutex<Option<Receiver<AdminCommand>>>,
 }
 
 impl DepictAppData {
-    pub fn new(fetched_data_set: &FetchedDataSet, ressource_path: PathBuf) -> anyhow::Result<Self> {
+    /// If `offline` is set, the stored data is served as it is: the update thread must not be started, and the admin commands are refused
+    pub fn new(
+        fetched_data_set: &FetchedDataSet,
+        ressource_path: PathBuf,
+        offline: bool,
+    ) -> anyhow::Result<Self> {
         let mut display_data_set =
             DisplayDataSet::new(&fetched_data_set.list_all_depiction_category());
 
@@ -32,65 +56,243 @@ impl DepictAppData {
                         .context("Storing the result in DisplayDataSetEntry")?,
                 ),
             );
+            display_data_set.feeds.insert(
+                depiction.clone(),
+                ArcSwap::from_pointee(Bytes::from(
+                    fetched_data_set.build_atom_for_depiction_category(depiction),
+                )),
+            );
+        }
+
+        let mut sources = BTreeSet::new();
+        for entry in &fetched_data_set.entries {
+            sources.insert(entry.storage.get_name().to_string());
+            display_data_set.last_diffs.insert(
+                entry.storage.get_name().to_string(),
+                ArcSwapOption::new(entry.storage.data.private.last_diff.clone().map(Arc::new)),
+            );
+            display_data_set.statuses.insert(
+                entry.storage.get_name().to_string(),
+                ArcSwap::from_pointee(entry.status()),
+            );
+        }
+
+        let override_report = fetched_data_set.check_overrides();
+        for problem in &override_report.problems {
+            warn!("Problem in the overrides: {problem}");
         }
+        display_data_set
+            .override_report
+            .store(Arc::new(override_report));
+
+        let curator = if offline || fetched_data_set.extra.config.admins.is_empty() {
+            None
+        } else {
+            Some(Curator::new(&ressource_path).context("Preparing the edition of the ressources")?)
+        };
+
+        let (admin_commands, admin_receiver) = channel();
+        let admin_receiver = if offline {
+            info!("Offline mode: serving the stored data, without ever updating it");
+            // Dropping it makes the admin commands fail, instead of waiting forever
+            None
+        } else {
+            Some(admin_receiver)
+        };
 
         Ok(Self {
             display_data_set: Arc::new(display_data_set),
             ressource_path,
+            curator,
+            time_source: Arc::new(SystemTimeSource::default()),
+            extra: fetched_data_set.extra.clone(),
+            sources,
+            offline,
+            admin_commands,
+            admin_receiver: Mutex::new(admin_receiver),
         })
     }
 
+    /// List the revisions that changed the data of the given source, up to `limit` of them and older than `before` if given. Return `None` if either the source or `before` does not exist.
+    pub fn source_history(
+        &self,
+        source: &str,
+        before: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Option<Vec<FileCommit>>> {
+        if !self.sources.contains(source) {
+            return Ok(None);
+        }
+        self.extra.backend.history(source, before, limit)
+    }
+
+    /// Return the entries of the given source as of the given revision. Return `None` if either the source or the revision does not exist.
+    pub fn source_entries_at(
+        &self,
+        source: &str,
+        revision: &str,
+    ) -> anyhow::Result<Option<BTreeSet<MapEntry>>> {
+        if !self.sources.contains(source) {
+            return Ok(None);
+        }
+        self.extra.backend.entries_at(source, revision)
+    }
+
     /// Will panic if called more than once
     pub fn start_update_thread(&mut self, mut fetched_data_set: FetchedDataSet) -> JoinHandle<()> {
         let display_data_set = self.display_data_set.clone();
+        let time_source = self.time_source.clone();
+        let admin_receiver = self.admin_receiver.get_mut().unwrap().take().unwrap();
 
         thread::spawn(move || {
             info!("Update thread spawned");
             loop {
+                for command in admin_receiver.try_iter() {
+                    handle_admin_command(
+                        command,
+                        &mut fetched_data_set,
+                        &display_data_set,
+                        time_source.as_ref(),
+                    );
+                }
+                match fetched_data_set.reload_overrides_if_modified() {
+                    Ok(true) => publish_all_categories(&fetched_data_set, &display_data_set),
+                    Ok(false) => (),
+                    Err(err) => {
+                        error!("Could not reload the overrides, keeping the previous ones: {err:?}")
+                    }
+                }
                 for entry_pos in 0..fetched_data_set.entries.len() {
-                    let current_time = match TaiTime::try_now() {
-                        Ok(t) => t,
-                        Err(err) => {
-                            panic!(
-                                "Something seriously wrong happened getting the current (TAI) time ({err:?}). The update thread will be destroyed."
-                            )
-                        }
-                    };
-                    match fetched_data_set.entries[entry_pos].perform_update_if_needed(current_time)
-                    {
-                        Ok(false) => (),
-                        Ok(true) => {
-                            for depiction in &fetched_data_set.entries[entry_pos].depict {
-                                let map_entries = fetched_data_set
-                                    .build_data_for_depiction_category(depiction.clone());
-                                let display_entry =
-                                    display_data_set.to_display.get(depiction).unwrap(); // All possible value should be set in the constructor (althought a bad implementation could lead to a panic here)
-                                match DisplayDataSetEntry::new(map_entries) {
-                                    Ok(entry) => display_entry.swap(Arc::new(entry)),
-                                    Err(err) => {
-                                        error!(
-                                            "Failed to create the struct used to share the data with the other threads: {err:#}"
-                                        );
-                                        continue;
-                                    }
-                                };
-                                info!(
-                                    "Update successfull for {:?}",
-                                    fetched_data_set.entries[entry_pos].fetcher.title()
-                                );
-                            }
+                    match fetched_data_set.update_source(entry_pos, time_source.as_ref(), false) {
+                        Some(UpdateOutcome::NotNeeded) => (),
+                        Some(UpdateOutcome::Updated) => {
+                            publish_update(&fetched_data_set, &display_data_set, entry_pos)
                         }
-                        Err(err) => {
-                            warn!(
-                                "Could not perform update of {:?}: {:?}",
-                                fetched_data_set.entries[entry_pos].fetcher.title(),
-                                err
-                            )
+                        Some(UpdateOutcome::HeldBack) | None => {
+                            publish_status(&fetched_data_set, &display_data_set, entry_pos)
                         }
                     }
                 }
+                if let Err(err) = fetched_data_set.extra.backend.maintenance() {
+                    warn!("Could not perform the storage maintenance: {err:?}");
+                }
                 sleep(Duration::from_secs(10));
             }
         })
     }
 }
+
+fn handle_admin_command(
+    command: AdminCommand,
+    fetched_data_set: &mut FetchedDataSet,
+    display_data_set: &DisplayDataSet,
+    time_source: &dyn TimeSource,
+) {
+    match command {
+        AdminCommand::AcceptPendingUpdate { source } => {
+            let Some(entry_pos) = fetched_data_set.find_source(&source) else {
+                warn!("Asked to accept the update of unknown source {source:?}");
+                return;
+            };
+            match fetched_data_set.accept_pending_update(entry_pos, time_source) {
+                Ok(true) => publish_update(fetched_data_set, display_data_set, entry_pos),
+                Ok(false) => warn!("There is no update to accept for {source:?}"),
+                Err(err) => error!("Could not apply the accepted update of {source:?}: {err:?}"),
+            }
+        }
+        AdminCommand::ReloadOverrides => match fetched_data_set.reload_overrides() {
+            Ok(()) => publish_all_categories(fetched_data_set, display_data_set),
+            Err(err) => {
+                error!("Could not reload the overrides, keeping the previous ones: {err:?}")
+            }
+        },
+    }
+}
+
+fn publish_status(
+    fetched_data_set: &FetchedDataSet,
+    display_data_set: &DisplayDataSet,
+    entry_pos: usize,
+) {
+    let entry = &fetched_data_set.entries[entry_pos];
+    if let Some(status) = display_data_set.statuses.get(entry.storage.get_name()) {
+        status.store(Arc::new(entry.status()));
+    }
+}
+
+/// Share the result of a successful update of the given source (already recorded in the feeds) with the other threads
+fn publish_update(
+    fetched_data_set: &FetchedDataSet,
+    display_data_set: &DisplayDataSet,
+    entry_pos: usize,
+) {
+    publish_status(fetched_data_set, display_data_set, entry_pos);
+    let storage = &fetched_data_set.entries[entry_pos].storage;
+    if let Some(last_diff) = display_data_set.last_diffs.get(storage.get_name()) {
+        last_diff.store(storage.data.private.last_diff.clone().map(Arc::new));
+    }
+    publish_override_report(fetched_data_set, display_data_set);
+    for depiction in &fetched_data_set.entries[entry_pos].depict {
+        if let Some(feed) = display_data_set.feeds.get(depiction) {
+            feed.store(Arc::new(Bytes::from(
+                fetched_data_set.build_atom_for_depiction_category(depiction),
+            )));
+        }
+        if !publish_category(fetched_data_set, display_data_set, depiction) {
+            continue;
+        }
+        info!(
+            "Update successfull for {:?}",
+            fetched_data_set.entries[entry_pos].fetcher.title()
+        );
+    }
+}
+
+/// Rebuild the entries displayed for the given category. Return false if it failed.
+fn publish_category(
+    fetched_data_set: &FetchedDataSet,
+    display_data_set: &DisplayDataSet,
+    depiction: &DepictionCategory,
+) -> bool {
+    let map_entries = fetched_data_set.build_data_for_depiction_category(depiction.clone());
+    let display_entry = display_data_set.to_display.get(depiction).unwrap(); // All possible value should be set in the constructor (althought a bad implementation could lead to a panic here)
+    match DisplayDataSetEntry::new(map_entries) {
+        Ok(entry) => display_entry.swap(Arc::new(entry)),
+        Err(err) => {
+            error!(
+                "Failed to create the struct used to share the data with the other threads: {err:#}"
+            );
+            return false;
+        }
+    };
+    true
+}
+
+/// Rebuild every category, after the overrides changed
+fn publish_all_categories(fetched_data_set: &FetchedDataSet, display_data_set: &DisplayDataSet) {
+    for depiction in fetched_data_set.list_all_depiction_category() {
+        publish_category(fetched_data_set, display_data_set, depiction);
+    }
+    info!("Rebuilt all categories with the new overrides");
+    publish_override_report(fetched_data_set, display_data_set);
+}
+
+/// Check the overrides again, logging the problems that were not there before
+fn publish_override_report(fetched_data_set: &FetchedDataSet, display_data_set: &DisplayDataSet) {
+    let override_report = fetched_data_set.check_overrides();
+    let previous_problems: HashSet<String> = display_data_set
+        .override_report
+        .load()
+        .problems
+        .iter()
+        .map(|problem| problem.to_string())
+        .collect();
+    for problem in &override_report.problems {
+        if !previous_problems.contains(&problem.to_string()) {
+            warn!("Problem in the overrides: {problem}");
+        }
+    }
+    display_data_set
+        .override_report
+        .store(Arc::new(override_report));
+}
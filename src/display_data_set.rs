@@ -1,9 +1,9 @@
 use std::collections::{HashMap, HashSet};
 
 use actix_web::web::Bytes;
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 
-use crate::{DepictionCategory, MapEntry};
+use crate::{DepictionCategory, EntryDiff, MapEntry, OverrideReport, SourceStatus};
 
 pub struct DisplayDataSetEntry {
     pub entries: Vec<MapEntry>,
@@ -32,14 +32,30 @@ impl Default for DisplayDataSetEntry {
 
 pub struct DisplayDataSet {
     pub to_display: HashMap<DepictionCategory, ArcSwap<DisplayDataSetEntry>>,
+    /// The changes found by the last update of each source, by source name
+    pub last_diffs: HashMap<String, ArcSwapOption<EntryDiff>>,
+    /// Atom feed of the additions and removals of each category
+    pub feeds: HashMap<DepictionCategory, ArcSwap<Bytes>>,
+    /// Status of each source, by source name
+    pub statuses: HashMap<String, ArcSwap<SourceStatus>>,
+    /// The problems found in the overrides, updated when the overrides or entries change
+    pub override_report: ArcSwap<OverrideReport>,
 }
 
 impl DisplayDataSet {
     pub fn new(depictions: &HashSet<&DepictionCategory>) -> Self {
         let mut to_display = HashMap::new();
+        let mut feeds = HashMap::new();
         for depiction in depictions.iter() {
             to_display.insert((*depiction).clone(), ArcSwap::default());
+            feeds.insert((*depiction).clone(), ArcSwap::default());
+        }
+        Self {
+            to_display,
+            last_diffs: HashMap::new(),
+            feeds,
+            statuses: HashMap::new(),
+            override_report: ArcSwap::default(),
         }
-        Self { to_display }
     }
 }
@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ElementId, MapEntry, storage::keyed_entries};
+
+/// Maximum number of entry listed in a commit message
+const MAX_COMMIT_MESSAGE_LINES: usize = 100;
+
+/// An entry that exist both before and after an update, but with different values
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryChange {
+    pub id: ElementId,
+    /// Name of the `MapEntry` fields that changed
+    pub fields: Vec<String>,
+    pub old: MapEntry,
+    pub new: MapEntry,
+}
+
+/**
+ * The difference between two versions of the entries of a source, with entries matched by the key they are stored with (their primary `ElementId`, with a `#2` suffix... if it is shared).
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EntryDiff {
+    /// True if there was no entry before, in which case everything is "added"
+    #[serde(default)]
+    pub is_initial: bool,
+    pub added: Vec<MapEntry>,
+    pub removed: Vec<MapEntry>,
+    pub changed: Vec<EntryChange>,
+}
+
+fn changed_fields(old: &MapEntry, new: &MapEntry) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut check = |name: &str, is_same: bool| {
+        if !is_same {
+            fields.push(name.to_string());
+        }
+    };
+    check("pos", old.pos == new.pos);
+    check("name", old.name == new.name);
+    check("location_name", old.location_name == new.location_name);
+    check("image", old.image == new.image);
+    check("source_url", old.source_url == new.source_url);
+    check("source_text", old.source_text == new.source_text);
+    check("is_in_exhibit", old.is_in_exhibit == new.is_in_exhibit);
+    check("nature", old.nature == new.nature);
+    check("element_ids", old.element_ids == new.element_ids);
+    fields
+}
+
+/// The entries that have an id, by the key they are stored with (so that several entries sharing a primary id are still compared one by one)
+fn by_storage_key(entries: &BTreeSet<MapEntry>) -> BTreeMap<String, &MapEntry> {
+    keyed_entries(entries)
+        .into_iter()
+        .filter(|(_, entry)| entry.primary_id().is_some())
+        .collect()
+}
+
+fn describe(entry: &MapEntry) -> String {
+    let id = entry
+        .primary_id()
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| "<no id>".to_string());
+    match &entry.name {
+        Some(name) => format!("{id} ({name})"),
+        None => id,
+    }
+}
+
+impl EntryDiff {
+    pub fn compute(old: &BTreeSet<MapEntry>, new: &BTreeSet<MapEntry>) -> Self {
+        let old_by_key = by_storage_key(old);
+        let new_by_key = by_storage_key(new);
+
+        let mut result = Self {
+            is_initial: old.is_empty(),
+            ..Default::default()
+        };
+        for (key, new_entry) in &new_by_key {
+            match old_by_key.get(key) {
+                None => result.added.push((*new_entry).clone()),
+                Some(old_entry) => {
+                    if old_entry != new_entry
+                        && let Some(id) = new_entry.primary_id()
+                    {
+                        result.changed.push(EntryChange {
+                            id: id.clone(),
+                            fields: changed_fields(old_entry, new_entry),
+                            old: (*old_entry).clone(),
+                            new: (*new_entry).clone(),
+                        });
+                    }
+                }
+            }
+        }
+        for (key, old_entry) in &old_by_key {
+            if !new_by_key.contains_key(key) {
+                result.removed.push((*old_entry).clone());
+            }
+        }
+
+        // Entries without id can only be compared as a whole
+        for entry in new.difference(old) {
+            if entry.primary_id().is_none() {
+                result.added.push(entry.clone());
+            }
+        }
+        for entry in old.difference(new) {
+            if entry.primary_id().is_none() {
+                result.removed.push(entry.clone());
+            }
+        }
+
+        result
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// A one line summary, like "2 added, 0 removed, 1 changed"
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )
+    }
+
+    /// The summary followed by one line per modified entry, to be used as the body of a commit message
+    pub fn commit_message_body(&self) -> String {
+        let mut lines = Vec::new();
+        for entry in &self.added {
+            lines.push(format!("+ {}", describe(entry)));
+        }
+        for entry in &self.removed {
+            lines.push(format!("- {}", describe(entry)));
+        }
+        for change in &self.changed {
+            lines.push(format!(
+                "~ {}: {}",
+                describe(&change.new),
+                change.fields.join(", ")
+            ));
+        }
+
+        let mut body = self.summary();
+        body.push('\n');
+        let total = lines.len();
+        for line in lines.into_iter().take(MAX_COMMIT_MESSAGE_LINES) {
+            body.push('\n');
+            body.push_str(&line);
+        }
+        if total > MAX_COMMIT_MESSAGE_LINES {
+            body.push_str(&format!(
+                "\n… and {} more",
+                total - MAX_COMMIT_MESSAGE_LINES
+            ));
+        }
+        body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: Option<u64>, name: &str) -> MapEntry {
+        MapEntry {
+            pos: None,
+            name: Some(name.to_string()),
+            location_name: None,
+            image: None,
+            source_url: None,
+            source_text: "test".to_string(),
+            is_in_exhibit: false,
+            nature: None,
+            element_ids: id.map(ElementId::Osm).into_iter().collect(),
+        }
+    }
+
+    fn names(entries: &[MapEntry]) -> Vec<&str> {
+        entries
+            .iter()
+            .filter_map(|entry| entry.name.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn detects_added_removed_and_changed() {
+        let old = BTreeSet::from([entry(Some(1), "a"), entry(Some(2), "b")]);
+        let new = BTreeSet::from([entry(Some(1), "a2"), entry(Some(3), "c")]);
+        let diff = EntryDiff::compute(&old, &new);
+        assert!(!diff.is_initial);
+        assert_eq!(names(&diff.added), ["c"]);
+        assert_eq!(names(&diff.removed), ["b"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].id, ElementId::Osm(1));
+        assert_eq!(diff.changed[0].fields, ["name"]);
+        assert_eq!(diff.summary(), "1 added, 1 removed, 1 changed");
+    }
+
+    #[test]
+    fn same_entries_make_an_empty_diff() {
+        let entries = BTreeSet::from([entry(Some(1), "a"), entry(None, "no id")]);
+        let diff = EntryDiff::compute(&entries, &entries);
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn shared_primary_ids_are_compared_one_by_one() {
+        let old = BTreeSet::from([entry(Some(1), "a")]);
+        let new = BTreeSet::from([entry(Some(1), "a"), entry(Some(1), "b")]);
+        let diff = EntryDiff::compute(&old, &new);
+        assert_eq!(names(&diff.added), ["b"]);
+        assert!(diff.removed.is_empty());
+        assert!(diff.changed.is_empty());
+
+        let diff = EntryDiff::compute(&new, &old);
+        assert_eq!(names(&diff.removed), ["b"]);
+        assert!(diff.added.is_empty());
+    }
+
+    #[test]
+    fn entries_without_id_are_compared_as_a_whole() {
+        let old = BTreeSet::from([entry(None, "a"), entry(None, "b")]);
+        let new = BTreeSet::from([entry(None, "b"), entry(None, "c")]);
+        let diff = EntryDiff::compute(&old, &new);
+        assert_eq!(names(&diff.added), ["c"]);
+        assert_eq!(names(&diff.removed), ["a"]);
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn everything_is_added_initially() {
+        let new = BTreeSet::from([entry(Some(1), "a"), entry(None, "b")]);
+        let diff = EntryDiff::compute(&BTreeSet::new(), &new);
+        assert!(diff.is_initial);
+        assert_eq!(diff.added.len(), 2);
+    }
+}
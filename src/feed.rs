@@ -0,0 +1,313 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, rename},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{DepictionCategory, MapEntry, time_source::format_rfc3339};
+
+/// Maximum number of items kept in a feed
+const MAX_FEED_ITEMS: usize = 200;
+
+fn escape_xml(unescaped: &str) -> String {
+    unescaped
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#039;")
+}
+
+/// Make the urls of the files served by this program (like `/images/dragon.jpg`) absolute, as the feed readers do not all resolve them, and not against the site
+fn absolute_url(url: &str, public_url: Option<&Url>) -> String {
+    match (url.strip_prefix('/'), public_url) {
+        (Some(path), Some(public_url)) if !path.starts_with('/') => public_url
+            .join(path)
+            .map(|url| url.to_string())
+            .unwrap_or_else(|_| url.to_string()),
+        _ => url.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FeedItemKind {
+    Added,
+    /// The entry is the new version
+    Changed,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedItem {
+    pub kind: FeedItemKind,
+    pub entry: MapEntry,
+    /// When the change was noticed, as an UTC Unix timestamp
+    pub time: i64,
+    pub source_title: String,
+}
+
+impl FeedItem {
+    fn to_atom_entry(&self, category: &DepictionCategory, public_url: Option<&Url>) -> String {
+        let name = self.entry.name.as_deref().unwrap_or("no/unknown name");
+        let (verb, kind_id) = match self.kind {
+            FeedItemKind::Added => ("New", "added"),
+            FeedItemKind::Changed => ("Changed", "changed"),
+            FeedItemKind::Removed => ("Removed", "removed"),
+        };
+        let element_id = self
+            .entry
+            .primary_id()
+            .map(|id| id.to_string())
+            .unwrap_or_default();
+
+        let mut content = String::new();
+        if let Some(nature) = &self.entry.nature {
+            content.push_str(&format!("<p><i>{}</i></p>", escape_xml(nature)));
+        }
+        if let Some(location_name) = &self.entry.location_name {
+            content.push_str(&format!("<p>{}</p>", escape_xml(location_name)));
+        }
+        if let Some((lat, lon)) = &self.entry.pos {
+            content.push_str(&format!("<p>Coordinates: {lat}, {lon}</p>"));
+        }
+        if let Some(image) = &self.entry.image {
+            content.push_str(&format!(
+                "<img src=\"{}\"/>",
+                escape_xml(&absolute_url(&image.url, public_url))
+            ));
+        }
+        content.push_str(&format!("<p>{}</p>", escape_xml(&self.source_title)));
+
+        let mut result = String::new();
+        result.push_str("<entry>");
+        result.push_str(&format!(
+            "<id>urn:depiction-map:{}:{}:{}:{}</id>",
+            escape_xml(&category.0),
+            kind_id,
+            self.time,
+            escape_xml(&element_id)
+        ));
+        result.push_str(&format!("<title>{}: {}</title>", verb, escape_xml(name)));
+        result.push_str(&format!("<updated>{}</updated>", format_rfc3339(self.time)));
+        if let Some(source_url) = &self.entry.source_url {
+            result.push_str(&format!(
+                "<link href=\"{}\"/>",
+                escape_xml(&absolute_url(source_url, public_url))
+            ));
+        }
+        if let Some((lat, lon)) = &self.entry.pos {
+            result.push_str(&format!("<georss:point>{lat} {lon}</georss:point>"));
+        }
+        result.push_str(&format!(
+            "<content type=\"html\">{}</content>",
+            escape_xml(&content)
+        ));
+        result.push_str("</entry>\n");
+        result
+    }
+}
+
+/**
+ * The latest additions, changes and removals of entries of a depiction category, newest first.
+ */
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Feed {
+    pub items: VecDeque<FeedItem>,
+}
+
+impl Feed {
+    /// Path where the feed of the given category is stored
+    pub fn get_path(save_storage_dir: &Path, category: &DepictionCategory) -> PathBuf {
+        save_storage_dir.join(format!("{}.feed.private", category.0))
+    }
+
+    /// Load the feed at `path`, or return an empty feed if it does not exist
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let f = File::open(path).with_context(|| format!("Opening feed at {path:?}"))?;
+        serde_json::from_reader(f).with_context(|| format!("Reading feed at {path:?}"))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut temp_path = path.to_path_buf();
+        temp_path.set_extension("tmp");
+        {
+            let mut f_out = File::create(&temp_path)
+                .with_context(|| format!("Could not create/truncate file at {temp_path:?}"))?;
+            serde_json::ser::to_writer(&mut f_out, self)
+                .with_context(|| format!("Could not write feed to {temp_path:?}"))?;
+        }
+        rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    pub fn push(&mut self, item: FeedItem) {
+        self.items.push_front(item);
+        self.items.truncate(MAX_FEED_ITEMS);
+    }
+
+    /// Render the feed. `public_url` is where the map is served, to make the links to its files absolute.
+    pub fn to_atom(&self, category: &DepictionCategory, public_url: Option<&Url>) -> String {
+        let updated = self.items.front().map(|item| item.time).unwrap_or(0);
+
+        let mut result = String::new();
+        result.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        result.push_str(
+            "<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:georss=\"http://www.georss.org/georss\">\n",
+        );
+        result.push_str(&format!(
+            "<id>urn:depiction-map:feed:{}</id>\n",
+            escape_xml(&category.0)
+        ));
+        result.push_str(&format!(
+            "<title>New, changed and removed {} depictions</title>\n",
+            escape_xml(&category.0)
+        ));
+        result.push_str(&format!("<updated>{}</updated>\n", format_rfc3339(updated)));
+        result.push_str("<author><name>Depiction Map</name></author>\n");
+        for item in &self.items {
+            result.push_str(&item.to_atom_entry(category, public_url));
+        }
+        result.push_str("</feed>\n");
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn entry(id: u64, name: &str, image: Option<&str>) -> MapEntry {
+        serde_json::from_value(json!({
+            "pos": [48.5, -1.25],
+            "name": name,
+            "location_name": null,
+            "image": image.map(|url| json!({ "url": url, "credit_url": null, "credit_text": null })),
+            "source_url": format!("https://www.openstreetmap.org/node/{id}"),
+            "source_text": "OpenStreetMap",
+            "is_in_exhibit": false,
+            "nature": "statue",
+            "element_ids": [{ "Osm": id }],
+        }))
+        .unwrap()
+    }
+
+    fn item(kind: FeedItemKind, entry: MapEntry, time: i64) -> FeedItem {
+        FeedItem {
+            kind,
+            entry,
+            time,
+            source_title: "dragons from OSM".into(),
+        }
+    }
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(
+            escape_xml(r#"Tom & "Jerry" <'s>"#),
+            "Tom &amp; &quot;Jerry&quot; &lt;&#039;s&gt;"
+        );
+        assert_eq!(escape_xml("&amp;"), "&amp;amp;");
+    }
+
+    #[test]
+    fn keeps_the_newest_items() {
+        let mut feed = Feed::default();
+        for time in 0..MAX_FEED_ITEMS as i64 + 10 {
+            feed.push(item(FeedItemKind::Added, entry(1, "dragon", None), time));
+        }
+        assert_eq!(feed.items.len(), MAX_FEED_ITEMS);
+        assert_eq!(feed.items.front().unwrap().time, MAX_FEED_ITEMS as i64 + 9);
+        assert_eq!(feed.items.back().unwrap().time, 10);
+    }
+
+    #[test]
+    fn renders_atom() {
+        let mut feed = Feed::default();
+        feed.push(item(
+            FeedItemKind::Removed,
+            entry(3, "Old dragon", None),
+            1714521600,
+        ));
+        feed.push(item(
+            FeedItemKind::Changed,
+            entry(
+                2,
+                "Green dragon",
+                Some("https://upload.wikimedia.org/green.jpg"),
+            ),
+            1714521660,
+        ));
+        feed.push(item(
+            FeedItemKind::Added,
+            entry(1, r#"Dragon & <"friends">"#, Some("/images/dragon.jpg")),
+            1714521720,
+        ));
+        let public_url = Url::parse("https://dragons.example.org/map/").unwrap();
+        let expected = concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+            "<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:georss=\"http://www.georss.org/georss\">\n",
+            "<id>urn:depiction-map:feed:dragon</id>\n",
+            "<title>New, changed and removed dragon depictions</title>\n",
+            "<updated>2024-05-01T00:02:00Z</updated>\n",
+            "<author><name>Depiction Map</name></author>\n",
+            "<entry><id>urn:depiction-map:dragon:added:1714521720:osm:1</id>",
+            "<title>New: Dragon &amp; &lt;&quot;friends&quot;&gt;</title>",
+            "<updated>2024-05-01T00:02:00Z</updated>",
+            "<link href=\"https://www.openstreetmap.org/node/1\"/>",
+            "<georss:point>48.5 -1.25</georss:point>",
+            "<content type=\"html\">&lt;p&gt;&lt;i&gt;statue&lt;/i&gt;&lt;/p&gt;&lt;p&gt;Coordinates: 48.5, -1.25&lt;/p&gt;",
+            "&lt;img src=&quot;https://dragons.example.org/map/images/dragon.jpg&quot;/&gt;",
+            "&lt;p&gt;dragons from OSM&lt;/p&gt;</content></entry>\n",
+            "<entry><id>urn:depiction-map:dragon:changed:1714521660:osm:2</id>",
+            "<title>Changed: Green dragon</title>",
+            "<updated>2024-05-01T00:01:00Z</updated>",
+            "<link href=\"https://www.openstreetmap.org/node/2\"/>",
+            "<georss:point>48.5 -1.25</georss:point>",
+            "<content type=\"html\">&lt;p&gt;&lt;i&gt;statue&lt;/i&gt;&lt;/p&gt;&lt;p&gt;Coordinates: 48.5, -1.25&lt;/p&gt;",
+            "&lt;img src=&quot;https://upload.wikimedia.org/green.jpg&quot;/&gt;",
+            "&lt;p&gt;dragons from OSM&lt;/p&gt;</content></entry>\n",
+            "<entry><id>urn:depiction-map:dragon:removed:1714521600:osm:3</id>",
+            "<title>Removed: Old dragon</title>",
+            "<updated>2024-05-01T00:00:00Z</updated>",
+            "<link href=\"https://www.openstreetmap.org/node/3\"/>",
+            "<georss:point>48.5 -1.25</georss:point>",
+            "<content type=\"html\">&lt;p&gt;&lt;i&gt;statue&lt;/i&gt;&lt;/p&gt;&lt;p&gt;Coordinates: 48.5, -1.25&lt;/p&gt;",
+            "&lt;p&gt;dragons from OSM&lt;/p&gt;</content></entry>\n",
+            "</feed>\n",
+        );
+        assert_eq!(
+            feed.to_atom(&DepictionCategory::dragon(), Some(&public_url)),
+            expected
+        );
+    }
+
+    #[test]
+    fn makes_local_urls_absolute() {
+        let public_url = Url::parse("https://dragons.example.org/").unwrap();
+        assert_eq!(
+            absolute_url("/images/dragon.jpg", Some(&public_url)),
+            "https://dragons.example.org/images/dragon.jpg"
+        );
+        assert_eq!(
+            absolute_url("//cdn.example.org/dragon.jpg", Some(&public_url)),
+            "//cdn.example.org/dragon.jpg"
+        );
+        assert_eq!(
+            absolute_url("https://example.org/dragon.jpg", Some(&public_url)),
+            "https://example.org/dragon.jpg"
+        );
+        assert_eq!(
+            absolute_url("/images/dragon.jpg", None),
+            "/images/dragon.jpg"
+        );
+    }
+}
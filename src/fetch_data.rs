@@ -1,6 +1,8 @@
-use std::{collections::BTreeSet, time::Duration};
+use std::{collections::BTreeSet, sync::Mutex, time::SystemTime};
 
-use crate::MapEntry;
+use tai_time::TaiTime;
+
+use crate::{MapEntry, RefreshSchedule, time_source::tai_from_system_time};
 
 /**
  * A trait that describe how to fetch information about some depiction from a source
@@ -10,5 +12,65 @@ pub trait FetchData {
 
     fn title(&self) -> String;
 
-    fn retry_every(&self) -> Duration;
+    fn schedule(&self) -> &RefreshSchedule;
+
+    /// Whether the source is known to have changed since the last fetch, in which case it is fetched again without waiting for the schedule. `last_updated` is the time of the last update of the stored data, possibly before the program started.
+    fn source_changed(&self, _last_updated: Option<TaiTime<0>>) -> bool {
+        false
+    }
+}
+
+/// Whether a file changed since it was last read, `read_modified` being its modification time then. If it was not read since the program started, its modification time is compared with the last update instead, so the changes made while the program was not running are not missed.
+pub(crate) fn file_changed(
+    read_modified: &Mutex<Option<SystemTime>>,
+    modified: Option<SystemTime>,
+    last_updated: Option<TaiTime<0>>,
+) -> bool {
+    let Ok(read_modified) = read_modified.lock() else {
+        return false;
+    };
+    match (*read_modified, modified) {
+        (Some(read_modified), modified) => Some(read_modified) != modified,
+        (None, Some(modified)) => {
+            last_updated.is_some_and(|last_updated| tai_from_system_time(modified) >= last_updated)
+        }
+        (None, None) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn file_changed_since_read() {
+        let read = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let later = read + Duration::from_secs(60);
+        let read_modified = Mutex::new(Some(read));
+        assert!(!file_changed(&read_modified, Some(read), None));
+        assert!(file_changed(&read_modified, Some(later), None));
+        assert!(file_changed(&read_modified, None, None));
+    }
+
+    #[test]
+    fn file_changed_before_start() {
+        let updated = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let last_updated = Some(tai_from_system_time(updated));
+        let not_read = Mutex::new(None);
+        assert!(file_changed(
+            &not_read,
+            Some(updated + Duration::from_secs(60)),
+            last_updated
+        ));
+        assert!(!file_changed(
+            &not_read,
+            Some(updated - Duration::from_secs(60)),
+            last_updated
+        ));
+        // Never updated, the schedule already makes it due
+        assert!(!file_changed(&not_read, Some(updated), None));
+        assert!(!file_changed(&not_read, None, last_updated));
+    }
 }
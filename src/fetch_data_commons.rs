@@ -0,0 +1,559 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::info;
+use ordered_float::OrderedFloat;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    COMMONS_API_URL, ElementId, FetchData, MapEntry, MapEntryImageSource, RefreshSchedule,
+    USER_AGENT,
+    commons_metadata::{extmetadata_value, get_json},
+};
+
+/// Maximum number of pages or entities the API accepts in one query
+const IDS_PER_REQUEST: usize = 50;
+
+const CATEGORY_NAMESPACE: u64 = 14;
+const FILE_NAMESPACE: u64 = 6;
+
+/// What is known of a file, merged from the (possibly continued) answers
+#[derive(Default)]
+struct CommonsFile {
+    title: String,
+    /// Latitude, longitude, and whether it is the primary coordinates (the camera location)
+    coordinates: Vec<(f64, f64, bool)>,
+    image_url: Option<String>,
+    description_url: Option<String>,
+    object_name: Option<String>,
+}
+
+impl CommonsFile {
+    /// The location of the depicted object if set, otherwise the one of the camera
+    fn position(&self) -> Option<(f64, f64)> {
+        self.coordinates
+            .iter()
+            .find(|(_, _, primary)| !primary)
+            .or_else(|| self.coordinates.first())
+            .map(|(lat, lon, _)| (*lat, *lon))
+    }
+
+    /// The file name, without the `File:` prefix
+    fn file_name(&self) -> &str {
+        self.title.strip_prefix("File:").unwrap_or(&self.title)
+    }
+
+    /// The name of the depicted object if set, otherwise the file name without its extension
+    fn name(&self) -> String {
+        match &self.object_name {
+            Some(object_name) => object_name.clone(),
+            None => match self.file_name().rsplit_once('.') {
+                Some((name, _)) => name.to_string(),
+                None => self.file_name().to_string(),
+            },
+        }
+    }
+
+    fn to_map_entry(&self) -> Option<MapEntry> {
+        let (lat, lon) = self.position()?;
+        Some(MapEntry {
+            pos: Some((OrderedFloat(lat), OrderedFloat(lon))),
+            name: Some(self.name()),
+            location_name: None,
+            image: self
+                .image_url
+                .as_ref()
+                .map(|image_url| MapEntryImageSource {
+                    url: image_url.clone(),
+                    credit_url: self.description_url.clone(),
+                    credit_text: Some("Image from Wikimedia Commons".into()),
+                    license_url: None,
+                    thumbnail_url: None,
+                }),
+            source_url: self.description_url.clone(),
+            source_text: "From Wikimedia Commons".into(),
+            is_in_exhibit: false,
+            nature: None,
+            element_ids: vec![ElementId::Commons(self.file_name().to_string())],
+        })
+    }
+}
+
+fn default_commons_category() -> String {
+    "Dragons in art".to_string()
+}
+
+fn default_max_depth() -> usize {
+    3
+}
+
+/**
+ * Settings of the source of geotagged Wikimedia Commons files (see [`FetchDataCommonsCategory`]).
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommonsCategoryConfig {
+    /// Without the `Category:` prefix
+    #[serde(default = "default_commons_category")]
+    pub category: String,
+    /// How many levels of subcategories are followed. Each category visited is one more request.
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+    /// Wikidata items (like `Q7559`). If set, only the files whose structured data depicts one of them are kept.
+    #[serde(default)]
+    pub depicts: Vec<String>,
+}
+
+impl CommonsCategoryConfig {
+    /// Return the settings that can be parsed but would not work as intended
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.category.is_empty() || self.category.starts_with("Category:") {
+            problems.push(format!(
+                "The Commons category {:?} should be given without the Category: prefix",
+                self.category
+            ));
+        }
+        for item in &self.depicts {
+            let is_item = item
+                .strip_prefix('Q')
+                .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()));
+            if !is_item {
+                problems.push(format!("{item:?} is not a Wikidata item, like Q7559"));
+            }
+        }
+        problems
+    }
+}
+
+/**
+ * The geotagged files of a Wikimedia Commons category and of its subcategories. Files without coordinates are ignored. Their author and license are added like for the other Commons images.
+ */
+pub struct FetchDataCommonsCategory {
+    api_url: String,
+    category: String,
+    max_depth: usize,
+    depicts: Vec<String>,
+    title: String,
+    schedule: RefreshSchedule,
+}
+
+impl FetchDataCommonsCategory {
+    /// `category` is without the `Category:` prefix. Subcategories are followed up to `max_depth` levels.
+    pub fn new(
+        category: String,
+        max_depth: usize,
+        title: String,
+        schedule: RefreshSchedule,
+    ) -> Self {
+        Self {
+            api_url: COMMONS_API_URL.to_string(),
+            category,
+            max_depth,
+            depicts: Vec::new(),
+            title,
+            schedule,
+        }
+    }
+
+    /// Only keep the files whose structured data depicts (P180) one of those Wikidata items
+    pub fn with_depicts(mut self, depicts: Vec<String>) -> Self {
+        self.depicts = depicts;
+        self
+    }
+
+    /// Query another MediaWiki API than the one of Wikimedia Commons
+    pub fn with_api_url(mut self, api_url: String) -> Self {
+        self.api_url = api_url;
+        self
+    }
+
+    fn api_url(&self, params: &[(&str, &str)]) -> anyhow::Result<Url> {
+        let mut url = Url::parse(&self.api_url)?;
+        {
+            let mut query_pairs = url.query_pairs_mut();
+            query_pairs
+                .append_pair("format", "json")
+                .append_pair("formatversion", "2");
+            for (key, value) in params {
+                query_pairs.append_pair(key, value);
+            }
+        }
+        Ok(url)
+    }
+
+    /// Perform the query, following the continuations, and return the `query` part of every answer
+    fn query_all(&self, client: &Client, params: &[(&str, &str)]) -> anyhow::Result<Vec<Value>> {
+        let mut result = Vec::new();
+        let mut continuation: Vec<(String, String)> = Vec::new();
+        loop {
+            let mut all_params = vec![("action", "query")];
+            all_params.extend_from_slice(params);
+            all_params.extend(
+                continuation
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            );
+            let answer = get_json(client, &self.api_url(&all_params)?)?;
+            if let Some(query) = answer.get("query") {
+                result.push(query.clone());
+            }
+            match answer.get("continue").and_then(|c| c.as_object()) {
+                Some(next) => {
+                    continuation = next
+                        .iter()
+                        .map(|(key, value)| match value.as_str() {
+                            Some(text) => (key.clone(), text.to_string()),
+                            None => (key.clone(), value.to_string()),
+                        })
+                        .collect()
+                }
+                None => return Ok(result),
+            }
+        }
+    }
+
+    /// Walk the category tree, returning the title of every file by page id
+    fn list_files(&self, client: &Client) -> anyhow::Result<BTreeMap<u64, String>> {
+        let mut files = BTreeMap::new();
+        let root = format!("Category:{}", self.category);
+        let mut visited = HashSet::from([root.clone()]);
+        let mut queue = VecDeque::from([(root, 0)]);
+        while let Some((category, depth)) = queue.pop_front() {
+            let answers = self
+                .query_all(
+                    client,
+                    &[
+                        ("list", "categorymembers"),
+                        ("cmtitle", &category),
+                        ("cmtype", "subcat|file"),
+                        ("cmlimit", "max"),
+                    ],
+                )
+                .with_context(|| format!("Listing the members of {category:?}"))?;
+            for member in answers
+                .iter()
+                .filter_map(|query| query.get("categorymembers")?.as_array())
+                .flatten()
+            {
+                let (Some(namespace), Some(page_id), Some(title)) = (
+                    member.get("ns").and_then(|ns| ns.as_u64()),
+                    member.get("pageid").and_then(|id| id.as_u64()),
+                    member.get("title").and_then(|title| title.as_str()),
+                ) else {
+                    continue;
+                };
+                match namespace {
+                    // Category cycles are common on Commons
+                    CATEGORY_NAMESPACE
+                        if depth < self.max_depth && visited.insert(title.into()) =>
+                    {
+                        queue.push_back((title.to_string(), depth + 1))
+                    }
+                    FILE_NAMESPACE => {
+                        files.insert(page_id, title.to_string());
+                    }
+                    _ => (),
+                }
+            }
+        }
+        info!(
+            "Found {} files in {} categories under {:?}",
+            files.len(),
+            visited.len(),
+            self.category
+        );
+        Ok(files)
+    }
+
+    /// Get the coordinates, url and object name of the files
+    fn get_files_info(
+        &self,
+        client: &Client,
+        page_ids: &[u64],
+    ) -> anyhow::Result<BTreeMap<u64, CommonsFile>> {
+        let mut files: BTreeMap<u64, CommonsFile> = BTreeMap::new();
+        for chunk in page_ids.chunks(IDS_PER_REQUEST) {
+            let page_ids = chunk
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join("|");
+            let answers = self.query_all(
+                client,
+                &[
+                    ("prop", "coordinates|imageinfo"),
+                    ("pageids", &page_ids),
+                    ("coprimary", "all"),
+                    ("colimit", "max"),
+                    ("iiprop", "url|extmetadata"),
+                    ("iiextmetadatafilter", "ObjectName"),
+                ],
+            )?;
+            for page in answers
+                .iter()
+                .filter_map(|query| query.get("pages")?.as_array())
+                .flatten()
+            {
+                let Some(page_id) = page.get("pageid").and_then(|id| id.as_u64()) else {
+                    continue;
+                };
+                let file = files.entry(page_id).or_default();
+                if let Some(title) = page.get("title").and_then(|title| title.as_str()) {
+                    file.title = title.to_string();
+                }
+                for coordinates in page
+                    .get("coordinates")
+                    .and_then(|c| c.as_array())
+                    .into_iter()
+                    .flatten()
+                {
+                    let globe = coordinates.get("globe").and_then(|globe| globe.as_str());
+                    if globe.is_some_and(|globe| globe != "earth") {
+                        continue;
+                    }
+                    if let (Some(lat), Some(lon)) = (
+                        coordinates.get("lat").and_then(|lat| lat.as_f64()),
+                        coordinates.get("lon").and_then(|lon| lon.as_f64()),
+                    ) {
+                        let primary = coordinates
+                            .get("primary")
+                            .and_then(Value::as_bool)
+                            .unwrap_or(false);
+                        file.coordinates.push((lat, lon, primary));
+                    }
+                }
+                if let Some(imageinfo) = page.get("imageinfo").and_then(|i| i.get(0)) {
+                    let get = |field: &str| imageinfo.get(field)?.as_str().map(str::to_string);
+                    file.image_url = get("url");
+                    file.description_url = get("descriptionurl");
+                    file.object_name = imageinfo
+                        .get("extmetadata")
+                        .and_then(|extmetadata| extmetadata_value(extmetadata, "ObjectName"));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Return the page ids of the files whose structured data depicts one of `self.depicts`
+    fn filter_depicts(&self, client: &Client, page_ids: &[u64]) -> anyhow::Result<HashSet<u64>> {
+        let mut result = HashSet::new();
+        for chunk in page_ids.chunks(IDS_PER_REQUEST) {
+            let ids = chunk
+                .iter()
+                .map(|id| format!("M{id}"))
+                .collect::<Vec<_>>()
+                .join("|");
+            let url = self.api_url(&[
+                ("action", "wbgetentities"),
+                ("ids", &ids),
+                ("props", "claims"),
+            ])?;
+            let answer = get_json(client, &url)?;
+            let Some(entities) = answer.get("entities").and_then(|e| e.as_object()) else {
+                continue;
+            };
+            for (id, entity) in entities {
+                let mut depicted = entity
+                    .get("statements")
+                    .and_then(|statements| statements.get("P180"))
+                    .and_then(|p180| p180.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|statement| {
+                        statement
+                            .get("mainsnak")?
+                            .get("datavalue")?
+                            .get("value")?
+                            .get("id")?
+                            .as_str()
+                    });
+                let is_wanted = depicted.any(|qid| self.depicts.iter().any(|wanted| wanted == qid));
+                if is_wanted
+                    && let Some(page_id) = id.strip_prefix('M').and_then(|id| id.parse().ok())
+                {
+                    result.insert(page_id);
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl FetchData for FetchDataCommonsCategory {
+    fn fetch_data(&self) -> anyhow::Result<BTreeSet<MapEntry>> {
+        let client = Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(Duration::from_secs(60))
+            .build()?;
+
+        let mut page_ids: Vec<u64> = self.list_files(&client)?.into_keys().collect();
+        if !self.depicts.is_empty() {
+            let wanted = self
+                .filter_depicts(&client, &page_ids)
+                .context("Reading the structured data of the files")?;
+            page_ids.retain(|page_id| wanted.contains(page_id));
+        }
+
+        let files = self
+            .get_files_info(&client, &page_ids)
+            .context("Reading the information of the files")?;
+        Ok(files
+            .values()
+            .filter_map(|file| file.to_map_entry())
+            .collect())
+    }
+
+    fn schedule(&self) -> &RefreshSchedule {
+        &self.schedule
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Answer like the Commons API, for a small category tree
+    fn answer(request_line: &str) -> Value {
+        if request_line.contains("list=categorymembers") {
+            let members = if request_line.contains("Category%3ADragons") {
+                json!([
+                    { "ns": 14, "pageid": 10, "title": "Category:Sub" },
+                    { "ns": 6, "pageid": 1, "title": "File:A.jpg" },
+                    { "ns": 6, "pageid": 2, "title": "File:B.jpg" },
+                ])
+            } else if request_line.contains("Category%3ASub") {
+                json!([
+                    { "ns": 14, "pageid": 11, "title": "Category:Deep" },
+                    // A cycle
+                    { "ns": 14, "pageid": 12, "title": "Category:Dragons" },
+                    { "ns": 6, "pageid": 3, "title": "File:C.jpg" },
+                ])
+            } else {
+                json!([{ "ns": 6, "pageid": 4, "title": "File:D.jpg" }])
+            };
+            json!({ "query": { "categorymembers": members } })
+        } else if request_line.contains("action=wbgetentities") {
+            let depicts = |item: &str| {
+                json!({ "statements": { "P180": [
+                    { "mainsnak": { "datavalue": { "value": { "id": item } } } }
+                ] } })
+            };
+            json!({ "entities": {
+                "M1": depicts("Q1"),
+                "M2": depicts("Q2"),
+                "M3": depicts("Q1"),
+                "M4": depicts("Q1"),
+            } })
+        } else {
+            json!({ "query": { "pages": [
+                {
+                    "pageid": 1,
+                    "title": "File:A.jpg",
+                    "coordinates": [
+                        { "lat": 1.0, "lon": 2.0, "primary": true, "globe": "earth" },
+                        { "lat": 3.0, "lon": 4.0, "primary": false, "globe": "earth" },
+                    ],
+                    "imageinfo": [{
+                        "url": "https://upload.wikimedia.org/A.jpg",
+                        "descriptionurl": "https://commons.wikimedia.org/wiki/File:A.jpg",
+                        "extmetadata": { "ObjectName": { "value": "Dragon A" } }
+                    }]
+                },
+                {
+                    "pageid": 3,
+                    "title": "File:C.jpg",
+                    "coordinates": [
+                        { "lat": 5.0, "lon": 6.0, "primary": true, "globe": "earth" },
+                    ],
+                },
+            ] } })
+        }
+    }
+
+    #[test]
+    fn fetches_the_geotagged_files_of_the_category() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_url = format!("http://{}/w/api.php", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                let body = answer(&request_line).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let fetcher = FetchDataCommonsCategory::new(
+            "Dragons".into(),
+            1,
+            "test".into(),
+            RefreshSchedule::every(Duration::from_secs(3600)),
+        )
+        .with_depicts(vec!["Q1".into()])
+        .with_api_url(api_url);
+        let entries: Vec<_> = fetcher.fetch_data().unwrap().into_iter().collect();
+        let found: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.element_ids.clone(),
+                    entry.name.clone(),
+                    entry.pos.map(|(lat, lon)| (lat.0, lon.0)),
+                )
+            })
+            .collect();
+        // B does not depict Q1, D is too deep, and only A and C have coordinates: the object location of A is used rather than the camera one
+        assert_eq!(found.len(), 2);
+        assert!(found.contains(&(
+            vec![ElementId::Commons("A.jpg".into())],
+            Some("Dragon A".into()),
+            Some((3.0, 4.0))
+        )));
+        assert!(found.contains(&(
+            vec![ElementId::Commons("C.jpg".into())],
+            Some("C".into()),
+            Some((5.0, 6.0))
+        )));
+    }
+
+    #[test]
+    fn config_problems() {
+        let config: CommonsCategoryConfig =
+            serde_json::from_value(json!({ "depicts": ["Q7559", "dragon", "Q"] })).unwrap();
+        assert_eq!(config.category, "Dragons in art");
+        assert_eq!(config.max_depth, 3);
+        assert_eq!(config.problems().len(), 2);
+        let config: CommonsCategoryConfig =
+            serde_json::from_value(json!({ "category": "Category:Dragons" })).unwrap();
+        assert_eq!(config.problems().len(), 1);
+    }
+}
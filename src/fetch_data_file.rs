@@ -0,0 +1,519 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{metadata, read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, bail};
+use log::warn;
+use ordered_float::OrderedFloat;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tai_time::TaiTime;
+use url::Url;
+
+use crate::{
+    ElementId, FetchData, MapEntry, MapEntryImageSource, RefreshSchedule, USER_AGENT,
+    fetch_data::file_changed,
+};
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_latitude() -> String {
+    "lat".to_string()
+}
+
+fn default_longitude() -> String {
+    "lon".to_string()
+}
+
+/// The format of an imported file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum FileFormat {
+    /// A `FeatureCollection` (or a single `Feature`). The position is the one of the `Point` geometries, or the center of the bounding box of the others.
+    GeoJson,
+    /// With a header line naming the columns
+    Csv {
+        #[serde(default = "default_delimiter")]
+        delimiter: char,
+    },
+}
+
+/**
+ * Which column of a CSV file (or property of a GeoJSON feature, or path in an item of a JSON API) holds each field of the entries. Only the position is needed, and not for GeoJSON files.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldMapping {
+    /// The id of the entry in the dataset. Without it, the `id` of the GeoJSON features, or the position of the entry in other formats, is used, which changes when entries are inserted before.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// The latitude, in decimal degrees (with a dot or a comma)
+    #[serde(default = "default_latitude")]
+    pub latitude: String,
+    #[serde(default = "default_longitude")]
+    pub longitude: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub location_name: Option<String>,
+    /// The url of an image
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub image_credit: Option<String>,
+    #[serde(default)]
+    pub source_url: Option<String>,
+    #[serde(default)]
+    pub nature: Option<String>,
+}
+
+/// Keep only the http(s) urls, as the others (like `javascript:`) could run code when the link or image is shown in the popup
+fn web_url(url: String) -> Option<String> {
+    match Url::parse(&url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Some(url),
+        _ => None,
+    }
+}
+
+impl FieldMapping {
+    /// Make the entry with the given id and position, its other fields being read by `get` from where they are mapped
+    pub(crate) fn make_entry(
+        &self,
+        element_id: ElementId,
+        source_text: &str,
+        pos: (f64, f64),
+        get: impl Fn(&Option<String>) -> Option<String>,
+    ) -> MapEntry {
+        MapEntry {
+            pos: Some((OrderedFloat(pos.0), OrderedFloat(pos.1))),
+            name: get(&self.name),
+            location_name: get(&self.location_name),
+            image: get(&self.image)
+                .and_then(web_url)
+                .map(|url| MapEntryImageSource {
+                    url,
+                    credit_url: None,
+                    credit_text: get(&self.image_credit),
+                    license_url: None,
+                    thumbnail_url: None,
+                }),
+            source_url: get(&self.source_url).and_then(web_url),
+            source_text: source_text.to_string(),
+            is_in_exhibit: false,
+            nature: get(&self.nature),
+            element_ids: vec![element_id],
+        }
+    }
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        Self {
+            id: None,
+            latitude: default_latitude(),
+            longitude: default_longitude(),
+            name: None,
+            location_name: None,
+            image: None,
+            image_credit: None,
+            source_url: None,
+            nature: None,
+        }
+    }
+}
+
+/// Where an imported file is read from
+#[derive(Debug, Clone)]
+pub enum FileLocation {
+    Local(PathBuf),
+    Remote(String),
+}
+
+impl FileLocation {
+    /// Parse an http(s) url, or a path relative to `base`
+    pub fn parse(location: &str, base: &Path) -> Self {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            Self::Remote(location.to_string())
+        } else {
+            Self::Local(base.join(location))
+        }
+    }
+}
+
+/// Parse a coordinate, accepting the decimal comma of some spreadsheets
+pub(crate) fn parse_coordinate(text: &str) -> Option<f64> {
+    let value: f64 = text.trim().replace(',', ".").parse().ok()?;
+    if value.is_finite() { Some(value) } else { None }
+}
+
+/// The text of a JSON value, which may be a number
+pub(crate) fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(boolean) => Some(boolean.to_string()),
+        _ => None,
+    }
+}
+
+/// Extend `bounds` (min lat, min lon, max lat, max lon) with every position of GeoJSON `coordinates`, at any nesting
+fn extend_bounds(coordinates: &Value, bounds: &mut Option<(f64, f64, f64, f64)>) {
+    let Some(array) = coordinates.as_array() else {
+        return;
+    };
+    if let (Some(lon), Some(lat)) = (
+        array.first().and_then(|lon| lon.as_f64()),
+        array.get(1).and_then(|lat| lat.as_f64()),
+    ) {
+        *bounds = Some(match *bounds {
+            None => (lat, lon, lat, lon),
+            Some((min_lat, min_lon, max_lat, max_lon)) => (
+                min_lat.min(lat),
+                min_lon.min(lon),
+                max_lat.max(lat),
+                max_lon.max(lon),
+            ),
+        });
+        return;
+    }
+    for nested in array {
+        extend_bounds(nested, bounds);
+    }
+}
+
+/// Keep the first of the entries with the same id, as they would otherwise replace each other in the storage
+pub(crate) fn without_duplicates(entries: Vec<MapEntry>, title: &str) -> BTreeSet<MapEntry> {
+    let mut result = BTreeSet::new();
+    let mut ids = BTreeSet::new();
+    for entry in entries {
+        if let Some(id) = entry.primary_id()
+            && !ids.insert(id.clone())
+        {
+            warn!("Ignored a duplicate of {id} in {title:?}");
+            continue;
+        }
+        result.insert(entry);
+    }
+    result
+}
+
+fn geometry_position(geometry: &Value) -> Option<(f64, f64)> {
+    let mut bounds = None;
+    match geometry.get("type")?.as_str()? {
+        "GeometryCollection" => {
+            for geometry in geometry.get("geometries")?.as_array()? {
+                if let Some(coordinates) = geometry.get("coordinates") {
+                    extend_bounds(coordinates, &mut bounds);
+                }
+            }
+        }
+        _ => extend_bounds(geometry.get("coordinates")?, &mut bounds),
+    }
+    let (min_lat, min_lon, max_lat, max_lon) = bounds?;
+    Some(((min_lat + max_lat) / 2.0, (min_lon + max_lon) / 2.0))
+}
+
+/**
+ * Import the entries of a GeoJSON or CSV file, like those sent by partners, from a local path or an http(s) url. Their ids are in their own namespace, as `external:{namespace}:{id}`. Local files are read again as soon as they are modified.
+ */
+pub struct FetchDataFile {
+    namespace: String,
+    location: FileLocation,
+    format: FileFormat,
+    mapping: FieldMapping,
+    title: String,
+    source_text: String,
+    schedule: RefreshSchedule,
+    /// Modification time of the local file when it was last read
+    read_modified: Mutex<Option<SystemTime>>,
+}
+
+impl FetchDataFile {
+    /// The source text defaults to the title
+    pub fn new(
+        namespace: String,
+        location: FileLocation,
+        format: FileFormat,
+        title: String,
+        schedule: RefreshSchedule,
+    ) -> Self {
+        Self {
+            namespace,
+            location,
+            format,
+            mapping: FieldMapping::default(),
+            source_text: title.clone(),
+            title,
+            schedule,
+            read_modified: Mutex::new(None),
+        }
+    }
+
+    pub fn with_mapping(mut self, mapping: FieldMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    /// The text of the link to the source of the entries
+    pub fn with_source_text(mut self, source_text: String) -> Self {
+        self.source_text = source_text;
+        self
+    }
+
+    fn element_id(&self, id: String) -> ElementId {
+        ElementId::External {
+            namespace: self.namespace.clone(),
+            id,
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        match &self.location {
+            FileLocation::Local(path) => {
+                metadata(path).and_then(|metadata| metadata.modified()).ok()
+            }
+            FileLocation::Remote(_) => None,
+        }
+    }
+
+    fn read_content(&self) -> anyhow::Result<Vec<u8>> {
+        match &self.location {
+            FileLocation::Local(path) => {
+                read(path).with_context(|| format!("Reading the file to import at {path:?}"))
+            }
+            FileLocation::Remote(url) => {
+                let client = Client::builder()
+                    .user_agent(USER_AGENT)
+                    .timeout(Duration::from_secs(60))
+                    .build()?;
+                let response = client
+                    .get(url)
+                    .send()
+                    .and_then(|response| response.error_for_status())
+                    .with_context(|| format!("Downloading {url}"))?;
+                Ok(response
+                    .bytes()
+                    .with_context(|| format!("Downloading {url}"))?
+                    .to_vec())
+            }
+        }
+    }
+
+    fn parse_csv(&self, content: &[u8], delimiter: char) -> anyhow::Result<Vec<MapEntry>> {
+        if !delimiter.is_ascii() {
+            bail!("The delimiter {delimiter:?} is not an ASCII character");
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter as u8)
+            .flexible(true)
+            .from_reader(content);
+        let columns: HashMap<String, usize> = reader
+            .headers()
+            .context("Reading the header line")?
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.trim().to_string(), index))
+            .collect();
+        let mapping = &self.mapping;
+        for column in [Some(&mapping.latitude), Some(&mapping.longitude)]
+            .into_iter()
+            .chain([
+                mapping.id.as_ref(),
+                mapping.name.as_ref(),
+                mapping.location_name.as_ref(),
+                mapping.image.as_ref(),
+                mapping.image_credit.as_ref(),
+                mapping.source_url.as_ref(),
+                mapping.nature.as_ref(),
+            ])
+            .flatten()
+        {
+            if !columns.contains_key(column) {
+                bail!("There is no column {column:?}");
+            }
+        }
+
+        let mut entries = Vec::new();
+        let mut without_position = 0;
+        for record in reader.records() {
+            let record = record.context("Reading a line")?;
+            let get = |column: &Option<String>| {
+                let value = record.get(columns[column.as_ref()?])?.trim();
+                if value.is_empty() {
+                    None
+                } else {
+                    Some(value.to_string())
+                }
+            };
+            let (Some(lat), Some(lon)) = (
+                record
+                    .get(columns[&mapping.latitude])
+                    .and_then(parse_coordinate),
+                record
+                    .get(columns[&mapping.longitude])
+                    .and_then(parse_coordinate),
+            ) else {
+                without_position += 1;
+                continue;
+            };
+            let id = match &mapping.id {
+                Some(_) => get(&mapping.id),
+                None => record
+                    .position()
+                    .map(|position| position.line().to_string()),
+            };
+            let Some(id) = id else {
+                warn!("Ignored a line without id in {:?}", self.title);
+                continue;
+            };
+            entries.push(self.mapping.make_entry(
+                self.element_id(id),
+                &self.source_text,
+                (lat, lon),
+                get,
+            ));
+        }
+        if without_position > 0 {
+            warn!(
+                "Ignored {without_position} lines without a valid position in {:?}",
+                self.title
+            );
+        }
+        Ok(entries)
+    }
+
+    fn parse_geojson(&self, content: &[u8]) -> anyhow::Result<Vec<MapEntry>> {
+        let geojson: Value = serde_json::from_slice(content).context("Parsing the GeoJSON")?;
+        let features = match geojson.get("type").and_then(|t| t.as_str()) {
+            Some("FeatureCollection") => geojson
+                .get("features")
+                .and_then(|features| features.as_array())
+                .context("No \"features\" in the FeatureCollection")?
+                .iter()
+                .collect(),
+            Some("Feature") => vec![&geojson],
+            other => bail!("Expected a FeatureCollection or a Feature, not {other:?}"),
+        };
+
+        let mut entries = Vec::new();
+        let mut without_position = 0;
+        for feature in features {
+            let Some(pos) = feature.get("geometry").and_then(geometry_position) else {
+                without_position += 1;
+                continue;
+            };
+            let properties = feature.get("properties");
+            let get = |property: &Option<String>| {
+                value_to_string(properties?.get(property.as_ref()?)?)
+                    .filter(|value| !value.is_empty())
+            };
+            let id = match &self.mapping.id {
+                Some(_) => get(&self.mapping.id),
+                None => feature.get("id").and_then(value_to_string),
+            };
+            let Some(id) = id else {
+                warn!("Ignored a feature without id in {:?}", self.title);
+                continue;
+            };
+            entries.push(
+                self.mapping
+                    .make_entry(self.element_id(id), &self.source_text, pos, get),
+            );
+        }
+        if without_position > 0 {
+            warn!(
+                "Ignored {without_position} features without geometry in {:?}",
+                self.title
+            );
+        }
+        Ok(entries)
+    }
+}
+
+impl FetchData for FetchDataFile {
+    fn fetch_data(&self) -> anyhow::Result<BTreeSet<MapEntry>> {
+        // Set before reading, so a broken file is not read again until it is modified
+        if let Ok(mut read_modified) = self.read_modified.lock() {
+            *read_modified = self.modified();
+        }
+        let content = self.read_content()?;
+        let content = content.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&content);
+
+        let entries = match &self.format {
+            FileFormat::GeoJson => self.parse_geojson(content),
+            FileFormat::Csv { delimiter } => self.parse_csv(content, *delimiter),
+        }
+        .with_context(|| format!("Importing {:?}", self.title))?;
+
+        Ok(without_duplicates(entries, &self.title))
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn schedule(&self) -> &RefreshSchedule {
+        &self.schedule
+    }
+
+    fn source_changed(&self, last_updated: Option<TaiTime<0>>) -> bool {
+        file_changed(&self.read_modified, self.modified(), last_updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_only_web_urls() {
+        let mapping = FieldMapping {
+            image: Some("image".into()),
+            source_url: Some("link".into()),
+            ..FieldMapping::default()
+        };
+        let make = |image: &str, link: &str| {
+            let fields = HashMap::from([("image", image.to_string()), ("link", link.to_string())]);
+            mapping.make_entry(
+                ElementId::External {
+                    namespace: "test".into(),
+                    id: "1".into(),
+                },
+                "test",
+                (1.0, 2.0),
+                |field| fields.get(field.as_deref()?).cloned(),
+            )
+        };
+
+        let entry = make(
+            "https://example.org/dragon.jpg",
+            "http://example.org/dragon",
+        );
+        assert_eq!(
+            entry.image.map(|image| image.url).as_deref(),
+            Some("https://example.org/dragon.jpg")
+        );
+        assert_eq!(
+            entry.source_url.as_deref(),
+            Some("http://example.org/dragon")
+        );
+
+        let entry = make(
+            "javascript:alert(1)",
+            "data:text/html,<script>alert(1)</script>",
+        );
+        assert!(entry.image.is_none());
+        assert!(entry.source_url.is_none());
+        assert!(
+            make("dragon.jpg", " JAVASCRIPT:alert(1)")
+                .source_url
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,652 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use log::{info, warn};
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    ElementId, FetchData, FieldMapping, MapEntry, RefreshSchedule, USER_AGENT,
+    fetch_data_file::{parse_coordinate, value_to_string, without_duplicates},
+};
+
+/// Stop following the pages after this many, in case the API never returns an empty one
+const MAX_PAGES: usize = 1000;
+
+fn default_page_param() -> String {
+    "page".to_string()
+}
+
+fn default_first_page() -> u64 {
+    1
+}
+
+fn default_offset_param() -> String {
+    "offset".to_string()
+}
+
+/// How to get the next pages of the answer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Pagination {
+    /// Every item is in the first answer
+    #[default]
+    None,
+    /// The pages are numbered by the `param` query parameter, until one has no item (or is the same as the previous one)
+    Page {
+        #[serde(default = "default_page_param")]
+        param: String,
+        #[serde(default = "default_first_page")]
+        first: u64,
+    },
+    /// The `param` query parameter is the number of items to skip, until a page has no item (or is the same as the previous one)
+    Offset {
+        #[serde(default = "default_offset_param")]
+        param: String,
+    },
+    /// The url of the next page is in the answer, at `path` (possibly relative to the current page). It must be on the same site as `url`.
+    NextUrl { path: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+    /// Every element of an array, or value of an object
+    All,
+}
+
+/// Parse a path like `$.data.items[*]` or `images[0].url`. The leading `$` is optional.
+fn parse_json_path(path: &str) -> anyhow::Result<Vec<PathSegment>> {
+    let mut segments = Vec::new();
+    let rest = path.strip_prefix('$').unwrap_or(path);
+    for part in rest.split('.') {
+        let (key, mut brackets) = match part.find('[') {
+            Some(start) => part.split_at(start),
+            None => (part, ""),
+        };
+        if key.contains(']') {
+            bail!("Unbalanced brackets in the path {path:?}");
+        }
+        match key {
+            "" => (),
+            "*" => segments.push(PathSegment::All),
+            key => segments.push(PathSegment::Key(key.to_string())),
+        }
+        while !brackets.is_empty() {
+            let Some((inside, after)) = brackets
+                .strip_prefix('[')
+                .and_then(|brackets| brackets.split_once(']'))
+            else {
+                bail!("Unbalanced brackets in the path {path:?}");
+            };
+            segments.push(match inside {
+                "*" => PathSegment::All,
+                index => PathSegment::Index(
+                    index
+                        .parse()
+                        .with_context(|| format!("Invalid index {index:?} in the path {path:?}"))?,
+                ),
+            });
+            brackets = after;
+        }
+    }
+    Ok(segments)
+}
+
+/// Return every value matching the path, in order
+pub fn select_json_path<'a>(value: &'a Value, path: &str) -> anyhow::Result<Vec<&'a Value>> {
+    let mut selected = vec![value];
+    for segment in parse_json_path(path)? {
+        selected = selected
+            .into_iter()
+            .flat_map(|value| -> Vec<&Value> {
+                match (&segment, value) {
+                    (PathSegment::Key(key), Value::Object(object)) => {
+                        object.get(key).into_iter().collect()
+                    }
+                    (PathSegment::Index(index), Value::Array(array)) => {
+                        array.get(*index).into_iter().collect()
+                    }
+                    (PathSegment::All, Value::Array(array)) => array.iter().collect(),
+                    (PathSegment::All, Value::Object(object)) => object.values().collect(),
+                    _ => Vec::new(),
+                }
+            })
+            .collect();
+    }
+    Ok(selected)
+}
+
+/// Check that the path of the items and those of the mapping can be parsed
+pub(crate) fn check_json_paths(items: &str, mapping: &FieldMapping) -> anyhow::Result<()> {
+    for path in [items, &mapping.latitude, &mapping.longitude]
+        .into_iter()
+        .chain(
+            [
+                &mapping.id,
+                &mapping.name,
+                &mapping.location_name,
+                &mapping.image,
+                &mapping.image_credit,
+                &mapping.source_url,
+                &mapping.nature,
+            ]
+            .into_iter()
+            .flatten()
+            .map(|path| path.as_str()),
+        )
+    {
+        parse_json_path(path)?;
+    }
+    Ok(())
+}
+
+/**
+ * Fetch entries from any JSON API, like those of museum collections or open data portals. The items are selected in each answer with a JSONPath-like path, and their fields read following a [`FieldMapping`] of paths relative to the item. Their ids are in their own namespace, as `external:{namespace}:{id}`.
+ */
+pub struct FetchDataJsonApi {
+    namespace: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    pagination: Pagination,
+    items: String,
+    mapping: FieldMapping,
+    title: String,
+    source_text: String,
+    schedule: RefreshSchedule,
+}
+
+impl FetchDataJsonApi {
+    /// `items` is the path of the items in each answer. If it selects a single array, its elements are the items. The source text defaults to the title.
+    pub fn new(
+        namespace: String,
+        url: String,
+        items: String,
+        title: String,
+        schedule: RefreshSchedule,
+    ) -> Self {
+        Self {
+            namespace,
+            url,
+            headers: BTreeMap::new(),
+            pagination: Pagination::None,
+            items,
+            mapping: FieldMapping::default(),
+            source_text: title.clone(),
+            title,
+            schedule,
+        }
+    }
+
+    pub fn with_mapping(mut self, mapping: FieldMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    pub fn with_pagination(mut self, pagination: Pagination) -> Self {
+        self.pagination = pagination;
+        self
+    }
+
+    /// Headers to send with every request, like an API key
+    pub fn with_headers(mut self, headers: BTreeMap<String, String>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// The text of the link to the source of the entries
+    pub fn with_source_text(mut self, source_text: String) -> Self {
+        self.source_text = source_text;
+        self
+    }
+
+    fn get_page(&self, client: &Client, url: &Url) -> anyhow::Result<Value> {
+        let mut request = client.get(url.clone());
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request
+            .send()
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Querying {url}"))?;
+        let text = response
+            .text()
+            .with_context(|| format!("Could not decode encoding of {url}"))?;
+        serde_json::from_str(&text).with_context(|| format!("Could not parse answer from {url}"))
+    }
+
+    fn page_items<'a>(&self, page: &'a Value) -> anyhow::Result<Vec<&'a Value>> {
+        let selected = select_json_path(page, &self.items)?;
+        Ok(match selected.as_slice() {
+            [Value::Array(array)] => array.iter().collect(),
+            _ => selected,
+        })
+    }
+
+    /// Make the entry from the item, or return `None` if it has no valid position or id
+    fn item_to_map_entry(&self, item: &Value, position: usize) -> Option<MapEntry> {
+        let read = |path: &str| {
+            select_json_path(item, path)
+                .ok()?
+                .into_iter()
+                .find_map(value_to_string)
+                .filter(|value| !value.is_empty())
+        };
+        let get = |path: &Option<String>| read(path.as_ref()?);
+        let lat = parse_coordinate(&read(&self.mapping.latitude)?)?;
+        let lon = parse_coordinate(&read(&self.mapping.longitude)?)?;
+        let id = match &self.mapping.id {
+            Some(_) => get(&self.mapping.id)?,
+            None => position.to_string(),
+        };
+        let element_id = ElementId::External {
+            namespace: self.namespace.clone(),
+            id,
+        };
+        Some(
+            self.mapping
+                .make_entry(element_id, &self.source_text, (lat, lon), get),
+        )
+    }
+
+    /// The url of a numbered page, or of the page starting at the given item
+    fn numbered_page_url(&self, base_url: &Url, page_count: usize, item_count: usize) -> Url {
+        let mut url = base_url.clone();
+        match &self.pagination {
+            Pagination::Page { param, first } => {
+                url.query_pairs_mut()
+                    .append_pair(param, &(first + page_count as u64).to_string());
+            }
+            Pagination::Offset { param } => {
+                url.query_pairs_mut()
+                    .append_pair(param, &item_count.to_string());
+            }
+            Pagination::None | Pagination::NextUrl { .. } => (),
+        }
+        url
+    }
+
+    /// Every page of the answer, following the pagination
+    fn get_pages(&self, client: &Client) -> anyhow::Result<Vec<Value>> {
+        let base_url =
+            Url::parse(&self.url).with_context(|| format!("Invalid url {:?}", self.url))?;
+        let mut pages = Vec::new();
+        let mut url = self.numbered_page_url(&base_url, 0, 0);
+        let mut item_count = 0;
+        loop {
+            if pages.len() >= MAX_PAGES {
+                bail!("The answer has more than {MAX_PAGES} pages");
+            }
+            let page = self.get_page(client, &url)?;
+            if matches!(
+                self.pagination,
+                Pagination::Page { .. } | Pagination::Offset { .. }
+            ) && pages.last() == Some(&page)
+            {
+                // The API likely ignores the parameter, and would return this page forever
+                warn!(
+                    "The page at {url} is the same as the previous one, stopping there for {:?}",
+                    self.title
+                );
+                break;
+            }
+            let page_item_count = self.page_items(&page)?.len();
+            item_count += page_item_count;
+
+            let next_url = match &self.pagination {
+                Pagination::None => None,
+                Pagination::Page { .. } | Pagination::Offset { .. } => (page_item_count > 0)
+                    .then(|| self.numbered_page_url(&base_url, pages.len() + 1, item_count)),
+                Pagination::NextUrl { path } => match select_json_path(&page, path)?
+                    .first()
+                    .and_then(|next| next.as_str())
+                    .filter(|next| !next.is_empty())
+                {
+                    Some(next) => {
+                        let next_url = url
+                            .join(next)
+                            .with_context(|| format!("Invalid next page url {next:?}"))?;
+                        // The headers, like an API key, are only sent to the configured API
+                        if next_url.origin() != base_url.origin() {
+                            bail!(
+                                "The next page url {next_url} is not on the same site as {base_url}"
+                            );
+                        }
+                        (next_url != url).then_some(next_url)
+                    }
+                    None => None,
+                },
+            };
+            pages.push(page);
+            match next_url {
+                Some(next_url) => url = next_url,
+                None => break,
+            }
+        }
+        info!(
+            "Got {item_count} items in {} pages from {:?}",
+            pages.len(),
+            self.title
+        );
+        Ok(pages)
+    }
+}
+
+impl FetchData for FetchDataJsonApi {
+    fn fetch_data(&self) -> anyhow::Result<BTreeSet<MapEntry>> {
+        // Check the paths now, as an invalid one would otherwise be the same as a missing field
+        check_json_paths(&self.items, &self.mapping)?;
+
+        let client = Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(Duration::from_secs(60))
+            .build()?;
+        let pages = self.get_pages(&client)?;
+
+        let mut items = Vec::new();
+        for page in &pages {
+            items.extend(self.page_items(page)?);
+        }
+        let mut entries = Vec::new();
+        let mut without_position = 0;
+        for (position, item) in items.into_iter().enumerate() {
+            match self.item_to_map_entry(item, position) {
+                Some(entry) => entries.push(entry),
+                None => without_position += 1,
+            }
+        }
+        if without_position > 0 {
+            warn!(
+                "Ignored {without_position} items without a valid position or id in {:?}",
+                self.title
+            );
+        }
+        Ok(without_duplicates(entries, &self.title))
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn schedule(&self) -> &RefreshSchedule {
+        &self.schedule
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::mpsc::{Receiver, channel},
+        thread,
+    };
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_json_paths() {
+        use PathSegment::*;
+        assert_eq!(
+            parse_json_path("$.data.items[*]").unwrap(),
+            vec![Key("data".into()), Key("items".into()), All]
+        );
+        assert_eq!(
+            parse_json_path("images[0].url").unwrap(),
+            vec![Key("images".into()), Index(0), Key("url".into())]
+        );
+        assert_eq!(
+            parse_json_path("*.rows[2][*]").unwrap(),
+            vec![All, Key("rows".into()), Index(2), All]
+        );
+        assert_eq!(parse_json_path("$").unwrap(), vec![]);
+        for invalid in ["a[0", "a]", "a[0]]", "a[0]b", "a[x]", "a[-1]"] {
+            assert!(parse_json_path(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn selects_json_paths() {
+        let value = json!({
+            "data": {
+                "items": [
+                    { "name": "a", "images": [{ "url": "a.jpg" }] },
+                    { "name": "b", "images": [] },
+                ],
+                "by_id": { "x": { "name": "c" }, "y": { "name": "d" } },
+            }
+        });
+        let select = |path: &str| -> Vec<Value> {
+            select_json_path(&value, path)
+                .unwrap()
+                .into_iter()
+                .cloned()
+                .collect()
+        };
+        assert_eq!(select("$.data.items[*].name"), vec![json!("a"), json!("b")]);
+        assert_eq!(select("data.items[*].images[0].url"), vec![json!("a.jpg")]);
+        assert_eq!(select("$.data.by_id.*.name"), vec![json!("c"), json!("d")]);
+        assert_eq!(select("$.data.items[1].name"), vec![json!("b")]);
+        assert!(select("$.data.items[5]").is_empty());
+        assert!(select("$.data.items.name").is_empty());
+        assert!(select("$.data.missing[*]").is_empty());
+        assert!(select_json_path(&value, "data[").is_err());
+    }
+
+    /// Serve the answer for the path and query of each request, returning the url of the server and the head (request line and headers) of each request received
+    fn stub_api(answer: impl Fn(&str) -> Value + Send + 'static) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut head = String::new();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                    head.push_str(&line);
+                    line.clear();
+                }
+                let target = head.split(' ').nth(1).unwrap_or_default().to_string();
+                let _ = sender.send(head);
+                let body = answer(&target).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, receiver)
+    }
+
+    fn dragon(id: u64) -> Value {
+        json!({ "objectId": id, "title": format!("Dragon {id}"), "geo": { "lat": 48.0, "lng": id as f64 } })
+    }
+
+    fn fetcher(url: String, pagination: Pagination) -> FetchDataJsonApi {
+        let mapping: FieldMapping = serde_json::from_value(json!({
+            "id": "objectId",
+            "name": "title",
+            "latitude": "geo.lat",
+            "longitude": "geo.lng",
+            "image": "images[0].url",
+            "source_url": "link",
+        }))
+        .unwrap();
+        FetchDataJsonApi::new(
+            "museum".into(),
+            url,
+            "$.data.records[*]".into(),
+            "Museum".into(),
+            RefreshSchedule::every(Duration::from_secs(3600)),
+        )
+        .with_mapping(mapping)
+        .with_pagination(pagination)
+        .with_headers(BTreeMap::from([("X-Api-Key".into(), "secret".into())]))
+    }
+
+    fn ids(entries: &BTreeSet<MapEntry>) -> BTreeSet<String> {
+        entries
+            .iter()
+            .flat_map(|entry| entry.element_ids.iter().map(|id| id.to_string()))
+            .collect()
+    }
+
+    fn records(records: Vec<Value>) -> Value {
+        json!({ "data": { "records": records } })
+    }
+
+    #[test]
+    fn maps_the_fields_of_the_items() {
+        let (url, requests) = stub_api(|_| {
+            records(vec![
+                json!({
+                    "objectId": "A12",
+                    "title": "Dragon of the portal",
+                    "geo": { "lat": "48.1", "lng": -1.6 },
+                    "images": [{ "url": "https://museum.example.org/a12.jpg" }],
+                    "link": "https://museum.example.org/objects/A12",
+                }),
+                // No position
+                json!({ "objectId": "B7", "title": "Lost dragon" }),
+            ])
+        });
+        let entries = fetcher(format!("{url}/api/objects?q=dragon"), Pagination::None)
+            .fetch_data()
+            .unwrap();
+        let head = requests.recv().unwrap();
+        assert!(head.starts_with("GET /api/objects?q=dragon "));
+        assert!(head.to_lowercase().contains("x-api-key: secret"));
+        assert!(requests.try_recv().is_err());
+
+        assert_eq!(entries.len(), 1);
+        let entry = entries.first().unwrap();
+        assert_eq!(entry.element_ids[0].to_string(), "external:museum:A12");
+        assert_eq!(entry.name.as_deref(), Some("Dragon of the portal"));
+        assert_eq!(
+            entry.pos.map(|(lat, lon)| (lat.0, lon.0)),
+            Some((48.1, -1.6))
+        );
+        assert_eq!(
+            entry.image.as_ref().map(|image| image.url.as_str()),
+            Some("https://museum.example.org/a12.jpg")
+        );
+        assert_eq!(
+            entry.source_url.as_deref(),
+            Some("https://museum.example.org/objects/A12")
+        );
+        assert_eq!(entry.source_text, "Museum");
+    }
+
+    #[test]
+    fn follows_numbered_pages() {
+        let (url, requests) = stub_api(|target| match target {
+            "/api?page=1" => records(vec![dragon(1), dragon(2)]),
+            "/api?page=2" => records(vec![dragon(3)]),
+            _ => records(vec![]),
+        });
+        let pagination = Pagination::Page {
+            param: "page".into(),
+            first: 1,
+        };
+        let entries = fetcher(format!("{url}/api"), pagination)
+            .fetch_data()
+            .unwrap();
+        assert_eq!(
+            ids(&entries),
+            BTreeSet::from([
+                "external:museum:1".into(),
+                "external:museum:2".into(),
+                "external:museum:3".into()
+            ])
+        );
+        assert_eq!(requests.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn follows_offsets() {
+        let (url, requests) = stub_api(|target| match target {
+            "/api?offset=0" => records(vec![dragon(1), dragon(2)]),
+            "/api?offset=2" => records(vec![dragon(3)]),
+            _ => records(vec![]),
+        });
+        let pagination = Pagination::Offset {
+            param: "offset".into(),
+        };
+        let entries = fetcher(format!("{url}/api"), pagination)
+            .fetch_data()
+            .unwrap();
+        assert_eq!(entries.len(), 3);
+        let targets: Vec<String> = requests
+            .try_iter()
+            .map(|head| head.split(' ').nth(1).unwrap().to_string())
+            .collect();
+        assert_eq!(
+            targets,
+            vec!["/api?offset=0", "/api?offset=2", "/api?offset=3"]
+        );
+    }
+
+    #[test]
+    fn stops_when_a_page_repeats() {
+        // Like an API ignoring the page parameter
+        let (url, requests) = stub_api(|_| records(vec![dragon(1), dragon(2)]));
+        let pagination = Pagination::Page {
+            param: "page".into(),
+            first: 1,
+        };
+        let entries = fetcher(format!("{url}/api"), pagination)
+            .fetch_data()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(requests.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn follows_next_urls_on_the_same_site() {
+        let (url, requests) = stub_api(|target| match target {
+            "/api" => json!({
+                "data": { "records": [dragon(1)] },
+                "links": { "next": "/api?cursor=b" }
+            }),
+            _ => json!({ "data": { "records": [dragon(2)] }, "links": { "next": null } }),
+        });
+        let pagination = Pagination::NextUrl {
+            path: "links.next".into(),
+        };
+        let entries = fetcher(format!("{url}/api"), pagination)
+            .fetch_data()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        let heads: Vec<String> = requests.try_iter().collect();
+        assert_eq!(heads.len(), 2);
+        assert!(heads[1].starts_with("GET /api?cursor=b "));
+        assert!(heads[1].to_lowercase().contains("x-api-key: secret"));
+
+        let (url, requests) = stub_api(|_| {
+            json!({
+                "data": { "records": [dragon(1)] },
+                "links": { "next": "http://other.example.org/api?cursor=b" }
+            })
+        });
+        let pagination = Pagination::NextUrl {
+            path: "links.next".into(),
+        };
+        assert!(
+            fetcher(format!("{url}/api"), pagination)
+                .fetch_data()
+                .is_err()
+        );
+        assert_eq!(requests.try_iter().count(), 1);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{File, metadata},
+    path::PathBuf,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use tai_time::TaiTime;
+
+use crate::{
+    ElementId, FetchData, MapEntry, MapEntryImageSource, RefreshSchedule, fetch_data::file_changed,
+};
+
+fn default_source_text() -> String {
+    "Manually added".to_string()
+}
+
+/**
+ * An entry written by hand, for depictions that are in neither OpenStreetMap nor Wikidata.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalEntry {
+    /// Position, as `[latitude, longitude]`
+    pub pos: Option<(OrderedFloat<f64>, OrderedFloat<f64>)>,
+    pub name: Option<String>,
+    pub location_name: Option<String>,
+    /// File name of the image, in the `images` folder of the ressource directory
+    pub local_image: Option<String>,
+    pub image_source_url: Option<String>,
+    pub image_source_text: Option<String>,
+    pub source_url: Option<String>,
+    #[serde(default = "default_source_text")]
+    pub source_text: String,
+    #[serde(default)]
+    pub is_in_exhibit: bool,
+    pub nature: Option<String>,
+}
+
+impl LocalEntry {
+    pub fn to_map_entry(&self, id: &str) -> MapEntry {
+        MapEntry {
+            pos: self.pos,
+            name: self.name.clone(),
+            location_name: self.location_name.clone(),
+            image: self
+                .local_image
+                .as_ref()
+                .map(|local_image| MapEntryImageSource {
+                    url: format!("/images/{local_image}"),
+                    credit_url: self.image_source_url.clone(),
+                    credit_text: self.image_source_text.clone(),
+                    license_url: None,
+                    thumbnail_url: None,
+                }),
+            source_url: self.source_url.clone(),
+            source_text: self.source_text.clone(),
+            is_in_exhibit: self.is_in_exhibit,
+            nature: self.nature.clone(),
+            element_ids: vec![ElementId::Local(id.to_string())],
+        }
+    }
+}
+
+/**
+ * Read entries from a JSON file, as an object of [`LocalEntry`] keyed by their local id. It is read again as soon as the file is modified.
+ */
+pub struct FetchDataLocal {
+    path: PathBuf,
+    title: String,
+    schedule: RefreshSchedule,
+    /// Modification time of the file when it was last read
+    read_modified: Mutex<Option<SystemTime>>,
+}
+
+impl FetchDataLocal {
+    pub fn new(path: PathBuf, title: String, schedule: RefreshSchedule) -> Self {
+        Self {
+            path,
+            title,
+            schedule,
+            read_modified: Mutex::new(None),
+        }
+    }
+
+    /// Read the entries of the file, by local id
+    pub fn read_entries(&self) -> anyhow::Result<BTreeMap<String, LocalEntry>> {
+        let f = File::open(&self.path)
+            .with_context(|| format!("Opening local entries at {:?}", self.path))?;
+        serde_json::from_reader(f)
+            .with_context(|| format!("Parsing local entries at {:?}", self.path))
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
+
+impl FetchData for FetchDataLocal {
+    fn fetch_data(&self) -> anyhow::Result<BTreeSet<MapEntry>> {
+        // Set before reading, so a broken file is not read again until it is modified
+        if let Ok(mut read_modified) = self.read_modified.lock() {
+            *read_modified = self.modified();
+        }
+        let entries = self.read_entries()?;
+
+        Ok(entries
+            .iter()
+            .map(|(id, entry)| entry.to_map_entry(id))
+            .collect())
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn schedule(&self) -> &RefreshSchedule {
+        &self.schedule
+    }
+
+    fn source_changed(&self, last_updated: Option<TaiTime<0>>) -> bool {
+        file_changed(&self.read_modified, self.modified(), last_updated)
+    }
+}
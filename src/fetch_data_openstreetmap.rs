@@ -1,10 +1,10 @@
-use std::{collections::BTreeSet, time::Duration};
+use std::collections::BTreeSet;
 
 use anyhow::bail;
 use log::warn;
 use osm_overpass::api::{NWR, OverpassAPI};
 
-use crate::{ElementId, FetchData, MapEntry};
+use crate::{ElementId, FetchData, MapEntry, RefreshSchedule};
 
 #[allow(clippy::single_match)]
 fn guess_nature_from_tags(tags: &std::collections::HashMap<String, String>) -> Option<String> {
@@ -21,6 +21,7 @@ pub struct FetchDataOpenStreetMap {
     pub query: String,
     pub api: OverpassAPI,
     pub title: String,
+    pub schedule: RefreshSchedule,
 }
 
 impl FetchDataOpenStreetMap {
@@ -74,7 +75,7 @@ impl FetchData for FetchDataOpenStreetMap {
         self.title.clone()
     }
 
-    fn retry_every(&self) -> Duration {
-        Duration::from_secs(3600 * 3)
+    fn schedule(&self) -> &RefreshSchedule {
+        &self.schedule
     }
 }
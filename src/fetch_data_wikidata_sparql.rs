@@ -1,4 +1,4 @@
-use std::{collections::BTreeSet, time::Duration};
+use std::collections::BTreeSet;
 
 use anyhow::{Context, bail};
 use ordered_float::OrderedFloat;
@@ -6,7 +6,7 @@ use reqwest::blocking::Client;
 use serde::Deserialize;
 use url::Url;
 
-use crate::{ElementId, FetchData, MapEntry, MapEntryImageSource, USER_AGENT};
+use crate::{ElementId, FetchData, MapEntry, MapEntryImageSource, RefreshSchedule, USER_AGENT};
 
 fn parse_point(value: &str) -> Option<(f64, f64)> {
     let second_part = value.split("Point(").nth(1)?;
@@ -105,11 +105,16 @@ impl WikidataDocument {
 pub struct FetchDataWikidataSparql {
     query: String,
     title: String,
+    schedule: RefreshSchedule,
 }
 
 impl FetchDataWikidataSparql {
-    pub fn new(query: String, title: String) -> anyhow::Result<Self> {
-        Ok(Self { query, title })
+    pub fn new(query: String, title: String, schedule: RefreshSchedule) -> anyhow::Result<Self> {
+        Ok(Self {
+            query,
+            title,
+            schedule,
+        })
     }
 }
 
@@ -185,6 +190,8 @@ impl FetchData for FetchDataWikidataSparql {
                         url: image_url.into(),
                         credit_text: Some("Image from Wikimedia Commons".into()),
                         credit_url: image_credit_url,
+                        license_url: None,
+                        thumbnail_url: None,
                     }),
                 source_url: Some(item_url),
                 source_text: "From Wikidata".into(),
@@ -202,8 +209,8 @@ impl FetchData for FetchDataWikidataSparql {
         Ok(results)
     }
 
-    fn retry_every(&self) -> std::time::Duration {
-        Duration::from_secs(3600 * 3)
+    fn schedule(&self) -> &RefreshSchedule {
+        &self.schedule
     }
 
     fn title(&self) -> String {
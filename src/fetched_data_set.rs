@@ -1,71 +1,245 @@
 use std::{
-    collections::{BTreeSet, HashSet},
-    fs::File,
-    io::Write,
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    collections::{BTreeSet, HashMap, HashSet},
+    fs::metadata,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
 };
 
 use anyhow::{Context, bail};
-use git2::{Repository, RepositoryInitOptions};
+use arc_swap::ArcSwap;
 use log::{info, warn};
-use pathdiff::diff_paths;
+use serde::{Deserialize, Serialize};
 use tai_time::TaiTime;
 
-use crate::{DepictionCategory, FetchData, MapEntry, Overrides, Storage, make_commit};
+use crate::{
+    COMMONS_API_URL, CommonsMetadataFetcher, Config, DepictionCategory, EntryDiff, Feed, FeedItem,
+    FeedItemKind, FetchData, MapEntry, OverrideReport, Overrides, PendingUpdateStatus,
+    SourceStatus, Storage, StorageBackend, Thumbnailer, TimeSource, WebhookNotifier,
+    WebhookPayload, commons_title, open_storage_backend, open_storage_backend_read_only,
+    time_source::unix_secs_from_tai,
+};
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Fetched entries held back by the mass-deletion guard. It is stored with the private data, so it can still be accepted after a restart (or a `fetch` from cron).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingUpdate {
+    pub entries: BTreeSet<MapEntry>,
+    pub diff: EntryDiff,
+    pub fetched_at: TaiTime<0>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    NotNeeded,
+    Updated,
+    /// The new data was fetched but not applied, as it would remove too many entries
+    HeldBack,
+}
 
 pub struct FetchedDataEntry {
     pub storage: Storage,
     pub fetcher: Box<dyn FetchData + Send>,
     pub depict: BTreeSet<DepictionCategory>,
+    /// The error of the last failed update, reset on success
+    pub last_error: Option<String>,
 }
 
 impl FetchedDataEntry {
-    pub fn should_be_updated(&self, current_time: TaiTime<0>) -> bool {
-        if let Some(fetched_time) = &self.storage.data.private.last_updated {
-            // if the clock goes backward for some reason, refetch the data (and so re-set the time)
-            if current_time < *fetched_time {
-                return true;
+    pub fn should_be_updated(&self, time_source: &dyn TimeSource) -> bool {
+        let current_time = time_source.now();
+        let last_updated = self.storage.data.private.last_updated;
+        if self.fetcher.source_changed(last_updated) {
+            return true;
+        }
+        self.fetcher.schedule().is_due(last_updated, current_time)
+    }
+
+    /// Update the source if it is due, see [`Self::perform_update`]
+    pub fn perform_update_if_needed(
+        &mut self,
+        time_source: &dyn TimeSource,
+    ) -> anyhow::Result<UpdateOutcome> {
+        if !self.should_be_updated(time_source) {
+            return Ok(UpdateOutcome::NotNeeded);
+        }
+        self.perform_update(time_source)
+    }
+
+    /// Fetch the data, and replace the stored entries unless the mass-deletion guard is triggered
+    pub fn perform_update(
+        &mut self,
+        time_source: &dyn TimeSource,
+    ) -> anyhow::Result<UpdateOutcome> {
+        info!("Updating {:?}", self.fetcher.title());
+        let current_time = time_source.now();
+        self.storage.data.private.last_updated = Some(current_time); // Set first but not save, so it will still wait if an error occur (but will retry when restarted or just later)
+        let new_entries = self
+            .fetcher
+            .fetch_data()
+            .with_context(|| format!("Fetching data from {:?}", self.fetcher.title()))?;
+        let new_entries = self.add_commons_attributions(new_entries);
+        let diff = EntryDiff::compute(&self.storage.data.public.entries, &new_entries);
+        info!("Changes for {:?}: {}", self.fetcher.title(), diff.summary());
+
+        let guard = &self.storage.get_extra().config.mass_deletion_guard;
+        if guard.is_triggered_by(&diff, self.storage.data.public.entries.len()) {
+            warn!(
+                "Holding back the update of {:?}, as it would remove {} of {} entries. It needs to be accepted by an admin.",
+                self.fetcher.title(),
+                diff.removed.len(),
+                self.storage.data.public.entries.len()
+            );
+            let title = self.fetcher.title();
+            self.storage.data.private.pending_update = Some(PendingUpdate {
+                entries: new_entries,
+                diff,
+                fetched_at: current_time,
+            });
+            self.storage
+                .save(&format!("Hold back the update of {title}"))
+                .with_context(|| format!("Saving the held back update of {title:?}"))?;
+            return Ok(UpdateOutcome::HeldBack);
+        }
+
+        self.storage.data.private.pending_update = None;
+        self.apply_update(new_entries, diff, current_time)?;
+        Ok(UpdateOutcome::Updated)
+    }
+
+    /// Credit the Wikimedia Commons images with their author and license, querying Commons for the files that are not yet cached. The generic credit is kept if Commons can’t be reached.
+    fn add_commons_attributions(&mut self, entries: BTreeSet<MapEntry>) -> BTreeSet<MapEntry> {
+        let extra = self.storage.get_extra().clone();
+        let cache = &mut self.storage.data.private.commons_attributions;
+        let titles: BTreeSet<String> = entries
+            .iter()
+            .filter_map(|entry| entry.image.as_ref())
+            .filter_map(|image| commons_title(&image.url))
+            .collect();
+        cache.retain(|title, _| titles.contains(title));
+
+        let missing: Vec<String> = titles
+            .into_iter()
+            .filter(|title| !cache.contains_key(title))
+            .collect();
+        if !missing.is_empty() {
+            info!(
+                "Querying the attribution of {} images from Wikimedia Commons",
+                missing.len()
+            );
+            let fetched = extra.commons_metadata.fetch(&missing);
+            for title in fetched.queried {
+                // Also cache the files that don’t exist, so they are not queried again
+                let attribution = fetched
+                    .attributions
+                    .get(&title)
+                    .cloned()
+                    .unwrap_or_default();
+                cache.insert(title, attribution);
             }
-            if *fetched_time + self.fetcher.retry_every() < current_time {
-                return true;
+            if let Some(err) = fetched.error {
+                warn!("Could not get the attribution of some of the Commons images: {err:?}");
             }
-            false
-        } else {
-            true
         }
+
+        entries
+            .into_iter()
+            .map(|mut entry| {
+                if let Some(image) = &mut entry.image
+                    && let Some(title) = commons_title(&image.url)
+                    && let Some(attribution) = cache.get(&title)
+                {
+                    attribution.apply(image);
+                }
+                entry
+            })
+            .collect()
     }
 
-    /// Return true if successfully updated, false if not needed
-    pub fn perform_update_if_needed(&mut self, current_time: TaiTime<0>) -> anyhow::Result<bool> {
-        if self.should_be_updated(current_time) {
-            info!("Updating {:?}", self.fetcher.title());
-            self.storage.data.private.last_updated = Some(current_time); // Set first but not save, so it will still wait if an error occur (but will retry when restarted or just later)
-            self.storage.data.public.entries = self
-                .fetcher
-                .fetch_data()
-                .with_context(|| format!("Fetching data from {:?}", self.fetcher.title()))?;
-            self.storage
-                .save()
-                .with_context(|| format!("Saving data of {:?}", self.fetcher.title()))?;
-
-            let extra = self.storage.get_extra();
-            let repo = match extra.repo.lock() {
-                Ok(r) => r,
-                Err(err) => bail!("Failed to get repo: {:?}", err), // This error can’t be used by anyhow directly
-            };
-
-            make_commit(
-                &repo,
-                &diff_paths(self.storage.get_storage_file(), &extra.save_storage_dir)
-                    .context("Could not diff paths for indexing with git")?,
-                &format!("Update {}", self.fetcher.title()),
-            )
-            .context("Commiting changes to git")?;
+    /// Apply the update held back by the mass-deletion guard. Return false if there was none.
+    pub fn accept_pending_update(&mut self) -> anyhow::Result<bool> {
+        match self.storage.data.private.pending_update.take() {
+            Some(pending_update) => {
+                info!(
+                    "Accepting the held back update of {:?}",
+                    self.fetcher.title()
+                );
+                self.apply_update(
+                    pending_update.entries,
+                    pending_update.diff,
+                    pending_update.fetched_at,
+                )?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
 
-            Ok(true)
-        } else {
-            Ok(false)
+    /// Store the new entries, fetched at `fetched_at`
+    fn apply_update(
+        &mut self,
+        new_entries: BTreeSet<MapEntry>,
+        diff: EntryDiff,
+        fetched_at: TaiTime<0>,
+    ) -> anyhow::Result<()> {
+        let commit_message = format!(
+            "Update {}\n\n{}",
+            self.fetcher.title(),
+            diff.commit_message_body()
+        );
+        self.storage.data.public.entries = new_entries;
+        self.storage.data.private.last_diff = Some(diff);
+        self.storage.data.private.last_successful_update = Some(fetched_at);
+        self.storage
+            .save(&commit_message)
+            .with_context(|| format!("Saving data of {:?}", self.fetcher.title()))?;
+
+        let extra = self.storage.get_extra();
+        if let Some(diff) = &self.storage.data.private.last_diff
+            && !diff.is_empty()
+        {
+            extra.webhooks.notify(WebhookPayload::new(
+                self.storage.get_name().to_string(),
+                self.fetcher.title(),
+                self.depict.iter().cloned().collect(),
+                diff,
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn status(&self) -> SourceStatus {
+        SourceStatus {
+            name: self.storage.get_name().to_string(),
+            title: self.fetcher.title(),
+            categories: self.depict.iter().cloned().collect(),
+            entries_count: self.storage.data.public.entries.len(),
+            last_updated: self
+                .storage
+                .data
+                .private
+                .last_updated
+                .map(unix_secs_from_tai),
+            last_successful_update: self
+                .storage
+                .data
+                .private
+                .last_successful_update
+                .map(unix_secs_from_tai),
+            age_secs: None,
+            pending_update: self.storage.data.private.pending_update.as_ref().map(
+                |pending_update| PendingUpdateStatus {
+                    previous_count: self.storage.data.public.entries.len(),
+                    new_count: pending_update.entries.len(),
+                    removed_count: pending_update.diff.removed.len(),
+                    fetched_at: unix_secs_from_tai(pending_update.fetched_at),
+                },
+            ),
+            last_error: self.last_error.clone(),
         }
     }
 }
@@ -73,51 +247,83 @@ impl FetchedDataEntry {
 pub struct FetchedDataSet {
     pub entries: Vec<FetchedDataEntry>,
     pub extra: Arc<FetchDataExtra>,
+    pub feeds: HashMap<DepictionCategory, Feed>,
+    /// Modification time of the overrides file when it was last loaded
+    overrides_modified: Option<SystemTime>,
 }
 
 pub struct FetchDataExtra {
     pub save_storage_dir: PathBuf,
-    pub overrides: Overrides,
-    pub repo: Mutex<Repository>,
+    pub overrides: ArcSwap<Overrides>,
+    pub overrides_path: PathBuf,
+    /// Where the `local_image` of the overrides and local entries are
+    pub images_path: PathBuf,
+    pub backend: Box<dyn StorageBackend>,
+    pub thumbnailer: Thumbnailer,
+    pub commons_metadata: CommonsMetadataFetcher,
+    pub config: Config,
+    pub webhooks: WebhookNotifier,
 }
 
 impl FetchedDataSet {
-    pub fn new(default_storage_dir: PathBuf, overrides: Overrides) -> anyhow::Result<Self> {
-        let repo = match Repository::open(&default_storage_dir) {
-            Ok(repo) => repo,
-            Err(err) => {
-                if err.code() == git2::ErrorCode::NotFound {
-                    info!("Creating new storage repo in {default_storage_dir:?}");
-                    Repository::init_opts(
-                        &default_storage_dir,
-                        RepositoryInitOptions::new().no_reinit(true),
-                    )
-                    .with_context(|| {
-                        format!("Creating new storage repo in {default_storage_dir:?}")
-                    })?
-                } else {
-                    return Err(Into::<anyhow::Error>::into(err)).with_context(|| {
-                        format!("Opening storage repo in {default_storage_dir:?}")
-                    });
-                }
-            }
-        };
+    pub fn new(
+        default_storage_dir: PathBuf,
+        ressource_path: &Path,
+        config: Config,
+    ) -> anyhow::Result<Self> {
+        let backend =
+            open_storage_backend(&config, &default_storage_dir).context("Opening the storage")?;
+        Self::with_backend(default_storage_dir, ressource_path, config, backend, false)
+    }
 
-        let gitignore_path = default_storage_dir.join(".gitignore");
-        if !gitignore_path.exists() {
-            let mut f = File::create(&gitignore_path)
-                .with_context(|| format!("Creating .gitignore in {default_storage_dir:?}"))?;
-            writeln!(f, "*.private\n")
-                .with_context(|| format!("Writing .gitignore in {default_storage_dir:?}"))?;
-            make_commit(&repo, &PathBuf::from(".gitignore"), "Add .gitignore")?;
+    /// Like [`Self::new`], but the storage is never written to, so the sources can’t be updated, and the remote images are not downloaded to make their thumbnails. To only look at the stored data.
+    pub fn new_read_only(
+        default_storage_dir: PathBuf,
+        ressource_path: &Path,
+        config: Config,
+    ) -> anyhow::Result<Self> {
+        let backend = open_storage_backend_read_only(&config, &default_storage_dir)
+            .context("Opening the storage")?;
+        Self::with_backend(default_storage_dir, ressource_path, config, backend, true)
+    }
+
+    fn with_backend(
+        default_storage_dir: PathBuf,
+        ressource_path: &Path,
+        config: Config,
+        backend: Box<dyn StorageBackend>,
+        read_only: bool,
+    ) -> anyhow::Result<Self> {
+        let overrides_path = ressource_path.join("overrides.json");
+        let overrides_modified = modified_time(&overrides_path);
+        let overrides = Overrides::load(&overrides_path)?;
+        let images_path = ressource_path.join("images");
+        let mut thumbnailer = Thumbnailer::new(
+            config.thumbnails.clone(),
+            images_path.clone(),
+            default_storage_dir.join("thumbnails"),
+        )
+        .context("Creating the thumbnailer")?;
+        if read_only {
+            thumbnailer = thumbnailer.without_downloads();
         }
 
         Ok(Self {
             entries: Vec::new(),
+            feeds: HashMap::new(),
+            overrides_modified,
             extra: Arc::new(FetchDataExtra {
                 save_storage_dir: default_storage_dir,
-                overrides,
-                repo: Mutex::new(repo),
+                overrides: ArcSwap::from_pointee(overrides),
+                overrides_path,
+                images_path,
+                backend,
+                thumbnailer,
+                commons_metadata: CommonsMetadataFetcher::new(COMMONS_API_URL.to_string())
+                    .context("Creating the Commons client")?,
+                webhooks: WebhookNotifier::new(config.webhooks.clone())
+                    .context("Creating the webhook notifier")?,
+                config,
             }),
         })
     }
@@ -128,16 +334,30 @@ impl FetchedDataSet {
         depict: Vec<DepictionCategory>,
         storage_file_name: String,
     ) -> anyhow::Result<()> {
-        let mut storage = Storage::new(storage_file_name.clone(), self.extra.clone())?;
-        match storage.load() {
-            Ok(_) => (),
-            Err(err) => warn!("Failed to load some storage at {storage_file_name}: {err:?}"),
-        };
+        if self
+            .entries
+            .iter()
+            .any(|entry| entry.storage.get_name() == storage_file_name)
+        {
+            bail!("There is already a source stored in {storage_file_name}");
+        }
+        let mut storage = Storage::new(storage_file_name.clone(), self.extra.clone());
+        storage
+            .load()
+            .with_context(|| format!("Loading the storage at {storage_file_name}"))?;
+
+        for category in &depict {
+            if !self.feeds.contains_key(category) {
+                let feed = Feed::load(&Feed::get_path(&self.extra.save_storage_dir, category))?;
+                self.feeds.insert(category.clone(), feed);
+            }
+        }
 
         self.entries.push(FetchedDataEntry {
             storage,
             fetcher: Box::new(fetch_data),
             depict: depict.into_iter().collect(),
+            last_error: None,
         });
 
         Ok(())
@@ -149,20 +369,20 @@ impl FetchedDataSet {
     ) -> Vec<MapEntry> {
         //TODO: deduplication
         let mut result = Vec::new();
+        let overrides = self.extra.overrides.load();
 
         for source_entry in &self.entries {
             let should_be_used = source_entry.depict.iter().any(|e| *e == depict_category);
             if should_be_used {
                 for map_entry in source_entry.storage.data.public.entries.iter() {
-                    let mut map_entry = map_entry.clone();
-                    for element_id in map_entry.element_ids.clone().iter() {
-                        if let Some(override_entry) = self.extra.overrides.get_override(element_id)
-                        {
-                            override_entry.override_map_entry(&mut map_entry);
+                    if let Some(mut map_entry) =
+                        Self::apply_overrides(&overrides, &depict_category, map_entry)
+                    {
+                        if let Some(image) = &mut map_entry.image {
+                            image.thumbnail_url = self.extra.thumbnailer.thumbnail_url(&image.url);
                         }
+                        result.push(map_entry);
                     }
-                    map_entry.post_process();
-                    result.push(map_entry);
                 }
             }
         }
@@ -170,6 +390,183 @@ impl FetchedDataSet {
         result
     }
 
+    /// Return a copy of the entry, in its final state in the given category, with the overrides applied. Return `None` if it is hidden.
+    fn apply_overrides(
+        overrides: &Overrides,
+        depict_category: &DepictionCategory,
+        map_entry: &MapEntry,
+    ) -> Option<MapEntry> {
+        let mut result = map_entry.clone();
+        for override_entry in overrides.get_overrides(&map_entry.element_ids, depict_category) {
+            if override_entry.hidden {
+                return None;
+            }
+            override_entry.override_map_entry(&mut result);
+        }
+        result.post_process();
+        Some(result)
+    }
+
+    /// Update once every source that needs it, recording the changes in the feeds. The errors are only logged, so the other sources are still updated. Return false if any update failed.
+    pub fn update_due_sources(&mut self, time_source: &dyn TimeSource) -> bool {
+        let mut success = true;
+        for entry_pos in 0..self.entries.len() {
+            success &= self.update_source(entry_pos, time_source, false).is_some();
+        }
+        success
+    }
+
+    /// Update the source at the given position if it needs it (or anyway if `force` is set), recording the changes in the feeds. Return `None` if the update failed, the error being logged and kept in its status.
+    pub fn update_source(
+        &mut self,
+        entry_pos: usize,
+        time_source: &dyn TimeSource,
+        force: bool,
+    ) -> Option<UpdateOutcome> {
+        let entry = &mut self.entries[entry_pos];
+        let outcome = if force {
+            entry.perform_update(time_source)
+        } else {
+            entry.perform_update_if_needed(time_source)
+        };
+        let title = entry.fetcher.title();
+        match outcome {
+            Ok(UpdateOutcome::NotNeeded) => (),
+            Ok(UpdateOutcome::Updated) => {
+                entry.last_error = None;
+                self.record_update_in_feeds(entry_pos, time_source);
+            }
+            Ok(UpdateOutcome::HeldBack) => {
+                entry.last_error = None;
+                warn!("The update of {title:?} is held back, keeping the stored data")
+            }
+            Err(err) => {
+                warn!("Could not perform update of {title:?}: {err:?}");
+                entry.last_error = Some(format!("{err:#}"));
+                return None;
+            }
+        }
+        outcome.ok()
+    }
+
+    /// Apply the update of the source at the given position held back by the mass-deletion guard, recording the changes in the feeds. Return false if there was none.
+    pub fn accept_pending_update(
+        &mut self,
+        entry_pos: usize,
+        time_source: &dyn TimeSource,
+    ) -> anyhow::Result<bool> {
+        if !self.entries[entry_pos].accept_pending_update()? {
+            return Ok(false);
+        }
+        self.record_update_in_feeds(entry_pos, time_source);
+        Ok(true)
+    }
+
+    fn record_update_in_feeds(&mut self, entry_pos: usize, time_source: &dyn TimeSource) {
+        if let Err(err) = self.record_in_feeds(entry_pos, unix_secs_from_tai(time_source.now())) {
+            warn!("Could not update the feeds: {err:?}");
+        }
+    }
+
+    /// Add the entries added, changed and removed by the last update of the given source to the feeds of its categories
+    pub fn record_in_feeds(&mut self, entry_pos: usize, unix_time: i64) -> anyhow::Result<()> {
+        let source_entry = &self.entries[entry_pos];
+        let Some(diff) = &source_entry.storage.data.private.last_diff else {
+            return Ok(());
+        };
+        if diff.is_initial {
+            // Do not flood the feed with everything that already existed
+            return Ok(());
+        }
+
+        let overrides = self.extra.overrides.load();
+        let changed: Vec<MapEntry> = diff
+            .changed
+            .iter()
+            .map(|change| change.new.clone())
+            .collect();
+        for category in source_entry.depict.clone() {
+            let mut items = Vec::new();
+            for (kind, entries) in [
+                (FeedItemKind::Added, &diff.added),
+                (FeedItemKind::Changed, &changed),
+                (FeedItemKind::Removed, &diff.removed),
+            ] {
+                for entry in entries {
+                    let Some(entry) = Self::apply_overrides(&overrides, &category, entry) else {
+                        continue;
+                    };
+                    items.push(FeedItem {
+                        kind,
+                        entry,
+                        time: unix_time,
+                        source_title: source_entry.fetcher.title(),
+                    });
+                }
+            }
+            if items.is_empty() {
+                continue;
+            }
+
+            let feed = self.feeds.entry(category.clone()).or_default();
+            for item in items {
+                feed.push(item);
+            }
+            feed.save(&Feed::get_path(&self.extra.save_storage_dir, &category))
+                .with_context(|| format!("Saving the feed of {category:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// Load the overrides file again, keeping the current overrides if it is invalid
+    pub fn reload_overrides(&mut self) -> anyhow::Result<()> {
+        self.overrides_modified = modified_time(&self.extra.overrides_path);
+        let overrides = Overrides::load(&self.extra.overrides_path)?;
+        self.extra.overrides.store(Arc::new(overrides));
+        info!("Reloaded overrides from {:?}", self.extra.overrides_path);
+        Ok(())
+    }
+
+    /// Reload the overrides if the file was modified since it was last loaded. Return true if they were reloaded.
+    pub fn reload_overrides_if_modified(&mut self) -> anyhow::Result<bool> {
+        if modified_time(&self.extra.overrides_path) == self.overrides_modified {
+            return Ok(false);
+        }
+        self.reload_overrides()?;
+        Ok(true)
+    }
+
+    /// Check the overrides against the stored entries and the images directory
+    pub fn check_overrides(&self) -> OverrideReport {
+        let known_ids = self
+            .entries
+            .iter()
+            .flat_map(|source_entry| source_entry.storage.data.public.entries.iter())
+            .flat_map(|map_entry| map_entry.element_ids.iter())
+            .collect();
+        OverrideReport::check(
+            &self.extra.overrides.load(),
+            &known_ids,
+            &self.extra.images_path,
+        )
+    }
+
+    /// Render the feed of the given category as an Atom document
+    pub fn build_atom_for_depiction_category(&self, depict_category: &DepictionCategory) -> String {
+        let public_url = self.extra.config.public_url();
+        match self.feeds.get(depict_category) {
+            Some(feed) => feed.to_atom(depict_category, public_url.as_ref()),
+            None => Feed::default().to_atom(depict_category, public_url.as_ref()),
+        }
+    }
+
+    /// Return the position of the source with the given name
+    pub fn find_source(&self, source: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.storage.get_name() == source)
+    }
+
     pub fn list_all_depiction_category(&self) -> HashSet<&DepictionCategory> {
         let mut result = HashSet::new();
         for source_entry in &self.entries {
@@ -180,3 +577,267 @@ impl FetchedDataSet {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{File, write},
+        ops::Range,
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    };
+
+    use serde_json::json;
+    use tempfile::{TempDir, tempdir};
+
+    use super::*;
+    use crate::{ElementId, FakeTimeSource, RefreshSchedule, time_source::tai_from_system_time};
+
+    /// What the stub source answers, `None` making the fetch fail
+    type StubAnswer = Arc<Mutex<Option<BTreeSet<MapEntry>>>>;
+
+    struct StubFetcher {
+        answer: StubAnswer,
+        schedule: RefreshSchedule,
+    }
+
+    impl FetchData for StubFetcher {
+        fn fetch_data(&self) -> anyhow::Result<BTreeSet<MapEntry>> {
+            self.answer
+                .lock()
+                .unwrap()
+                .clone()
+                .context("The source is down")
+        }
+
+        fn title(&self) -> String {
+            "stub".into()
+        }
+
+        fn schedule(&self) -> &RefreshSchedule {
+            &self.schedule
+        }
+    }
+
+    fn entries(ids: Range<u64>) -> BTreeSet<MapEntry> {
+        ids.map(|id| {
+            serde_json::from_value(json!({
+                "pos": [48.0, id as f64],
+                "name": format!("dragon {id}"),
+                "location_name": null,
+                "image": null,
+                "source_url": null,
+                "source_text": "stub",
+                "is_in_exhibit": false,
+                "nature": null,
+                "element_ids": [{ "Osm": id }],
+            }))
+            .unwrap()
+        })
+        .collect()
+    }
+
+    /// A set with a single stub source, in a new temporary ressource directory
+    fn stub_set(config: Config) -> (TempDir, FetchedDataSet, StubAnswer) {
+        let temp = tempdir().unwrap();
+        write(temp.path().join("overrides.json"), "{}").unwrap();
+        let mut fetched_data_set =
+            FetchedDataSet::new(temp.path().join("save"), temp.path(), config).unwrap();
+        let answer = Arc::new(Mutex::new(None));
+        fetched_data_set
+            .add_fetcher(
+                StubFetcher {
+                    answer: answer.clone(),
+                    schedule: RefreshSchedule::every(Duration::from_secs(3600)),
+                },
+                vec![DepictionCategory::dragon()],
+                "stub.json".into(),
+            )
+            .unwrap();
+        (temp, fetched_data_set, answer)
+    }
+
+    fn stored_count(fetched_data_set: &FetchedDataSet) -> usize {
+        fetched_data_set
+            .extra
+            .backend
+            .load("stub.json")
+            .unwrap()
+            .map(|data| data.public.entries.len())
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn held_back_update_waits_to_be_accepted() {
+        let (_temp, mut fetched_data_set, answer) = stub_set(Config::default());
+        let time_source = FakeTimeSource::new(tai_from_system_time(SystemTime::now()));
+
+        *answer.lock().unwrap() = Some(entries(0..20));
+        assert_eq!(
+            fetched_data_set.update_source(0, &time_source, true),
+            Some(UpdateOutcome::Updated)
+        );
+        assert_eq!(stored_count(&fetched_data_set), 20);
+
+        // Removes 75% of the entries
+        *answer.lock().unwrap() = Some(entries(0..5));
+        assert_eq!(
+            fetched_data_set.update_source(0, &time_source, true),
+            Some(UpdateOutcome::HeldBack)
+        );
+        let storage = &fetched_data_set.entries[0].storage;
+        assert_eq!(storage.data.public.entries, entries(0..20));
+        assert!(storage.data.private.pending_update.is_some());
+        assert_eq!(stored_count(&fetched_data_set), 20);
+        let status = fetched_data_set.entries[0].status();
+        assert_eq!(status.pending_update.unwrap().removed_count, 15);
+
+        assert!(
+            fetched_data_set
+                .accept_pending_update(0, &time_source)
+                .unwrap()
+        );
+        let storage = &fetched_data_set.entries[0].storage;
+        assert_eq!(storage.data.public.entries, entries(0..5));
+        assert!(storage.data.private.pending_update.is_none());
+        assert_eq!(stored_count(&fetched_data_set), 5);
+        // Nothing left to accept
+        assert!(
+            !fetched_data_set
+                .accept_pending_update(0, &time_source)
+                .unwrap()
+        );
+    }
+
+    /// Write the overrides file, with a modification time that differs from the previous writes
+    fn write_overrides(fetched_data_set: &FetchedDataSet, content: &str, age_secs: u64) {
+        let path = &fetched_data_set.extra.overrides_path;
+        write(path, content).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(age_secs))
+            .unwrap();
+    }
+
+    fn overridden_name(fetched_data_set: &FetchedDataSet) -> Option<String> {
+        fetched_data_set
+            .extra
+            .overrides
+            .load()
+            .global
+            .osm
+            .get(&1)
+            .and_then(|entry| entry.name.clone())
+    }
+
+    #[test]
+    fn reloads_modified_overrides() {
+        let (_temp, mut fetched_data_set, _answer) = stub_set(Config::default());
+        write_overrides(
+            &fetched_data_set,
+            r#"{"osm": {"1": {"name": "first"}}}"#,
+            30,
+        );
+        assert!(fetched_data_set.reload_overrides_if_modified().unwrap());
+        assert_eq!(overridden_name(&fetched_data_set).as_deref(), Some("first"));
+
+        // Same modification time
+        assert!(!fetched_data_set.reload_overrides_if_modified().unwrap());
+
+        write_overrides(
+            &fetched_data_set,
+            r#"{"osm": {"1": {"name": "second"}}}"#,
+            20,
+        );
+        assert!(fetched_data_set.reload_overrides_if_modified().unwrap());
+        assert_eq!(
+            overridden_name(&fetched_data_set).as_deref(),
+            Some("second")
+        );
+    }
+
+    #[test]
+    fn keeps_overrides_when_the_file_is_broken() {
+        let (_temp, mut fetched_data_set, _answer) = stub_set(Config::default());
+        write_overrides(
+            &fetched_data_set,
+            r#"{"osm": {"1": {"name": "valid"}}}"#,
+            30,
+        );
+        assert!(fetched_data_set.reload_overrides_if_modified().unwrap());
+
+        write_overrides(&fetched_data_set, r#"{"osm": {"1": {"name": "#, 20);
+        assert!(fetched_data_set.reload_overrides_if_modified().is_err());
+        assert_eq!(overridden_name(&fetched_data_set).as_deref(), Some("valid"));
+        // The broken file is not read again until it is modified
+        assert!(!fetched_data_set.reload_overrides_if_modified().unwrap());
+
+        write_overrides(
+            &fetched_data_set,
+            r#"{"osm": {"1": {"name": "fixed"}}}"#,
+            10,
+        );
+        assert!(fetched_data_set.reload_overrides_if_modified().unwrap());
+        assert_eq!(overridden_name(&fetched_data_set).as_deref(), Some("fixed"));
+    }
+
+    #[test]
+    fn category_overrides_win_over_global_ones_of_any_id() {
+        let overrides: Overrides = serde_json::from_value(json!({
+            "osm": { "1": { "name": "global osm" } },
+            "wikidata": { "Q1": { "name": "global wikidata", "nature": "statue" } },
+            "categories": { "dragon": { "osm": { "1": { "name": "dragon osm" } } } },
+        }))
+        .unwrap();
+        let mut map_entry = entries(1..2).into_iter().next().unwrap();
+        map_entry.element_ids = vec![ElementId::Osm(1), ElementId::Wikidata("Q1".into())];
+
+        let dragon =
+            FetchedDataSet::apply_overrides(&overrides, &DepictionCategory::dragon(), &map_entry)
+                .unwrap();
+        // The global override of the second id does not replace the category override of the first one
+        assert_eq!(dragon.name.as_deref(), Some("dragon osm"));
+        assert_eq!(dragon.nature.as_deref(), Some("statue"));
+
+        let wyvern = FetchedDataSet::apply_overrides(
+            &overrides,
+            &DepictionCategory("wyvern".into()),
+            &map_entry,
+        )
+        .unwrap();
+        assert_eq!(wyvern.name.as_deref(), Some("global wikidata"));
+    }
+
+    #[test]
+    fn age_is_of_the_last_successful_update() {
+        let (_temp, mut fetched_data_set, answer) = stub_set(Config::default());
+        let time_source = FakeTimeSource::new(tai_from_system_time(SystemTime::now()));
+        let fetched_at = unix_secs_from_tai(time_source.now());
+
+        *answer.lock().unwrap() = Some(entries(0..3));
+        assert!(
+            fetched_data_set
+                .update_source(0, &time_source, true)
+                .is_some()
+        );
+
+        // The source is down an hour later
+        time_source.advance(Duration::from_secs(3600));
+        *answer.lock().unwrap() = None;
+        assert!(
+            fetched_data_set
+                .update_source(0, &time_source, true)
+                .is_none()
+        );
+
+        let status = fetched_data_set.entries[0]
+            .status()
+            .with_age_at(fetched_at + 7200);
+        assert_eq!(status.last_updated, Some(fetched_at + 3600));
+        assert_eq!(status.last_successful_update, Some(fetched_at));
+        assert_eq!(status.age_secs, Some(7200));
+        assert!(status.last_error.is_some());
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    fs::read_to_string,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail};
+use git2::{Cred, CredentialType, Oid, PushOptions, RemoteCallbacks, Repository};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+fn default_retries() -> u32 {
+    3
+}
+
+/**
+ * Where and how to publish the storage repository.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteConfig {
+    /// URL of the remote, as understood by git. Can be a path to a local (bare) repository.
+    pub url: String,
+    /// Branch to push to on the remote. Default to the name of the local branch.
+    pub branch: Option<String>,
+    /// Private SSH key to authenticate with
+    pub ssh_key: Option<PathBuf>,
+    /// File containing a token used as the password for HTTPS authentication
+    pub token_file: Option<PathBuf>,
+    /// If set, push at most once every this many seconds instead of after each commit
+    pub push_interval_secs: Option<u64>,
+    /// How many time to retry a failed push before giving up until the next one
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+#[derive(Default)]
+struct PushState {
+    last_pushed: Option<Oid>,
+    last_attempt: Option<Instant>,
+    /// Failed attempts since the last successful push
+    failures: u32,
+}
+
+/// How long to wait before retrying after the given number of failed attempts
+fn retry_delay(failures: u32) -> Duration {
+    Duration::from_secs(2u64.pow(failures.min(10)))
+}
+
+/**
+ * Push the storage repository to a remote, either after each commit or batched on a timer. A failed push is retried from [`Self::push_if_due`], so it never waits while the repository is locked.
+ */
+pub struct GitPusher {
+    config: RemoteConfig,
+    /// The repository is opened again for each push, so it does not need the lock of the storage one
+    repo_path: PathBuf,
+    state: Mutex<PushState>,
+}
+
+impl GitPusher {
+    pub fn new(config: RemoteConfig, repo_path: PathBuf) -> Self {
+        Self {
+            config,
+            repo_path,
+            state: Mutex::new(PushState::default()),
+        }
+    }
+
+    /// To be called after each commit. Push immediately unless pushes are batched.
+    pub fn after_commit(&self) {
+        if self.config.push_interval_secs.is_none() {
+            // There is something new to push, so the retries start again
+            self.state.lock().unwrap().failures = 0;
+            self.try_push();
+        }
+    }
+
+    /// To be called periodically. Push if pushes are batched and the interval elapsed, or to retry a failed push.
+    pub fn push_if_due(&self) {
+        if self.is_due(&self.state.lock().unwrap()) {
+            self.try_push();
+        }
+    }
+
+    fn is_due(&self, state: &PushState) -> bool {
+        let Some(last_attempt) = state.last_attempt else {
+            // Batched pushes start with one, immediate ones with the first commit
+            return self.config.push_interval_secs.is_some();
+        };
+        if state.failures > 0 && state.failures <= self.config.retries {
+            return last_attempt.elapsed() >= retry_delay(state.failures);
+        }
+        match self.config.push_interval_secs {
+            Some(interval) => last_attempt.elapsed() >= Duration::from_secs(interval),
+            // Gave up until the next commit
+            None => false,
+        }
+    }
+
+    fn try_push(&self) {
+        let repo = match Repository::open(&self.repo_path) {
+            Ok(repo) => repo,
+            Err(err) => {
+                error!(
+                    "Could not open the storage repository at {:?} to push it: {err}",
+                    self.repo_path
+                );
+                return;
+            }
+        };
+        let head = match repo.head().ok().and_then(|head| head.target()) {
+            Some(head) => head,
+            None => return, // Nothing commited yet
+        };
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.last_attempt = Some(Instant::now());
+            if state.last_pushed == Some(head) {
+                state.failures = 0;
+                return;
+            }
+        }
+
+        let result = self.push(&repo);
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(()) => {
+                info!("Pushed storage commit {} to {}", head, self.config.url);
+                state.last_pushed = Some(head);
+                state.failures = 0;
+            }
+            Err(err) => {
+                state.failures += 1;
+                if state.failures <= self.config.retries {
+                    warn!(
+                        "Push attempt {} of {} to {} failed, retrying in {} seconds: {:#}",
+                        state.failures,
+                        self.config.retries + 1,
+                        self.config.url,
+                        retry_delay(state.failures).as_secs(),
+                        err
+                    );
+                } else {
+                    error!(
+                        "Could not push the storage repository to {}, will retry {}: {:#}",
+                        self.config.url,
+                        if self.config.push_interval_secs.is_some() {
+                            "at the next interval"
+                        } else {
+                            "after the next commit"
+                        },
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    fn push(&self, repo: &Repository) -> anyhow::Result<()> {
+        let head = repo.head()?;
+        let local_ref = head
+            .name()
+            .context("The storage repository HEAD is not a valid reference name")?
+            .to_string();
+        let branch = match &self.config.branch {
+            Some(branch) => branch.clone(),
+            None => head
+                .shorthand()
+                .context("Could not determine the local branch name")?
+                .to_string(),
+        };
+
+        let mut credential_calls = 0;
+        let mut rejection = None;
+        {
+            let mut callbacks = RemoteCallbacks::new();
+            callbacks.credentials(|_url, username_from_url, allowed| {
+                // libgit2 keep asking while the credentials are refused
+                credential_calls += 1;
+                if credential_calls > 3 {
+                    return Err(git2::Error::from_str("Authentication failed"));
+                }
+                let username = username_from_url.unwrap_or("git");
+                if let Some(ssh_key) = &self.config.ssh_key
+                    && allowed.contains(CredentialType::SSH_KEY)
+                {
+                    return Cred::ssh_key(username, None, ssh_key, None);
+                }
+                if let Some(token_file) = &self.config.token_file
+                    && allowed.contains(CredentialType::USER_PASS_PLAINTEXT)
+                {
+                    let token = read_to_string(token_file).map_err(|err| {
+                        git2::Error::from_str(&format!(
+                            "Could not read token file {token_file:?}: {err}"
+                        ))
+                    })?;
+                    return Cred::userpass_plaintext(username, token.trim());
+                }
+                Cred::default()
+            });
+            callbacks.push_update_reference(|refname, status| {
+                if let Some(status) = status {
+                    rejection = Some(format!("{refname}: {status}"));
+                }
+                Ok(())
+            });
+
+            let mut push_options = PushOptions::new();
+            push_options.remote_callbacks(callbacks);
+
+            let mut remote = repo
+                .remote_anonymous(&self.config.url)
+                .with_context(|| format!("Creating remote for {}", self.config.url))?;
+            remote
+                .push(
+                    &[format!("{local_ref}:refs/heads/{branch}")],
+                    Some(&mut push_options),
+                )
+                .with_context(|| format!("Pushing to {}", self.config.url))?;
+        }
+
+        if let Some(rejection) = rejection {
+            bail!("The remote rejected the push ({rejection})");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir_all, write},
+        path::Path,
+    };
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::make_commit;
+
+    fn remote_config(url: &Path) -> RemoteConfig {
+        RemoteConfig {
+            url: url.to_string_lossy().to_string(),
+            branch: Some("main".into()),
+            ssh_key: None,
+            token_file: None,
+            push_interval_secs: None,
+            retries: 3,
+        }
+    }
+
+    fn commit_file(repo: &Repository, content: &str) -> Oid {
+        write(repo.workdir().unwrap().join("data.json"), content).unwrap();
+        make_commit(repo, Path::new("data.json"), "Update").unwrap();
+        repo.head().unwrap().target().unwrap()
+    }
+
+    fn remote_head(bare_path: &Path) -> Option<Oid> {
+        Repository::open_bare(bare_path)
+            .unwrap()
+            .find_reference("refs/heads/main")
+            .ok()
+            .and_then(|reference| reference.target())
+    }
+
+    #[test]
+    fn push_to_local_bare_repository() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let work_path = dir.join("work");
+        let bare_path = dir.join("remote.git");
+        create_dir_all(&work_path).unwrap();
+        let repo = Repository::init(&work_path).unwrap();
+        Repository::init_bare(&bare_path).unwrap();
+
+        let pusher = GitPusher::new(remote_config(&bare_path), work_path.clone());
+        let first = commit_file(&repo, "1");
+        pusher.after_commit();
+        assert_eq!(remote_head(&bare_path), Some(first));
+
+        let second = commit_file(&repo, "2");
+        pusher.after_commit();
+        assert_eq!(remote_head(&bare_path), Some(second));
+    }
+
+    #[test]
+    fn retry_failed_push_later() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let work_path = dir.join("work");
+        let bare_path = dir.join("remote.git");
+        create_dir_all(&work_path).unwrap();
+        let repo = Repository::init(&work_path).unwrap();
+
+        let pusher = GitPusher::new(remote_config(&bare_path), work_path.clone());
+        let head = commit_file(&repo, "1");
+        // The remote does not exist yet
+        pusher.after_commit();
+        assert_eq!(pusher.state.lock().unwrap().failures, 1);
+        // Not before the retry delay
+        pusher.push_if_due();
+        assert_eq!(pusher.state.lock().unwrap().failures, 1);
+
+        Repository::init_bare(&bare_path).unwrap();
+        pusher.state.lock().unwrap().last_attempt = Some(Instant::now() - retry_delay(1));
+        pusher.push_if_due();
+        assert_eq!(remote_head(&bare_path), Some(head));
+        assert_eq!(pusher.state.lock().unwrap().failures, 0);
+    }
+
+    #[test]
+    fn give_up_after_the_retries() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let work_path = dir.join("work");
+        create_dir_all(&work_path).unwrap();
+        let repo = Repository::init(&work_path).unwrap();
+
+        let mut config = remote_config(&dir.join("missing.git"));
+        config.retries = 1;
+        let pusher = GitPusher::new(config, work_path.clone());
+        commit_file(&repo, "1");
+        pusher.after_commit();
+        pusher.state.lock().unwrap().last_attempt = Some(Instant::now() - retry_delay(1));
+        pusher.push_if_due();
+        assert_eq!(pusher.state.lock().unwrap().failures, 2);
+        // Waits for the next commit
+        pusher.state.lock().unwrap().last_attempt = Some(Instant::now() - retry_delay(10));
+        assert!(!pusher.is_due(&pusher.state.lock().unwrap()));
+    }
+}
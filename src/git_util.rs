@@ -1,23 +1,22 @@
-use anyhow::bail;
-use git2::{ErrorCode, Repository, Signature};
+use anyhow::{Context, bail};
+use git2::{
+    Commit, ErrorCode, FileMode, Oid, Repository, Signature, Sort, Tree, build::TreeUpdateBuilder,
+};
 use log::info;
+use serde::Serialize;
 use std::path::Path;
 
 pub fn make_commit(repo: &Repository, path: &Path, message: &str) -> anyhow::Result<()> {
-    let old_tree = repo.head()?.peel_to_tree()?;
-
-    let mut index = repo.index()?;
-    index.add_path(path)?;
-    index.write()?;
-
-    let new_tree_ref = index.write_tree()?;
-    let new_tree = repo.find_tree(new_tree_ref)?;
-
-    if new_tree.id() == old_tree.id() {
-        info!("No change detected, not commiting.");
-        return Ok(());
-    }
+    make_commit_as(repo, path, message, None)
+}
 
+/// Commit the file at `path` (relative to the work directory), with `author` as the author if set (the committer being the bot). Only this file is commited, on top of HEAD, even if other changes are staged.
+pub fn make_commit_as(
+    repo: &Repository,
+    path: &Path,
+    message: &str,
+    author: Option<&str>,
+) -> anyhow::Result<()> {
     let head = match repo.head() {
         Ok(head) => Some(head.peel_to_commit()?),
         Err(err) => {
@@ -28,6 +27,36 @@ pub fn make_commit(repo: &Repository, path: &Path, message: &str) -> anyhow::Res
             }
         }
     };
+    let workdir = repo.workdir().context("The repository is bare")?;
+    let exists = workdir.join(path).exists();
+
+    let head_tree = match head.as_ref() {
+        Some(head) => head.tree()?,
+        None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+    };
+    let mut update = TreeUpdateBuilder::new();
+    if exists {
+        update.upsert(path, repo.blob_path(&workdir.join(path))?, FileMode::Blob);
+    } else {
+        update.remove(path);
+    }
+    let new_tree = repo.find_tree(update.create_updated(repo, &head_tree)?)?;
+
+    // Keep the index in sync for this file only
+    let mut index = repo.index()?;
+    if exists {
+        index.add_path(path)?;
+    } else {
+        index.remove_path(path)?;
+    }
+    index.write()?;
+
+    if let Some(head) = head.as_ref()
+        && new_tree.id() == head.tree_id()
+    {
+        info!("No change detected, not commiting.");
+        return Ok(());
+    }
 
     let mut parents = Vec::new();
     if let Some(head) = head.as_ref() {
@@ -35,10 +64,14 @@ pub fn make_commit(repo: &Repository, path: &Path, message: &str) -> anyhow::Res
     };
 
     let signature = Signature::now("depict_bot", "nomail@example.org")?;
+    let author = match author {
+        Some(author) => Signature::now(author, "nomail@example.org")?,
+        None => signature.clone(),
+    };
 
     repo.commit(
         Some("HEAD"),
-        &signature,
+        &author,
         &signature,
         message,
         &new_tree,
@@ -47,3 +80,153 @@ pub fn make_commit(repo: &Repository, path: &Path, message: &str) -> anyhow::Res
 
     Ok(())
 }
+
+/// A commit that modified a given file
+#[derive(Debug, Clone, Serialize)]
+pub struct FileCommit {
+    pub id: String,
+    /// Commit time, as an Unix timestamp
+    pub time: i64,
+    pub message: String,
+}
+
+fn blob_id_in_tree(tree: &Tree, path: &Path) -> Option<Oid> {
+    tree.get_path(path).ok().map(|entry| entry.id())
+}
+
+/// Find the commit with the given id (that can be abbreviated). Return `None` if there is no such commit.
+fn find_commit<'a>(repo: &'a Repository, commit_id: &str) -> anyhow::Result<Option<Commit<'a>>> {
+    if commit_id.is_empty() || !commit_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let object = match repo.revparse_single(commit_id) {
+        Ok(object) => object,
+        Err(err) if matches!(err.code(), ErrorCode::NotFound | ErrorCode::Ambiguous) => {
+            return Ok(None);
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("Finding commit {commit_id}"));
+        }
+    };
+    Ok(object.peel_to_commit().ok())
+}
+
+/// List the commits reachable from HEAD that modified the file at `path`, newest first, up to `limit` of them. With `before`, only the commits older than this one are listed, and `None` is returned if it does not exist.
+pub fn list_file_history(
+    repo: &Repository,
+    path: &Path,
+    before: Option<&str>,
+    limit: usize,
+) -> anyhow::Result<Option<Vec<FileCommit>>> {
+    let mut revwalk = repo.revwalk()?;
+    match before {
+        Some(before) => {
+            let Some(before) = find_commit(repo, before)? else {
+                return Ok(None);
+            };
+            for parent_id in before.parent_ids() {
+                revwalk.push(parent_id)?;
+            }
+        }
+        None => match revwalk.push_head() {
+            Ok(()) => (),
+            Err(err) if err.code() == ErrorCode::UnbornBranch => return Ok(Some(Vec::new())),
+            Err(err) => bail!(err),
+        },
+    }
+    revwalk.set_sorting(Sort::TIME)?;
+
+    let mut result = Vec::new();
+    for commit_id in revwalk {
+        if result.len() >= limit {
+            break;
+        }
+        let commit = repo.find_commit(commit_id?)?;
+        let blob_id = blob_id_in_tree(&commit.tree()?, path);
+        let parent_blob_id = match commit.parents().next() {
+            Some(parent) => blob_id_in_tree(&parent.tree()?, path),
+            None => None,
+        };
+        if blob_id != parent_blob_id {
+            result.push(FileCommit {
+                id: commit.id().to_string(),
+                time: commit.time().seconds(),
+                message: commit.message().unwrap_or_default().to_string(),
+            });
+        }
+    }
+    Ok(Some(result))
+}
+
+/// Read the content of the file at `path` as of the given commit (that can be abbreviated). Return `None` if it did not exist then, or if there is no such commit.
+pub fn read_file_at_commit(
+    repo: &Repository,
+    commit_id: &str,
+    path: &Path,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(commit) = find_commit(repo, commit_id)? else {
+        return Ok(None);
+    };
+    let Some(blob_id) = blob_id_in_tree(&commit.tree()?, path) else {
+        return Ok(None);
+    };
+    Ok(Some(repo.find_blob(blob_id)?.content().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn commits_only_the_given_file() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let repo = Repository::init(dir).unwrap();
+
+        write(dir.join("a.json"), "1").unwrap();
+        make_commit(&repo, Path::new("a.json"), "Add a").unwrap();
+        // Staged by someone else, and not to be commited with a.json
+        write(dir.join("b.json"), "1").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("b.json")).unwrap();
+        index.write().unwrap();
+
+        write(dir.join("a.json"), "2").unwrap();
+        make_commit_as(&repo, Path::new("a.json"), "Update a", Some("admin")).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.author().name_bytes(), b"admin");
+        assert_eq!(head.parent_count(), 1);
+        let tree = head.tree().unwrap();
+        assert!(tree.get_path(Path::new("b.json")).is_err());
+        let content = read_file_at_commit(&repo, &head.id().to_string(), Path::new("a.json"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(content, b"2");
+        // b.json is still staged
+        assert!(
+            repo.index()
+                .unwrap()
+                .get_path(Path::new("b.json"), 0)
+                .is_some()
+        );
+
+        let history = list_file_history(&repo, Path::new("a.json"), None, 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        let older = list_file_history(&repo, Path::new("a.json"), Some(&history[0].id), 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(older.len(), 1);
+        assert_eq!(older[0].id, history[1].id);
+        assert!(
+            list_file_history(&repo, Path::new("a.json"), Some("0000000"), 10)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
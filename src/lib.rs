@@ -1,9 +1,26 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, bail};
+
 mod map_entry;
 pub use map_entry::{MapEntry, MapEntryImageSource};
 
 mod storage;
+mod storage_migration;
 use serde::{Deserialize, Serialize};
-pub use storage::Storage;
+pub use storage::{Storage, StoredData, StoredDataPrivate, StoredDataPublic};
+
+mod storage_backend;
+pub use storage_backend::{
+    StorageBackend, StorageConfig, copy_storage, open_storage_backend,
+    open_storage_backend_read_only,
+};
+
+mod storage_json_git;
+pub use storage_json_git::JsonGitBackend;
+
+mod storage_sqlite;
+pub use storage_sqlite::SqliteBackend;
 
 mod fetch_data;
 pub use fetch_data::FetchData;
@@ -14,8 +31,20 @@ pub use fetch_data_openstreetmap::FetchDataOpenStreetMap;
 mod fetch_data_wikidata_sparql;
 pub use fetch_data_wikidata_sparql::FetchDataWikidataSparql;
 
+mod fetch_data_commons;
+pub use fetch_data_commons::{CommonsCategoryConfig, FetchDataCommonsCategory};
+
+mod fetch_data_file;
+pub use fetch_data_file::{FetchDataFile, FieldMapping, FileFormat, FileLocation};
+
+mod fetch_data_json_api;
+pub use fetch_data_json_api::{FetchDataJsonApi, Pagination, select_json_path};
+
+mod fetch_data_local;
+pub use fetch_data_local::{FetchDataLocal, LocalEntry};
+
 mod fetched_data_set;
-pub use fetched_data_set::FetchedDataSet;
+pub use fetched_data_set::{FetchedDataEntry, FetchedDataSet, PendingUpdate, UpdateOutcome};
 
 mod display_data_set;
 pub use display_data_set::{DisplayDataSet, DisplayDataSetEntry};
@@ -24,15 +53,116 @@ mod depict_app_data;
 pub use depict_app_data::DepictAppData;
 
 mod overrides;
-pub use overrides::{OverrideEntry, Overrides};
+pub use overrides::{OverrideEntry, OverrideSet, Overrides};
+
+mod override_report;
+pub use override_report::{OverrideProblem, OverrideProblemKind, OverrideReport};
+
+mod curator;
+pub use curator::{Curator, is_valid_image_name};
+
+mod commons_metadata;
+pub use commons_metadata::{
+    COMMONS_API_URL, CommonsAttribution, CommonsMetadataFetcher, FetchedAttributions,
+    commons_file_name, commons_title, parse_imageinfo_response,
+};
+
+mod thumbnail;
+pub use thumbnail::{RemoteThumbnails, ThumbnailConfig, Thumbnailer};
+
+mod static_export;
+pub use static_export::{Asset, export_static_site};
 
 mod git_util;
-pub use git_util::make_commit;
+pub use git_util::{
+    FileCommit, list_file_history, make_commit, make_commit_as, read_file_at_commit,
+};
+
+mod git_remote;
+pub use git_remote::{GitPusher, RemoteConfig};
+
+mod config;
+pub use config::{Config, MassDeletionGuard};
+
+mod source_config;
+pub use source_config::{SourceConfig, SourceKind};
+
+mod admin;
+pub use admin::{AdminCommand, AdminUser};
+
+mod source_status;
+pub use source_status::{PendingUpdateStatus, SourceStatus};
+
+mod entry_diff;
+pub use entry_diff::{EntryChange, EntryDiff};
+
+mod feed;
+pub use feed::{Feed, FeedItem, FeedItemKind};
+
+mod webhook;
+pub use webhook::{SIGNATURE_HEADER, WebhookNotifier, WebhookPayload, WebhookTarget, sign_payload};
+
+mod refresh_schedule;
+pub use refresh_schedule::{HourRange, RefreshSchedule, ScheduleConfig};
+
+mod time_source;
+pub use time_source::{FakeTimeSource, SystemTimeSource, TimeSource, unix_secs_from_tai};
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub enum ElementId {
     Osm(u64),
     Wikidata(String),
+    /// An entry of the local entries file
+    Local(String),
+    /// A file on Wikimedia Commons, by its name (without the `File:` prefix)
+    Commons(String),
+    /// An entry of an imported dataset, by the id it has in this dataset
+    External {
+        namespace: String,
+        id: String,
+    },
+}
+
+impl fmt::Display for ElementId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Osm(id) => write!(f, "osm:{id}"),
+            Self::Wikidata(id) => write!(f, "wikidata:{id}"),
+            Self::Local(id) => write!(f, "local:{id}"),
+            Self::Commons(id) => write!(f, "commons:{id}"),
+            Self::External { namespace, id } => write!(f, "external:{namespace}:{id}"),
+        }
+    }
+}
+
+impl FromStr for ElementId {
+    type Err = anyhow::Error;
+
+    /// Parse the format used by `Display`, like `osm:123` or `wikidata:Q42`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((kind, id)) = s.split_once(':') else {
+            bail!("Missing the kind of the element id {s:?}");
+        };
+        Ok(match kind {
+            "osm" => Self::Osm(
+                id.parse()
+                    .with_context(|| format!("Invalid OpenStreetMap id {id:?}"))?,
+            ),
+            "wikidata" => Self::Wikidata(id.to_string()),
+            "local" => Self::Local(id.to_string()),
+            "commons" => Self::Commons(id.to_string()),
+            "external" => {
+                let Some((namespace, id)) = id.split_once(':') else {
+                    bail!("Missing the namespace of the external id {id:?}");
+                };
+                Self::External {
+                    namespace: namespace.to_string(),
+                    id: id.to_string(),
+                }
+            }
+            _ => bail!("Unknown kind of element id {kind:?}"),
+        })
+    }
 }
 
 /**
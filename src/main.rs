@@ -1,32 +1,40 @@
 use std::{
-    fs::File,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::exit,
+    str::FromStr,
     thread::{sleep, spawn},
     time::Duration,
 };
 
-use actix_files::Files;
+use actix_files::{Files, NamedFile};
 use actix_web::{
-    App, Either, HttpResponse, HttpServer, Responder, get,
-    http::{StatusCode, header::ContentType},
+    App, Either, HttpRequest, HttpResponse, HttpServer, Responder, delete, get,
+    http::{
+        StatusCode,
+        header::{AUTHORIZATION, ContentType},
+    },
+    post, put,
     rt::task::spawn_blocking,
     web::{self, Data},
 };
-use clap::Parser;
+use anyhow::{Context, anyhow, bail};
+use clap::{Parser, Subcommand};
 use depiction_map::{
-    DepictAppData, DepictionCategory, FetchDataOpenStreetMap, FetchDataWikidataSparql,
-    FetchedDataSet, Overrides,
+    AdminCommand, AdminUser, Asset, Config, DepictAppData, DepictionCategory, ElementId,
+    FetchDataCommonsCategory, FetchDataLocal, FetchDataOpenStreetMap, FetchDataWikidataSparql,
+    FetchedDataSet, OverrideEntry, Overrides, RefreshSchedule, SystemTimeSource, TimeSource,
+    export_static_site, is_valid_image_name, unix_secs_from_tai,
 };
 use env_logger::Env;
 use log::{error, info};
 use mime_guess::from_path;
-use rust_embed::Embed;
+use serde::Deserialize;
+use serde_json::json;
+
+/// Maximum size of an uploaded image, in bytes
+const MAX_IMAGE_SIZE: usize = 20 * 1024 * 1024;
 
 // based on https://git.sr.ht/~pyrossh/rust-embed/tree/master/item/examples/actix.rs (for the static file delivery)
-#[derive(Embed)]
-#[folder = "static"]
-struct Asset;
 
 fn handle_embedded_file(path: &str) -> HttpResponse {
     match Asset::get(path) {
@@ -63,77 +71,688 @@ async fn get_depiction(
     }
 }
 
+/// Number of revisions listed by `/history/{source}` when no limit is given
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+const MAX_HISTORY_LIMIT: usize = 500;
+
+/// Query parameters of the history listing. To get the next page, `before` is the id of the last revision of the previous one.
+#[derive(Deserialize, Debug)]
+struct HistoryPage {
+    limit: Option<usize>,
+    before: Option<String>,
+}
+
+#[get("/history/{source}")]
+async fn get_history(
+    source: web::Path<String>,
+    page: web::Query<HistoryPage>,
+    data: Data<DepictAppData>,
+) -> HttpResponse {
+    let HistoryPage { limit, before } = page.into_inner();
+    let limit = limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+    let history = web::block(move || data.source_history(&source, before.as_deref(), limit)).await;
+    match history {
+        Ok(Ok(Some(history))) => HttpResponse::Ok().json(history),
+        Ok(Ok(None)) => HttpResponse::NotFound().body("source or revision does not exist"),
+        Ok(Err(err)) => {
+            error!("Could not read history: {err:?}");
+            HttpResponse::InternalServerError().body("could not read history")
+        }
+        Err(err) => {
+            error!("Could not read history: {err:?}");
+            HttpResponse::InternalServerError().body("could not read history")
+        }
+    }
+}
+
+#[get("/history/{source}/{commit}")]
+async fn get_history_entries(
+    path: web::Path<(String, String)>,
+    data: Data<DepictAppData>,
+) -> HttpResponse {
+    let (source, commit) = path.into_inner();
+    let entries = web::block(move || data.source_entries_at(&source, &commit)).await;
+    match entries {
+        Ok(Ok(Some(entries))) => HttpResponse::Ok().json(entries),
+        Ok(Ok(None)) => HttpResponse::NotFound().body("source does not exist at this commit"),
+        Ok(Err(err)) => {
+            error!("Could not read history: {err:?}");
+            HttpResponse::InternalServerError().body("could not read history")
+        }
+        Err(err) => {
+            error!("Could not read history: {err:?}");
+            HttpResponse::InternalServerError().body("could not read history")
+        }
+    }
+}
+
+#[get("/feed/{category}.atom")]
+async fn get_feed(
+    category: web::Path<String>,
+    data: Data<DepictAppData>,
+) -> Either<HttpResponse, (&'static str, StatusCode)> {
+    let category = DepictionCategory(category.into_inner());
+    match data.display_data_set.feeds.get(&category) {
+        Some(feed) => Either::Left(
+            HttpResponse::Ok()
+                .content_type("application/atom+xml")
+                .body(feed.load().as_ref().clone()),
+        ),
+        None => Either::Right(("category does not exist", StatusCode::NOT_FOUND)),
+    }
+}
+
+#[get("/diff/{source}")]
+async fn get_last_diff(source: web::Path<String>, data: Data<DepictAppData>) -> HttpResponse {
+    match data.display_data_set.last_diffs.get(source.as_str()) {
+        Some(last_diff) => HttpResponse::Ok().json(last_diff.load().as_deref()),
+        None => HttpResponse::NotFound().body("source does not exist"),
+    }
+}
+
+#[get("/status.json")]
+async fn get_status(data: Data<DepictAppData>) -> HttpResponse {
+    let now = unix_secs_from_tai(data.time_source.now());
+    let mut sources: Vec<_> = data
+        .display_data_set
+        .statuses
+        .values()
+        .map(|status| status.load().as_ref().clone().with_age_at(now))
+        .collect();
+    sources.sort_by(|a, b| a.name.cmp(&b.name));
+    HttpResponse::Ok().json(json!({
+        "clock_degraded": data.time_source.is_degraded(),
+        "offline": data.offline,
+        "sources": sources,
+    }))
+}
+
+#[get("/thumbnails/{key}.jpg")]
+async fn get_thumbnail(
+    key: web::Path<String>,
+    req: HttpRequest,
+    data: Data<DepictAppData>,
+) -> HttpResponse {
+    let thumbnail = web::block(move || data.extra.thumbnailer.get_thumbnail(&key)).await;
+    match thumbnail {
+        Ok(Ok(Some(path))) => match NamedFile::open(&path) {
+            Ok(file) => file.into_response(&req),
+            Err(err) => {
+                error!("Could not open the thumbnail at {path:?}: {err:?}");
+                HttpResponse::InternalServerError().body("could not read the thumbnail")
+            }
+        },
+        Ok(Ok(None)) => HttpResponse::NotFound().body("no such thumbnail"),
+        Ok(Err(err)) => {
+            error!("Could not make a thumbnail: {err:?}");
+            HttpResponse::BadGateway().body("could not make the thumbnail")
+        }
+        Err(err) => {
+            error!("Could not make a thumbnail: {err:?}");
+            HttpResponse::InternalServerError().body("could not make the thumbnail")
+        }
+    }
+}
+
+/// Return the admin performing this request, if it is correctly authenticated
+fn authenticate(req: &HttpRequest, data: &DepictAppData) -> Option<AdminUser> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let token = header.strip_prefix("Bearer ")?;
+    data.extra.config.find_admin(token).cloned()
+}
+
+/// In offline mode, nothing is written: the admin actions changing something are refused before doing anything
+fn refuse_if_offline(data: &DepictAppData) -> Option<HttpResponse> {
+    data.offline.then(|| {
+        HttpResponse::ServiceUnavailable()
+            .body("the server is offline, the admin actions are disabled")
+    })
+}
+
+#[get("/overrides/report.json")]
+async fn get_override_report(data: Data<DepictAppData>) -> HttpResponse {
+    HttpResponse::Ok().json(data.display_data_set.override_report.load().as_ref())
+}
+
+#[post("/admin/sources/{source}/accept-update")]
+async fn accept_pending_update(
+    source: web::Path<String>,
+    req: HttpRequest,
+    data: Data<DepictAppData>,
+) -> HttpResponse {
+    let Some(admin) = authenticate(&req, &data) else {
+        return HttpResponse::Unauthorized().body("invalid or missing admin token");
+    };
+    if let Some(response) = refuse_if_offline(&data) {
+        return response;
+    }
+    let Some(status) = data.display_data_set.statuses.get(source.as_str()) else {
+        return HttpResponse::NotFound().body("source does not exist");
+    };
+    if status.load().pending_update.is_none() {
+        return HttpResponse::Conflict().body("there is no pending update for this source");
+    }
+    info!("{} accepted the pending update of {:?}", admin.name, source);
+    match data.admin_commands.send(AdminCommand::AcceptPendingUpdate {
+        source: source.into_inner(),
+    }) {
+        Ok(()) => HttpResponse::Accepted().body("the update will be applied shortly"),
+        Err(_) => HttpResponse::InternalServerError().body("the update thread is not running"),
+    }
+}
+
+#[post("/admin/reload-overrides")]
+async fn reload_overrides(req: HttpRequest, data: Data<DepictAppData>) -> HttpResponse {
+    let Some(admin) = authenticate(&req, &data) else {
+        return HttpResponse::Unauthorized().body("invalid or missing admin token");
+    };
+    if let Some(response) = refuse_if_offline(&data) {
+        return response;
+    }
+    // Check it first, to report the error to the admin
+    let overrides_path = data.extra.overrides_path.clone();
+    match web::block(move || Overrides::load(&overrides_path)).await {
+        Ok(Ok(_)) => (),
+        Ok(Err(err)) => return HttpResponse::BadRequest().body(format!("{err:#}")),
+        Err(err) => {
+            error!("Could not check the overrides: {err:?}");
+            return HttpResponse::InternalServerError().body("could not check the overrides");
+        }
+    }
+    info!("{} asked to reload the overrides", admin.name);
+    match data.admin_commands.send(AdminCommand::ReloadOverrides) {
+        Ok(()) => HttpResponse::Accepted().body("the overrides will be reloaded shortly"),
+        Err(_) => HttpResponse::InternalServerError().body("the update thread is not running"),
+    }
+}
+
+#[actix_web::get("/admin")]
+async fn admin_page() -> impl Responder {
+    handle_embedded_file("admin.html")
+}
+
+#[get("/admin/overrides.json")]
+async fn get_admin_overrides(req: HttpRequest, data: Data<DepictAppData>) -> HttpResponse {
+    if authenticate(&req, &data).is_none() {
+        return HttpResponse::Unauthorized().body("invalid or missing admin token");
+    }
+    HttpResponse::Ok().json(data.extra.overrides.load().as_ref())
+}
+
+/// Query parameters of the override edition. Without a category, the global override is edited.
+#[derive(Deserialize, Debug)]
+struct OverrideScope {
+    category: Option<DepictionCategory>,
+}
+
+/// Replace the override of the element (or remove it if `None`), then ask the update thread to reload them
+async fn edit_override(
+    element_id: &str,
+    scope: OverrideScope,
+    override_entry: Option<OverrideEntry>,
+    req: &HttpRequest,
+    data: Data<DepictAppData>,
+) -> HttpResponse {
+    let Some(admin) = authenticate(req, &data) else {
+        return HttpResponse::Unauthorized().body("invalid or missing admin token");
+    };
+    if let Some(response) = refuse_if_offline(&data) {
+        return response;
+    }
+    let element_id = match ElementId::from_str(element_id) {
+        Ok(element_id) => element_id,
+        Err(err) => return HttpResponse::BadRequest().body(format!("{err:#}")),
+    };
+    let data_cloned = data.clone();
+    let result = web::block(move || match &data_cloned.curator {
+        Some(curator) => curator.set_override(
+            &element_id,
+            scope.category.as_ref(),
+            override_entry,
+            &admin.name,
+        ),
+        None => Err(anyhow!("There is no admin to edit the overrides")),
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => (),
+        Ok(Err(err)) => {
+            error!("Could not edit the overrides: {err:?}");
+            return HttpResponse::InternalServerError().body(format!("{err:#}"));
+        }
+        Err(err) => {
+            error!("Could not edit the overrides: {err:?}");
+            return HttpResponse::InternalServerError().body("could not edit the overrides");
+        }
+    }
+    match data.admin_commands.send(AdminCommand::ReloadOverrides) {
+        Ok(()) => HttpResponse::Ok().body("saved, the map will be updated shortly"),
+        Err(_) => HttpResponse::InternalServerError().body("the update thread is not running"),
+    }
+}
+
+#[put("/admin/overrides/{element_id}")]
+async fn put_override(
+    element_id: web::Path<String>,
+    scope: web::Query<OverrideScope>,
+    override_entry: web::Json<OverrideEntry>,
+    req: HttpRequest,
+    data: Data<DepictAppData>,
+) -> HttpResponse {
+    edit_override(
+        &element_id,
+        scope.into_inner(),
+        Some(override_entry.into_inner()),
+        &req,
+        data,
+    )
+    .await
+}
+
+#[delete("/admin/overrides/{element_id}")]
+async fn delete_override(
+    element_id: web::Path<String>,
+    scope: web::Query<OverrideScope>,
+    req: HttpRequest,
+    data: Data<DepictAppData>,
+) -> HttpResponse {
+    edit_override(&element_id, scope.into_inner(), None, &req, data).await
+}
+
+#[put("/admin/images/{name}")]
+async fn put_image(
+    name: web::Path<String>,
+    body: web::Bytes,
+    req: HttpRequest,
+    data: Data<DepictAppData>,
+) -> HttpResponse {
+    let Some(admin) = authenticate(&req, &data) else {
+        return HttpResponse::Unauthorized().body("invalid or missing admin token");
+    };
+    if let Some(response) = refuse_if_offline(&data) {
+        return response;
+    }
+    let name = name.into_inner();
+    if !is_valid_image_name(&name) {
+        return HttpResponse::BadRequest().body(
+            "the name should only contain letters, digits, '.', '-' and '_', and be an image",
+        );
+    }
+    let result = web::block(move || match &data.curator {
+        Some(curator) if curator.image_exists(&name) => Ok(false),
+        Some(curator) => curator.add_image(&name, &body, &admin.name).map(|()| true),
+        None => Err(anyhow!("There is no admin to upload images")),
+    })
+    .await;
+    match result {
+        Ok(Ok(true)) => HttpResponse::Created().body("image uploaded"),
+        Ok(Ok(false)) => HttpResponse::Conflict().body("there is already an image with this name"),
+        Ok(Err(err)) => {
+            error!("Could not upload an image: {err:?}");
+            HttpResponse::InternalServerError().body(format!("{err:#}"))
+        }
+        Err(err) => {
+            error!("Could not upload an image: {err:?}");
+            HttpResponse::InternalServerError().body("could not upload the image")
+        }
+    }
+}
+
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_HOST: &str = "127.0.0.1";
+
 #[derive(Parser, Debug)]
 pub struct Opts {
     ressource_path: PathBuf,
     save_path: PathBuf,
-    #[arg(long, default_value = "8080")]
-    port: u16,
-    #[arg(long, default_value = "127.0.0.1")]
-    host: String,
+    /// What to do. Without subcommand, the server is started.
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Deprecated, same as `serve --port`
+    #[arg(long)]
+    port: Option<u16>,
+    /// Deprecated, same as `serve --host`
+    #[arg(long)]
+    host: Option<String>,
 }
 
-#[actix_web::main]
-async fn main() {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+impl Opts {
+    /// The command to run, given by the subcommand or by the options used before there were subcommands
+    fn take_command(&mut self) -> anyhow::Result<Command> {
+        let uses_old_options = self.port.is_some() || self.host.is_some();
+        match self.command.take() {
+            Some(_) if uses_old_options => bail!(
+                "--port and --host can’t be used with a subcommand, use those of the subcommand instead"
+            ),
+            Some(command) => Ok(command),
+            None => Ok(Command::Serve {
+                port: self.port.unwrap_or(DEFAULT_PORT),
+                host: self.host.take().unwrap_or_else(|| DEFAULT_HOST.to_string()),
+                offline: false,
+            }),
+        }
+    }
+}
 
-    let opts = Opts::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve the map, updating the sources in the background
+    Serve {
+        #[arg(long, default_value_t = DEFAULT_PORT)]
+        port: u16,
+        #[arg(long, default_value = DEFAULT_HOST)]
+        host: String,
+        /// Only serve the stored data, without ever fetching it (nor reloading the overrides)
+        #[arg(long)]
+        offline: bool,
+    },
+    /// Update once the sources that are due and exit (with code 1 if an update failed), for example from cron
+    Fetch {
+        /// Only update this source, identified by its storage name (like osm_dragon.json), even if it is not due
+        #[arg(long)]
+        source: Option<String>,
+    },
+    /// Apply the update of this source held back by the mass-deletion guard (at the last `fetch` or while serving), and exit (with code 1 if there is none)
+    AcceptUpdate {
+        /// The storage name of the source, like osm_dragon.json
+        source: String,
+    },
+    /// Write the site with the current data in this directory, to be served by any static file server. The sources that are due are updated first.
+    Export {
+        output: PathBuf,
+        /// Only use the stored data, without fetching anything
+        #[arg(long)]
+        no_fetch: bool,
+    },
+    /// Check config.json, overrides.json and local_entries.json, print the problems and exit (with code 1 if there are any)
+    ValidateConfig,
+    /// Check the overrides against the stored entries and the images, print the problems as JSON and exit (with code 1 if there are any)
+    CheckOverrides,
+    /// Print the status of every source as JSON, from the stored data
+    ShowStatus,
+}
 
-    let app_data = spawn_blocking(move || {
-        let overrides_path = opts.ressource_path.join("overrides.json");
-        let overrides_file = File::open(&overrides_path).unwrap();
-        let overrides: Overrides = serde_json::from_reader(overrides_file).unwrap();
+/// Load the configuration, and the stored data of every source. If `read_only` is set, nothing is ever written to the save folder (so nothing can be updated).
+fn load_fetched_data_set(
+    ressource_path: &Path,
+    save_path: PathBuf,
+    read_only: bool,
+) -> anyhow::Result<FetchedDataSet> {
+    let config = Config::load(&ressource_path.join("config.json"))?;
 
-        let mut fetched_data_set = FetchedDataSet::new(opts.save_path, overrides).unwrap();
+    let mut fetched_data_set = if read_only {
+        FetchedDataSet::new_read_only(save_path, ressource_path, config)?
+    } else {
+        FetchedDataSet::new(save_path, ressource_path, config)?
+    };
 
-        let osm_dragon_fetcher = FetchDataOpenStreetMap {
-            api: FetchDataOpenStreetMap::default_api(),
-            query: "[out:json][timeout:30];
+    let osm_dragon_fetcher = FetchDataOpenStreetMap {
+        api: FetchDataOpenStreetMap::default_api(),
+        query: "[out:json][timeout:30];
 
-            nwr[\"artwork_subject\"~\"dragon\"][\"artwork_subject\"!~\"dragonfl\"]; // but what about both depiction of dragon and dragonfly? Does not appear to exist for now, but that really show that OSM data model is innapropriate for that kind of use
-            // idea: just get all dragon and then post-process locally
+        nwr[\"artwork_subject\"~\"dragon\"][\"artwork_subject\"!~\"dragonfl\"]; // but what about both depiction of dragon and dragonfly? Does not appear to exist for now, but that really show that OSM data model is innapropriate for that kind of use
+        // idea: just get all dragon and then post-process locally
 
-            out geom;".to_string(),
-            title: "Dragons from OpenStreetMap".to_string(),
-        };
+        out geom;".to_string(),
+        title: "Dragons from OpenStreetMap".to_string(),
+        schedule: fetched_data_set.extra.config.schedule_for(
+            "osm_dragon.json",
+            RefreshSchedule::every(Duration::from_secs(3600 * 3)),
+        )?,
+    };
+
+    fetched_data_set.add_fetcher(
+        osm_dragon_fetcher,
+        vec![DepictionCategory::dragon()],
+        "osm_dragon.json".into(),
+    )?;
+
+    let wikidata_dragon_fetcher = FetchDataWikidataSparql::new(
+        include_str!("../wikidata_dragon_query.sparql").to_string(),
+        "dragon from wikidata".into(),
+        fetched_data_set.extra.config.schedule_for(
+            "wikidata_dragon.json",
+            RefreshSchedule::every(Duration::from_secs(3600 * 3)),
+        )?,
+    )?;
+    fetched_data_set.add_fetcher(
+        wikidata_dragon_fetcher,
+        vec![DepictionCategory::dragon()],
+        "wikidata_dragon.json".into(),
+    )?;
 
+    if let Some(commons_dragon) = fetched_data_set.extra.config.commons_dragon.clone() {
+        let commons_dragon_fetcher = FetchDataCommonsCategory::new(
+            commons_dragon.category,
+            commons_dragon.max_depth,
+            "Geotagged dragons from Wikimedia Commons".into(),
+            fetched_data_set.extra.config.schedule_for(
+                "commons_dragon.json",
+                RefreshSchedule::every(Duration::from_secs(3600 * 24)).with_quiet_hours(8, 20)?,
+            )?,
+        )
+        .with_depicts(commons_dragon.depicts);
         fetched_data_set.add_fetcher(
-            osm_dragon_fetcher,
+            commons_dragon_fetcher,
             vec![DepictionCategory::dragon()],
-            "osm_dragon.json".into(),
-        ).unwrap();
+            "commons_dragon.json".into(),
+        )?;
+    }
 
-        let wikidata_dragon_fetcher = FetchDataWikidataSparql::new(include_str!("../wikidata_dragon_query.sparql").to_string(), "dragon from wikidata".into()).unwrap();
+    let local_entries_path = ressource_path.join("local_entries.json");
+    if local_entries_path.exists() {
+        let schedule = fetched_data_set
+            .extra
+            .config
+            .schedule_for("local_dragon.json", local_dragon_schedule())?;
         fetched_data_set.add_fetcher(
-            wikidata_dragon_fetcher,
+            local_dragon_fetcher(local_entries_path, schedule),
             vec![DepictionCategory::dragon()],
-            "wikidata_dragon.json".into(),
-        ).unwrap();
-
-        let mut app_data = DepictAppData::new(&fetched_data_set, opts.ressource_path.clone()).unwrap();
-        let handle = app_data.start_update_thread(fetched_data_set);
-        spawn(move || loop {
-            sleep(Duration::from_secs(2));
-            if handle.is_finished() {
-                error!("Background update thread finished while it should never stop. Exiting.");
-                exit(200);
+            "local_dragon.json".into(),
+        )?;
+    }
+
+    for source in fetched_data_set.extra.config.sources.clone() {
+        source
+            .add_to(&mut fetched_data_set, ressource_path)
+            .with_context(|| format!("Setting up the source {:?}", source.namespace))?;
+    }
+
+    Ok(fetched_data_set)
+}
+
+fn local_dragon_schedule() -> RefreshSchedule {
+    RefreshSchedule::every(Duration::from_secs(3600 * 24))
+}
+
+fn local_dragon_fetcher(local_entries_path: PathBuf, schedule: RefreshSchedule) -> FetchDataLocal {
+    FetchDataLocal::new(
+        local_entries_path,
+        "Manually added dragons".into(),
+        schedule,
+    )
+}
+
+/// Check the configuration files, without opening the storage. Print the problems and return the exit code.
+fn validate_config(ressource_path: &Path) -> i32 {
+    let mut problems = Vec::new();
+    match Config::load(&ressource_path.join("config.json")) {
+        Ok(config) => problems.extend(config.problems()),
+        Err(err) => problems.push(format!("{err:#}")),
+    }
+    if let Err(err) = Overrides::load(&ressource_path.join("overrides.json")) {
+        problems.push(format!("{err:#}"));
+    }
+    let local_entries_path = ressource_path.join("local_entries.json");
+    if local_entries_path.exists()
+        && let Err(err) =
+            local_dragon_fetcher(local_entries_path, local_dragon_schedule()).read_entries()
+    {
+        problems.push(format!("{err:#}"));
+    }
+    for problem in &problems {
+        println!("{problem}");
+    }
+    if problems.is_empty() {
+        println!("The configuration is valid");
+    }
+    if problems.is_empty() { 0 } else { 1 }
+}
+
+/// Run a command other than serve and validate-config, returning the exit code
+fn run_command(command: Command, mut fetched_data_set: FetchedDataSet) -> anyhow::Result<i32> {
+    let time_source = SystemTimeSource::default();
+    match command {
+        Command::Serve { .. } | Command::ValidateConfig => {
+            unreachable!("Not run with the loaded data")
+        }
+        Command::Fetch { source: None } => {
+            let success = fetched_data_set.update_due_sources(&time_source);
+            Ok(if success { 0 } else { 1 })
+        }
+        Command::Fetch {
+            source: Some(source),
+        } => {
+            let Some(entry_pos) = fetched_data_set.find_source(&source) else {
+                error!("There is no source named {source:?}");
+                return Ok(1);
+            };
+            let outcome = fetched_data_set.update_source(entry_pos, &time_source, true);
+            Ok(if outcome.is_some() { 0 } else { 1 })
+        }
+        Command::AcceptUpdate { source } => {
+            let Some(entry_pos) = fetched_data_set.find_source(&source) else {
+                error!("There is no source named {source:?}");
+                return Ok(1);
+            };
+            if !fetched_data_set.accept_pending_update(entry_pos, &time_source)? {
+                error!("There is no update to accept for {source:?}");
+                return Ok(1);
             }
-        });
-        Data::new(app_data)
-    }).await.unwrap();
+            Ok(0)
+        }
+        Command::Export { output, no_fetch } => {
+            if !no_fetch {
+                fetched_data_set.update_due_sources(&time_source);
+            }
+            export_static_site(&fetched_data_set, &output)?;
+            info!("Exported the site to {output:?}");
+            Ok(0)
+        }
+        Command::CheckOverrides => {
+            let report = fetched_data_set.check_overrides();
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            Ok(if report.is_empty() { 0 } else { 1 })
+        }
+        Command::ShowStatus => {
+            let now = unix_secs_from_tai(time_source.now());
+            let statuses: Vec<_> = fetched_data_set
+                .entries
+                .iter()
+                .map(|entry| entry.status().with_age_at(now))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&statuses)?);
+            Ok(0)
+        }
+    }
+}
+
+#[actix_web::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let mut opts = Opts::parse();
+    let command = match opts.take_command() {
+        Ok(command) => command,
+        Err(err) => {
+            error!("{err:#}");
+            exit(2);
+        }
+    };
+
+    let (app_data, host, port) = spawn_blocking(move || {
+        if let Command::ValidateConfig = command {
+            exit(validate_config(&opts.ressource_path));
+        }
+        // Those only look at the stored data
+        let read_only = matches!(
+            command,
+            Command::ShowStatus | Command::CheckOverrides | Command::Serve { offline: true, .. }
+        );
+        let fetched_data_set =
+            match load_fetched_data_set(&opts.ressource_path, opts.save_path, read_only) {
+                Ok(fetched_data_set) => fetched_data_set,
+                Err(err) => {
+                    error!("Could not load the configuration and the stored data: {err:?}");
+                    exit(1);
+                }
+            };
+
+        let (host, port, offline) = match command {
+            Command::Serve {
+                host,
+                port,
+                offline,
+            } => (host, port, offline),
+            command => match run_command(command, fetched_data_set) {
+                Ok(code) => exit(code),
+                Err(err) => {
+                    error!("{err:?}");
+                    exit(1);
+                }
+            },
+        };
+
+        let mut app_data =
+            DepictAppData::new(&fetched_data_set, opts.ressource_path.clone(), offline).unwrap();
+        if !offline {
+            let handle = app_data.start_update_thread(fetched_data_set);
+            spawn(move || {
+                loop {
+                    sleep(Duration::from_secs(2));
+                    if handle.is_finished() {
+                        error!(
+                            "Background update thread finished while it should never stop. Exiting."
+                        );
+                        exit(200);
+                    }
+                }
+            });
+        }
+        (Data::new(app_data), host, port)
+    })
+    .await
+    .unwrap();
 
-    info!("Starting server on {}:{}", opts.host, opts.port);
+    info!("Starting server on {}:{}", host, port);
 
     HttpServer::new(move || {
         let images_path = app_data.ressource_path.join("images");
         App::new()
+            .app_data(web::PayloadConfig::new(MAX_IMAGE_SIZE))
             .app_data(app_data.clone())
             .service(get_depiction)
+            .service(get_history)
+            .service(get_history_entries)
+            .service(get_last_diff)
+            .service(get_feed)
+            .service(get_status)
+            .service(get_override_report)
+            .service(get_thumbnail)
+            .service(accept_pending_update)
+            .service(reload_overrides)
+            .service(admin_page)
+            .service(get_admin_overrides)
+            .service(put_override)
+            .service(delete_override)
+            .service(put_image)
             .service(static_ressources)
             .service(index)
             .service(Files::new("/images", images_path).show_files_listing())
     })
-    .bind((opts.host, opts.port))
+    .bind((host, port))
     .unwrap()
     .run()
     .await
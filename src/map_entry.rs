@@ -10,6 +10,12 @@ pub struct MapEntryImageSource {
     pub url: String,
     pub credit_url: Option<String>,
     pub credit_text: Option<String>,
+    /// The page of the license of the image, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license_url: Option<String>,
+    /// A reduced version of the image, only set on the displayed entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_url: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, PartialOrd, Eq, Ord)]
@@ -26,6 +32,11 @@ pub struct MapEntry {
 }
 
 impl MapEntry {
+    /// The id used to recognise this entry between two updates
+    pub fn primary_id(&self) -> Option<&ElementId> {
+        self.element_ids.first()
+    }
+
     /// Transform some value once this map entry is at its otherwise definitive state.
     pub fn post_process(&mut self) {
         if let Some(image) = &mut self.image {
@@ -0,0 +1,211 @@
+use std::{collections::HashSet, fmt, path::Path};
+
+use safe_join::SafeJoin;
+use serde::Serialize;
+use url::Url;
+
+use crate::{DepictionCategory, ElementId, Overrides};
+
+/// Something likely wrong in an override
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OverrideProblemKind {
+    /// No source has an entry with this id (anymore)
+    Orphaned,
+    /// The `local_image` file does not exist in the images directory
+    MissingImage {
+        file: String,
+    },
+    MalformedUrl {
+        url: String,
+        error: String,
+    },
+    /// No field is set, so the override changes nothing
+    Empty,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OverrideProblem {
+    pub element_id: ElementId,
+    /// The category the override is restricted to, if any
+    pub category: Option<DepictionCategory>,
+    #[serde(flatten)]
+    pub kind: OverrideProblemKind,
+}
+
+impl fmt::Display for OverrideProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.element_id)?;
+        if let Some(category) = &self.category {
+            write!(f, " (in {})", category.0)?;
+        }
+        match &self.kind {
+            OverrideProblemKind::Orphaned => write!(f, ": no source has an entry with this id"),
+            OverrideProblemKind::MissingImage { file } => {
+                write!(f, ": the image {file:?} does not exist")
+            }
+            OverrideProblemKind::MalformedUrl { url, error } => {
+                write!(f, ": invalid url {url:?}: {error}")
+            }
+            OverrideProblemKind::Empty => write!(f, ": the override changes nothing"),
+        }
+    }
+}
+
+/**
+ * The result of checking the overrides against the fetched entries and the images directory.
+ */
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OverrideReport {
+    pub problems: Vec<OverrideProblem>,
+}
+
+impl OverrideReport {
+    /// `known_ids` are all the ids of the stored entries. An override of an id not in it is reported as orphaned.
+    pub fn check(
+        overrides: &Overrides,
+        known_ids: &HashSet<&ElementId>,
+        images_path: &Path,
+    ) -> Self {
+        let mut problems = Vec::new();
+        for (category, element_id, override_entry) in overrides.iter() {
+            let mut problem = |kind| {
+                problems.push(OverrideProblem {
+                    element_id: element_id.clone(),
+                    category: category.cloned(),
+                    kind,
+                })
+            };
+
+            if !known_ids.contains(&element_id) {
+                problem(OverrideProblemKind::Orphaned);
+            }
+
+            if override_entry.is_empty() {
+                problem(OverrideProblemKind::Empty);
+            }
+
+            if let Some(local_image) = &override_entry.local_image {
+                let exists = images_path
+                    .safe_join(local_image)
+                    .map(|path| path.is_file())
+                    .unwrap_or(false);
+                if !exists {
+                    problem(OverrideProblemKind::MissingImage {
+                        file: local_image.clone(),
+                    });
+                }
+            }
+
+            if let Some(url) = &override_entry.image_source_url
+                && let Err(err) = Url::parse(url)
+            {
+                problem(OverrideProblemKind::MalformedUrl {
+                    url: url.clone(),
+                    error: err.to_string(),
+                });
+            }
+        }
+        problems.sort_by(|a, b| (&a.element_id, &a.category).cmp(&(&b.element_id, &b.category)));
+        Self { problems }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn check(overrides: serde_json::Value, known_ids: &[ElementId]) -> OverrideReport {
+        let temp = tempdir().unwrap();
+        write(temp.path().join("dragon.png"), "").unwrap();
+        let overrides: Overrides = serde_json::from_value(overrides).unwrap();
+        OverrideReport::check(&overrides, &known_ids.iter().collect(), temp.path())
+    }
+
+    fn messages(report: &OverrideReport) -> Vec<String> {
+        report
+            .problems
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn valid_overrides() {
+        let report = check(
+            json!({
+                "osm": { "1": { "local_image": "dragon.png", "image_source_url": "https://example.org/" } },
+                "categories": { "dragon": { "wikidata": { "Q2": { "hidden": true } } } },
+            }),
+            &[ElementId::Osm(1), ElementId::Wikidata("Q2".into())],
+        );
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn reports_unknown_ids() {
+        let report = check(
+            json!({
+                "osm": { "1": { "name": "known" }, "3": { "name": "unknown" } },
+                "categories": { "dragon": { "osm": { "4": { "hidden": true } } } },
+            }),
+            &[ElementId::Osm(1), ElementId::Osm(2)],
+        );
+        assert_eq!(
+            messages(&report),
+            [
+                "osm:3: no source has an entry with this id",
+                "osm:4 (in dragon): no source has an entry with this id",
+            ]
+        );
+        assert!(matches!(
+            report.problems[0].kind,
+            OverrideProblemKind::Orphaned
+        ));
+        assert_eq!(
+            serde_json::to_value(&report.problems[1]).unwrap(),
+            json!({ "element_id": { "Osm": 4 }, "category": "dragon", "kind": "orphaned" })
+        );
+    }
+
+    #[test]
+    fn reports_overrides_changing_nothing() {
+        let report = check(
+            json!({ "osm": { "1": {}, "2": { "hidden": false } } }),
+            &[ElementId::Osm(1), ElementId::Osm(2)],
+        );
+        assert_eq!(
+            messages(&report),
+            [
+                "osm:1: the override changes nothing",
+                "osm:2: the override changes nothing",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_images_and_malformed_urls() {
+        let report = check(
+            json!({ "osm": { "1": { "local_image": "wyvern.png", "image_source_url": "not an url" } } }),
+            &[ElementId::Osm(1)],
+        );
+        assert_eq!(report.problems.len(), 2);
+        assert_eq!(
+            report.problems[0].to_string(),
+            "osm:1: the image \"wyvern.png\" does not exist"
+        );
+        assert!(matches!(
+            report.problems[1].kind,
+            OverrideProblemKind::MalformedUrl { .. }
+        ));
+    }
+}
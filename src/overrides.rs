@@ -1,23 +1,78 @@
-use std::collections::HashMap;
+use std::{collections::BTreeMap, fs::File, path::Path};
 
-use serde::{Deserialize, Serialize};
+use anyhow::Context;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Deserializer, Serialize, de::Error};
+use serde_json::{Map, Value};
 
-use crate::{ElementId, MapEntry, MapEntryImageSource};
+use crate::{DepictionCategory, ElementId, MapEntry, MapEntryImageSource};
 
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/**
+ * Manual corrections of a fetched entry. Every field is optional, and only replace the fetched value when set.
+ */
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OverrideEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub local_image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub image_source_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub image_source_text: Option<String>,
+    /// Corrected position, as `[latitude, longitude]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pos: Option<(OrderedFloat<f64>, OrderedFloat<f64>)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_in_exhibit: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nature: Option<String>,
+    /// Do not display this entry at all, for false positives
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub hidden: bool,
 }
 
 impl OverrideEntry {
+    /// True if no field is set, so that applying it changes nothing
+    pub fn is_empty(&self) -> bool {
+        self.local_image.is_none()
+            && self.image_source_url.is_none()
+            && self.image_source_text.is_none()
+            && self.pos.is_none()
+            && self.name.is_none()
+            && self.is_in_exhibit.is_none()
+            && self.nature.is_none()
+            && !self.hidden
+    }
+
     pub fn override_map_entry(&self, map_entry: &mut MapEntry) {
+        if let Some(pos) = self.pos {
+            map_entry.pos = Some(pos);
+        }
+
+        if let Some(name) = &self.name {
+            map_entry.name = Some(name.clone());
+        }
+
+        if let Some(is_in_exhibit) = self.is_in_exhibit {
+            map_entry.is_in_exhibit = is_in_exhibit;
+        }
+
+        if let Some(nature) = &self.nature {
+            map_entry.nature = Some(nature.clone());
+        }
+
         if let Some(local_image) = &self.local_image {
             map_entry.image = Some(MapEntryImageSource {
                 url: format!("/images/{}", local_image.clone()),
                 credit_url: None,
                 credit_text: None,
+                license_url: None,
+                thumbnail_url: None,
             })
         }
 
@@ -33,17 +88,395 @@ impl OverrideEntry {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Overrides {
-    pub osm: HashMap<u64, OverrideEntry>,
-    pub wikidata: HashMap<String, OverrideEntry>,
+/// Overrides by element id, for each kind of id
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OverrideSet {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub osm: BTreeMap<u64, OverrideEntry>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub wikidata: BTreeMap<String, OverrideEntry>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub local: BTreeMap<String, OverrideEntry>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub commons: BTreeMap<String, OverrideEntry>,
+    /// Overrides of the imported datasets, by namespace then id
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub external: BTreeMap<String, BTreeMap<String, OverrideEntry>>,
 }
 
-impl Overrides {
+impl OverrideSet {
+    /// Iterate over every override, with the id of the entry it applies to
+    pub fn iter(&self) -> impl Iterator<Item = (ElementId, &OverrideEntry)> {
+        let osm = self
+            .osm
+            .iter()
+            .map(|(id, entry)| (ElementId::Osm(*id), entry));
+        let wikidata = self
+            .wikidata
+            .iter()
+            .map(|(id, entry)| (ElementId::Wikidata(id.clone()), entry));
+        let local = self
+            .local
+            .iter()
+            .map(|(id, entry)| (ElementId::Local(id.clone()), entry));
+        let commons = self
+            .commons
+            .iter()
+            .map(|(id, entry)| (ElementId::Commons(id.clone()), entry));
+        let external = self.external.iter().flat_map(|(namespace, entries)| {
+            entries.iter().map(|(id, entry)| {
+                (
+                    ElementId::External {
+                        namespace: namespace.clone(),
+                        id: id.clone(),
+                    },
+                    entry,
+                )
+            })
+        });
+        osm.chain(wikidata)
+            .chain(local)
+            .chain(commons)
+            .chain(external)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.osm.is_empty()
+            && self.wikidata.is_empty()
+            && self.local.is_empty()
+            && self.commons.is_empty()
+            && self.external.is_empty()
+    }
+
+    /// Replace the override of the given element, or remove it if `None`
+    pub fn set_override(&mut self, element_id: &ElementId, override_entry: Option<OverrideEntry>) {
+        match (element_id, override_entry) {
+            (ElementId::Osm(id), Some(entry)) => {
+                self.osm.insert(*id, entry);
+            }
+            (ElementId::Osm(id), None) => {
+                self.osm.remove(id);
+            }
+            (ElementId::Wikidata(id), Some(entry)) => {
+                self.wikidata.insert(id.clone(), entry);
+            }
+            (ElementId::Wikidata(id), None) => {
+                self.wikidata.remove(id);
+            }
+            (ElementId::Local(id), Some(entry)) => {
+                self.local.insert(id.clone(), entry);
+            }
+            (ElementId::Local(id), None) => {
+                self.local.remove(id);
+            }
+            (ElementId::Commons(id), Some(entry)) => {
+                self.commons.insert(id.clone(), entry);
+            }
+            (ElementId::Commons(id), None) => {
+                self.commons.remove(id);
+            }
+            (ElementId::External { namespace, id }, Some(entry)) => {
+                self.external
+                    .entry(namespace.clone())
+                    .or_default()
+                    .insert(id.clone(), entry);
+            }
+            (ElementId::External { namespace, id }, None) => {
+                if let Some(entries) = self.external.get_mut(namespace) {
+                    entries.remove(id);
+                    if entries.is_empty() {
+                        self.external.remove(namespace);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn get_override(&self, element_id: &ElementId) -> Option<&OverrideEntry> {
         match element_id {
             ElementId::Osm(id) => self.osm.get(id),
             ElementId::Wikidata(id) => self.wikidata.get(id),
+            ElementId::Local(id) => self.local.get(id),
+            ElementId::Commons(id) => self.commons.get(id),
+            ElementId::External { namespace, id } => self.external.get(namespace)?.get(id),
         }
     }
 }
+
+/**
+ * The content of `overrides.json`: the global overrides at the top level, and in `categories` those only applied to one category (after the global ones).
+ */
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Overrides {
+    #[serde(flatten)]
+    pub global: OverrideSet,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub categories: BTreeMap<DepictionCategory, OverrideSet>,
+}
+
+// Not derived, as `#[serde(flatten)]` can’t deserialize the numeric keys of the OpenStreetMap overrides
+impl<'de> Deserialize<'de> for Overrides {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value = Map::<String, Value>::deserialize(deserializer)?;
+        let categories = match value.remove("categories") {
+            Some(categories) => serde_json::from_value(categories).map_err(D::Error::custom)?,
+            None => BTreeMap::new(),
+        };
+        let global = serde_json::from_value(Value::Object(value)).map_err(D::Error::custom)?;
+        Ok(Self { global, categories })
+    }
+}
+
+impl Overrides {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let f = File::open(path).with_context(|| format!("Opening overrides file {path:?}"))?;
+        serde_json::from_reader(f).with_context(|| format!("Parsing overrides file {path:?}"))
+    }
+
+    /// Iterate over every override, with the category it is restricted to (if any) and the id of the entry it applies to
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (Option<&DepictionCategory>, ElementId, &OverrideEntry)> {
+        let global = self
+            .global
+            .iter()
+            .map(|(element_id, entry)| (None, element_id, entry));
+        let categories = self.categories.iter().flat_map(|(category, set)| {
+            set.iter()
+                .map(move |(element_id, entry)| (Some(category), element_id, entry))
+        });
+        global.chain(categories)
+    }
+
+    /// Replace the override of the given element, either global or for the given category, or remove it if `None`
+    pub fn set_override(
+        &mut self,
+        element_id: &ElementId,
+        category: Option<&DepictionCategory>,
+        override_entry: Option<OverrideEntry>,
+    ) {
+        match category {
+            None => self.global.set_override(element_id, override_entry),
+            Some(category) => {
+                let set = self.categories.entry(category.clone()).or_default();
+                set.set_override(element_id, override_entry);
+                if set.is_empty() {
+                    self.categories.remove(category);
+                }
+            }
+        }
+    }
+
+    /// The overrides to apply to an entry with the given ids when displayed in the given category, in order: the global overrides of every id, then the overrides of the category for every id
+    pub fn get_overrides<'a>(
+        &'a self,
+        element_ids: &'a [ElementId],
+        category: &DepictionCategory,
+    ) -> impl Iterator<Item = &'a OverrideEntry> {
+        let global = element_ids
+            .iter()
+            .filter_map(|element_id| self.global.get_override(element_id));
+        let category = self.categories.get(category).into_iter().flat_map(|set| {
+            element_ids
+                .iter()
+                .filter_map(|element_id| set.get_override(element_id))
+        });
+        global.chain(category)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn dragon_entry() -> MapEntry {
+        serde_json::from_value(json!({
+            "pos": [48.85, 2.35],
+            "name": "Paris dragon",
+            "location_name": null,
+            "image": {
+                "url": "https://example.org/dragon.jpg",
+                "credit_url": null,
+                "credit_text": null,
+                "license_url": null,
+                "thumbnail_url": null,
+            },
+            "source_url": null,
+            "source_text": "OpenStreetMap",
+            "is_in_exhibit": false,
+            "nature": null,
+            "element_ids": [{ "Osm": 42 }],
+        }))
+        .unwrap()
+    }
+
+    fn named(name: &str) -> OverrideEntry {
+        serde_json::from_value(json!({ "name": name })).unwrap()
+    }
+
+    #[test]
+    fn deserializes_numeric_osm_keys() {
+        let overrides: Overrides = serde_json::from_value(json!({
+            "osm": { "42": { "name": "global" } },
+            "wikidata": { "Q42": { "hidden": true } },
+            "external": { "museum": { "7": { "nature": "statue" } } },
+            "categories": {
+                "dragon": { "osm": { "42": { "name": "dragon" } } },
+            },
+        }))
+        .unwrap();
+        assert_eq!(overrides.global.osm[&42].name.as_deref(), Some("global"));
+        assert!(overrides.global.wikidata["Q42"].hidden);
+        assert!(!overrides.global.osm[&42].hidden);
+        assert_eq!(
+            overrides.global.external["museum"]["7"].nature.as_deref(),
+            Some("statue")
+        );
+        assert_eq!(
+            overrides.categories[&DepictionCategory::dragon()].osm[&42]
+                .name
+                .as_deref(),
+            Some("dragon")
+        );
+        assert_eq!(overrides.iter().count(), 4);
+
+        assert!(serde_json::from_value::<Overrides>(json!({ "osm": { "way/42": {} } })).is_err());
+        assert!(
+            serde_json::from_value::<Overrides>(json!({}))
+                .unwrap()
+                .iter()
+                .next()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn serializes_without_unset_fields() {
+        let mut overrides = Overrides::default();
+        overrides.set_override(&ElementId::Osm(42), None, Some(named("global")));
+        overrides.set_override(
+            &ElementId::Osm(42),
+            Some(&DepictionCategory::dragon()),
+            Some(named("dragon")),
+        );
+        let value = serde_json::to_value(&overrides).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "osm": { "42": { "name": "global" } },
+                "categories": { "dragon": { "osm": { "42": { "name": "dragon" } } } },
+            })
+        );
+        let reloaded: Overrides = serde_json::from_value(value).unwrap();
+        assert_eq!(reloaded.iter().count(), 2);
+
+        // Removing the last override of a category removes the category
+        overrides.set_override(
+            &ElementId::Osm(42),
+            Some(&DepictionCategory::dragon()),
+            None,
+        );
+        assert!(overrides.categories.is_empty());
+    }
+
+    #[test]
+    fn overrides_map_entry() {
+        let override_entry: OverrideEntry = serde_json::from_value(json!({
+            "pos": [45.76, 4.83],
+            "name": "Lyon dragon",
+            "is_in_exhibit": true,
+            "nature": "statue",
+            "image_source_url": "https://example.org/credit",
+            "image_source_text": "Some photographer",
+        }))
+        .unwrap();
+        let mut map_entry = dragon_entry();
+        override_entry.override_map_entry(&mut map_entry);
+        assert_eq!(
+            map_entry.pos,
+            Some((OrderedFloat(45.76), OrderedFloat(4.83)))
+        );
+        assert_eq!(map_entry.name.as_deref(), Some("Lyon dragon"));
+        assert!(map_entry.is_in_exhibit);
+        assert_eq!(map_entry.nature.as_deref(), Some("statue"));
+        let image = map_entry.image.unwrap();
+        assert_eq!(image.url, "https://example.org/dragon.jpg");
+        assert_eq!(
+            image.credit_url.as_deref(),
+            Some("https://example.org/credit")
+        );
+        assert_eq!(image.credit_text.as_deref(), Some("Some photographer"));
+
+        // A local image replaces the fetched one, then gets the credit
+        let override_entry: OverrideEntry = serde_json::from_value(json!({
+            "local_image": "dragon.png",
+            "image_source_text": "Me",
+        }))
+        .unwrap();
+        let mut map_entry = dragon_entry();
+        override_entry.override_map_entry(&mut map_entry);
+        assert_eq!(map_entry.name.as_deref(), Some("Paris dragon"));
+        let image = map_entry.image.unwrap();
+        assert_eq!(image.url, "/images/dragon.png");
+        assert_eq!(image.credit_url, None);
+        assert_eq!(image.credit_text.as_deref(), Some("Me"));
+    }
+
+    #[test]
+    fn hidden_only_when_set() {
+        let hidden: OverrideEntry = serde_json::from_value(json!({ "hidden": true })).unwrap();
+        assert!(hidden.hidden);
+        assert_eq!(
+            serde_json::to_value(&hidden).unwrap(),
+            json!({ "hidden": true })
+        );
+
+        // Hiding does not modify the entry itself
+        let mut map_entry = dragon_entry();
+        hidden.override_map_entry(&mut map_entry);
+        assert_eq!(map_entry, dragon_entry());
+
+        let shown = named("dragon");
+        assert!(!shown.hidden);
+        assert_eq!(
+            serde_json::to_value(&shown).unwrap(),
+            json!({ "name": "dragon" })
+        );
+    }
+
+    #[test]
+    fn global_overrides_come_first() {
+        let mut overrides = Overrides::default();
+        let other_category = DepictionCategory("wyvern".into());
+        overrides.set_override(
+            &ElementId::Osm(42),
+            Some(&DepictionCategory::dragon()),
+            Some(named("dragon")),
+        );
+        overrides.set_override(
+            &ElementId::Osm(42),
+            Some(&other_category),
+            Some(named("wyvern")),
+        );
+        overrides.set_override(&ElementId::Osm(42), None, Some(named("global")));
+        overrides.set_override(&ElementId::Osm(43), None, Some(named("other")));
+
+        let names = |category: &DepictionCategory| {
+            overrides
+                .get_overrides(&[ElementId::Osm(42)], category)
+                .map(|entry| entry.name.clone().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&DepictionCategory::dragon()), ["global", "dragon"]);
+        assert_eq!(names(&other_category), ["global", "wyvern"]);
+        assert_eq!(names(&DepictionCategory("hydra".into())), ["global"]);
+        assert_eq!(
+            overrides
+                .get_overrides(&[ElementId::Osm(1)], &DepictionCategory::dragon())
+                .count(),
+            0
+        );
+    }
+}
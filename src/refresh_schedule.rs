@@ -0,0 +1,305 @@
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tai_time::TaiTime;
+
+use crate::time_source::unix_secs_from_tai;
+
+/// Return the UTC hour of the day (0-23) of the given time.
+fn utc_hour(time: TaiTime<0>) -> u8 {
+    (unix_secs_from_tai(time).rem_euclid(86400) / 3600) as u8
+}
+
+/**
+ * A range of UTC hours, from `start` (included) to `end` (excluded). Wrap around midnight if `end` is lower than `start`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HourRange {
+    pub start: u8,
+    pub end: u8,
+}
+
+impl HourRange {
+    pub fn contains(&self, hour: u8) -> bool {
+        if self.start <= self.end {
+            hour >= self.start && hour < self.end
+        } else {
+            hour >= self.start || hour < self.end
+        }
+    }
+}
+
+/**
+ * Describe when a source should be refreshed.
+ */
+#[derive(Debug, Clone)]
+pub struct RefreshSchedule {
+    /// Minimum time between two refreshes
+    pub interval: Duration,
+    /// If set, only refresh during those UTC hours (index is the hour)
+    pub allowed_hours: Option<[bool; 24]>,
+    /// If set, never refresh during this window
+    pub quiet_hours: Option<HourRange>,
+}
+
+impl RefreshSchedule {
+    pub fn every(interval: Duration) -> Self {
+        Self {
+            interval,
+            allowed_hours: None,
+            quiet_hours: None,
+        }
+    }
+
+    /// Restrict refresh to the UTC hours matched by the hour field of a cron expression, like `*`, `*/6`, `0-5` or `1,3,22-23`.
+    pub fn only_at_hours(mut self, cron_hours: &str) -> anyhow::Result<Self> {
+        self.allowed_hours = Some(
+            parse_cron_hours(cron_hours)
+                .with_context(|| format!("Parsing the cron hour field {cron_hours:?}"))?,
+        );
+        Ok(self)
+    }
+
+    /// Forbid refreshing from the UTC hour `start` (included) to `end` (excluded)
+    pub fn with_quiet_hours(mut self, start: u8, end: u8) -> anyhow::Result<Self> {
+        if start > 23 || end > 23 {
+            bail!("The quiet hours {start}-{end} are out of range");
+        }
+        self.quiet_hours = Some(HourRange { start, end });
+        Ok(self)
+    }
+
+    /// True if `current_time` is a moment where a refresh is allowed, ignoring the interval
+    pub fn allows(&self, current_time: TaiTime<0>) -> bool {
+        let hour = utc_hour(current_time);
+        let outside_allowed_hours = self
+            .allowed_hours
+            .is_some_and(|allowed_hours| !allowed_hours[hour as usize]);
+        let in_quiet_hours = self
+            .quiet_hours
+            .is_some_and(|quiet_hours| quiet_hours.contains(hour));
+        !outside_allowed_hours && !in_quiet_hours
+    }
+
+    /// True if a source last updated at `last_updated` should be refreshed at `current_time`
+    pub fn is_due(&self, last_updated: Option<TaiTime<0>>, current_time: TaiTime<0>) -> bool {
+        let Some(last_updated) = last_updated else {
+            // Nothing to serve yet, so ignore the time window
+            return true;
+        };
+        if !self.allows(current_time) {
+            return false;
+        }
+        // if the clock goes backward for some reason, refetch the data (and so re-set the time)
+        if current_time < last_updated {
+            return true;
+        }
+        last_updated + self.interval < current_time
+    }
+}
+
+/**
+ * Replace parts of the default refresh schedule of a source, from `config.json`.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    /// Minimum time between two refreshes, in seconds
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    /// Hour field of a cron expression, like `0-5` to only refresh at night
+    #[serde(default)]
+    pub hours: Option<String>,
+    /// `[start, end]` UTC hours during which the source is never refreshed
+    #[serde(default)]
+    pub quiet_hours: Option<(u8, u8)>,
+}
+
+impl ScheduleConfig {
+    pub fn apply(&self, mut schedule: RefreshSchedule) -> anyhow::Result<RefreshSchedule> {
+        if let Some(interval_secs) = self.interval_secs {
+            if interval_secs == 0 {
+                bail!("The refresh interval should not be 0");
+            }
+            schedule.interval = Duration::from_secs(interval_secs);
+        }
+        if let Some(hours) = &self.hours {
+            schedule = schedule.only_at_hours(hours)?;
+        }
+        if let Some((start, end)) = self.quiet_hours {
+            schedule = schedule.with_quiet_hours(start, end)?;
+        }
+        Ok(schedule)
+    }
+}
+
+fn parse_hour(value: &str) -> anyhow::Result<u8> {
+    let hour: u8 = value
+        .parse()
+        .with_context(|| format!("{value:?} is not a valid hour"))?;
+    if hour > 23 {
+        bail!("Hour {hour} is out of range");
+    }
+    Ok(hour)
+}
+
+fn parse_cron_hours(cron_hours: &str) -> anyhow::Result<[bool; 24]> {
+    let mut result = [false; 24];
+    for part in cron_hours.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u8>()
+                    .with_context(|| format!("{step:?} is not a valid step"))?,
+            ),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("A step can’t be 0");
+        }
+        let (start, end) = if range == "*" {
+            (0, 23)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_hour(start)?, parse_hour(end)?)
+        } else {
+            let hour = parse_hour(range)?;
+            (hour, hour)
+        };
+        if start > end {
+            bail!("Range {range:?} is reversed");
+        }
+        for hour in (start..=end).step_by(step as usize) {
+            result[hour as usize] = true;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::{FakeTimeSource, TimeSource, time_source::tai_from_system_time};
+
+    /// 2024-05-01T00:00:00Z
+    const MIDNIGHT: u64 = 1714521600;
+
+    fn clock_at(unix_secs: u64) -> FakeTimeSource {
+        FakeTimeSource::new(tai_from_system_time(
+            UNIX_EPOCH + Duration::from_secs(unix_secs),
+        ))
+    }
+
+    #[test]
+    fn never_updated_is_due() {
+        let schedule = RefreshSchedule::every(Duration::from_secs(3600))
+            .with_quiet_hours(0, 23)
+            .unwrap();
+        let clock = clock_at(MIDNIGHT);
+        assert!(schedule.is_due(None, clock.now()));
+    }
+
+    #[test]
+    fn due_after_the_interval() {
+        let schedule = RefreshSchedule::every(Duration::from_secs(3600));
+        let clock = clock_at(MIDNIGHT);
+        let last_updated = clock.now();
+
+        clock.advance(Duration::from_secs(1800));
+        assert!(!schedule.is_due(Some(last_updated), clock.now()));
+        clock.advance(Duration::from_secs(1801));
+        assert!(schedule.is_due(Some(last_updated), clock.now()));
+    }
+
+    #[test]
+    fn due_when_the_clock_goes_backward() {
+        let schedule = RefreshSchedule::every(Duration::from_secs(3600));
+        let clock = clock_at(MIDNIGHT);
+        let last_updated = clock.now();
+        clock.set(tai_from_system_time(
+            UNIX_EPOCH + Duration::from_secs(MIDNIGHT - 60),
+        ));
+        assert!(schedule.is_due(Some(last_updated), clock.now()));
+    }
+
+    #[test]
+    fn not_due_in_quiet_hours() {
+        let schedule = RefreshSchedule::every(Duration::from_secs(60))
+            .with_quiet_hours(8, 20)
+            .unwrap();
+        let clock = clock_at(MIDNIGHT + 7 * 3600);
+        let last_updated = clock.now();
+
+        // 10:00, in the quiet hours
+        clock.advance(Duration::from_secs(3 * 3600));
+        assert!(!schedule.is_due(Some(last_updated), clock.now()));
+        // 20:00, just after them
+        clock.advance(Duration::from_secs(10 * 3600));
+        assert!(schedule.is_due(Some(last_updated), clock.now()));
+    }
+
+    #[test]
+    fn quiet_hours_out_of_range() {
+        let schedule = RefreshSchedule::every(Duration::from_secs(60));
+        assert!(schedule.clone().with_quiet_hours(22, 24).is_err());
+        assert!(schedule.with_quiet_hours(30, 2).is_err());
+    }
+
+    #[test]
+    fn only_at_allowed_hours() {
+        let schedule = RefreshSchedule::every(Duration::from_secs(60))
+            .only_at_hours("0-5")
+            .unwrap();
+        let clock = clock_at(MIDNIGHT + 3 * 3600);
+        let last_updated = clock.now();
+        clock.advance(Duration::from_secs(3600));
+        assert!(schedule.is_due(Some(last_updated), clock.now()));
+        clock.advance(Duration::from_secs(2 * 3600));
+        assert!(!schedule.is_due(Some(last_updated), clock.now()));
+    }
+
+    #[test]
+    fn cron_hours() {
+        let hours = parse_cron_hours("1,3,22-23").unwrap();
+        let allowed: Vec<usize> = (0..24).filter(|hour| hours[*hour]).collect();
+        assert_eq!(allowed, vec![1, 3, 22, 23]);
+
+        let hours = parse_cron_hours("*/6").unwrap();
+        let allowed: Vec<usize> = (0..24).filter(|hour| hours[*hour]).collect();
+        assert_eq!(allowed, vec![0, 6, 12, 18]);
+
+        assert!(parse_cron_hours("24").is_err());
+        assert!(parse_cron_hours("5-2").is_err());
+        assert!(parse_cron_hours("*/0").is_err());
+        assert!(parse_cron_hours("night").is_err());
+    }
+
+    #[test]
+    fn schedule_config_replaces_the_default() {
+        let config: ScheduleConfig =
+            serde_json::from_str(r#"{ "interval_secs": 600, "quiet_hours": [8, 20] }"#).unwrap();
+        let schedule = config
+            .apply(RefreshSchedule::every(Duration::from_secs(3600)))
+            .unwrap();
+        assert_eq!(schedule.interval, Duration::from_secs(600));
+        assert_eq!(schedule.quiet_hours, Some(HourRange { start: 8, end: 20 }));
+        assert!(schedule.allowed_hours.is_none());
+
+        let config: ScheduleConfig = serde_json::from_str(r#"{ "hours": "25" }"#).unwrap();
+        assert!(
+            config
+                .apply(RefreshSchedule::every(Duration::from_secs(3600)))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn quiet_hours_wrap_around_midnight() {
+        let quiet_hours = HourRange { start: 22, end: 6 };
+        assert!(quiet_hours.contains(23));
+        assert!(quiet_hours.contains(0));
+        assert!(!quiet_hours.contains(6));
+        assert!(!quiet_hours.contains(12));
+    }
+}
@@ -0,0 +1,252 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    DepictionCategory, FetchDataFile, FetchDataJsonApi, FetchedDataSet, FieldMapping, FileFormat,
+    FileLocation, Pagination, RefreshSchedule, fetch_data_json_api::check_json_paths,
+    select_json_path,
+};
+
+fn default_categories() -> Vec<DepictionCategory> {
+    vec![DepictionCategory::dragon()]
+}
+
+/// Refresh interval of the sources that set neither `refresh_interval_secs` nor an `interval_secs` in the schedules
+const DEFAULT_REFRESH_INTERVAL_SECS: u64 = 3600 * 24;
+
+/// How the entries of a configured source are fetched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourceKind {
+    /// A GeoJSON or CSV file
+    File {
+        /// A path relative to the ressource directory, or an http(s) url
+        location: String,
+        #[serde(flatten)]
+        format: FileFormat,
+        #[serde(default)]
+        mapping: FieldMapping,
+    },
+    /// A JSON API, with `mapping` giving the path of each field in the items
+    JsonApi {
+        url: String,
+        /// Path of the items in each answer, like `$.results[*]`
+        items: String,
+        /// Headers to send with every request, like an API key
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default)]
+        pagination: Pagination,
+        #[serde(default)]
+        mapping: FieldMapping,
+    },
+}
+
+/**
+ * A source set up in `config.json`, in addition to the hardcoded ones. Its entries are stored as `external_{namespace}.json`.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceConfig {
+    /// Namespace of the ids of its entries, like `external:{namespace}:{id}`
+    pub namespace: String,
+    pub title: String,
+    /// The text of the link to the source of each entry, defaulting to the title
+    #[serde(default)]
+    pub source_text: Option<String>,
+    #[serde(default = "default_categories")]
+    pub categories: Vec<DepictionCategory>,
+    /// The interval of its default schedule. Setting `interval_secs` in the schedule of its storage file too is a problem, as only the latter would be used.
+    #[serde(default)]
+    pub refresh_interval_secs: Option<u64>,
+    #[serde(flatten)]
+    pub kind: SourceKind,
+}
+
+impl SourceConfig {
+    pub fn storage_file_name(&self) -> String {
+        format!("external_{}.json", self.namespace)
+    }
+
+    /// Return the settings that can be parsed but would not work as intended
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.namespace.is_empty()
+            || !self
+                .namespace
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            problems.push(format!(
+                "The namespace {:?} should only contain ASCII letters, digits, _ and -",
+                self.namespace
+            ));
+        }
+        if self.refresh_interval_secs == Some(0) {
+            problems.push(format!(
+                "The refresh interval of {:?} should not be 0",
+                self.namespace
+            ));
+        }
+        match &self.kind {
+            SourceKind::File {
+                location, format, ..
+            } => {
+                if let FileLocation::Remote(url) = FileLocation::parse(location, Path::new(""))
+                    && let Err(err) = Url::parse(&url)
+                {
+                    problems.push(format!("Invalid url {url:?}: {err}"));
+                }
+                if let FileFormat::Csv { delimiter } = format
+                    && !delimiter.is_ascii()
+                {
+                    problems.push(format!(
+                        "The delimiter {delimiter:?} of {:?} is not an ASCII character",
+                        self.namespace
+                    ));
+                }
+            }
+            SourceKind::JsonApi {
+                url,
+                items,
+                pagination,
+                mapping,
+                ..
+            } => {
+                if let Err(err) = Url::parse(url) {
+                    problems.push(format!("Invalid url {url:?}: {err}"));
+                }
+                if let Err(err) = check_json_paths(items, mapping) {
+                    problems.push(format!("In the source {:?}: {err}", self.namespace));
+                }
+                if let Pagination::NextUrl { path } = pagination
+                    && let Err(err) = select_json_path(&Value::Null, path)
+                {
+                    problems.push(format!("In the source {:?}: {err}", self.namespace));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Add the fetcher of this source to the set. Fail if it has any problem, as its namespace is used in the storage file name and in the ids.
+    pub fn add_to(
+        &self,
+        fetched_data_set: &mut FetchedDataSet,
+        ressource_path: &Path,
+    ) -> anyhow::Result<()> {
+        let problems = self.problems();
+        if !problems.is_empty() {
+            bail!("{}", problems.join(", "));
+        }
+        let schedule = fetched_data_set.extra.config.schedule_for(
+            &self.storage_file_name(),
+            RefreshSchedule::every(Duration::from_secs(
+                self.refresh_interval_secs
+                    .unwrap_or(DEFAULT_REFRESH_INTERVAL_SECS),
+            )),
+        )?;
+        match &self.kind {
+            SourceKind::File {
+                location,
+                format,
+                mapping,
+            } => {
+                let mut fetcher = FetchDataFile::new(
+                    self.namespace.clone(),
+                    FileLocation::parse(location, ressource_path),
+                    format.clone(),
+                    self.title.clone(),
+                    schedule,
+                )
+                .with_mapping(mapping.clone());
+                if let Some(source_text) = &self.source_text {
+                    fetcher = fetcher.with_source_text(source_text.clone());
+                }
+                fetched_data_set.add_fetcher(
+                    fetcher,
+                    self.categories.clone(),
+                    self.storage_file_name(),
+                )
+            }
+            SourceKind::JsonApi {
+                url,
+                items,
+                headers,
+                pagination,
+                mapping,
+            } => {
+                let mut fetcher = FetchDataJsonApi::new(
+                    self.namespace.clone(),
+                    url.clone(),
+                    items.clone(),
+                    self.title.clone(),
+                    schedule,
+                )
+                .with_headers(headers.clone())
+                .with_pagination(pagination.clone())
+                .with_mapping(mapping.clone());
+                if let Some(source_text) = &self.source_text {
+                    fetcher = fetcher.with_source_text(source_text.clone());
+                }
+                fetched_data_set.add_fetcher(
+                    fetcher,
+                    self.categories.clone(),
+                    self.storage_file_name(),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::Config;
+
+    fn source(namespace: &str) -> SourceConfig {
+        serde_json::from_value(json!({
+            "namespace": namespace,
+            "title": "Imported dragons",
+            "type": "file",
+            "location": "imports/dragons.csv",
+            "format": "csv",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn refuses_invalid_and_duplicate_namespaces() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        write(dir.join("overrides.json"), "{}").unwrap();
+        let mut fetched_data_set =
+            FetchedDataSet::new(dir.join("save"), dir, Config::default()).unwrap();
+
+        for namespace in ["../escape", "a:b", ""] {
+            assert_eq!(source(namespace).problems().len(), 1);
+            assert!(
+                source(namespace)
+                    .add_to(&mut fetched_data_set, dir)
+                    .is_err()
+            );
+        }
+        source("brittany")
+            .add_to(&mut fetched_data_set, dir)
+            .unwrap();
+        assert!(
+            source("brittany")
+                .add_to(&mut fetched_data_set, dir)
+                .is_err()
+        );
+        assert_eq!(fetched_data_set.entries.len(), 1);
+    }
+}
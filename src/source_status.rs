@@ -0,0 +1,43 @@
+use serde::Serialize;
+
+use crate::DepictionCategory;
+
+/// An update that was held back by the mass-deletion guard
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingUpdateStatus {
+    pub previous_count: usize,
+    pub new_count: usize,
+    pub removed_count: usize,
+    /// When it was fetched, as an UTC Unix timestamp
+    pub fetched_at: i64,
+}
+
+/**
+ * What is publicly known about the state of a source.
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceStatus {
+    pub name: String,
+    pub title: String,
+    pub categories: Vec<DepictionCategory>,
+    pub entries_count: usize,
+    /// Last time an update was tried (even if it failed), as an UTC Unix timestamp
+    pub last_updated: Option<i64>,
+    /// When the stored entries were fetched, as an UTC Unix timestamp
+    pub last_successful_update: Option<i64>,
+    /// How old the stored entries are, in seconds, as of when the status was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_secs: Option<i64>,
+    pub pending_update: Option<PendingUpdateStatus>,
+    pub last_error: Option<String>,
+}
+
+impl SourceStatus {
+    /// Set the age of the data, as of the given UTC Unix timestamp
+    pub fn with_age_at(mut self, now: i64) -> Self {
+        self.age_secs = self
+            .last_successful_update
+            .map(|last_successful_update| now - last_successful_update);
+        self
+    }
+}
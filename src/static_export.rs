@@ -0,0 +1,224 @@
+use std::{
+    fs::{self, create_dir_all},
+    path::Path,
+};
+
+use anyhow::Context;
+use log::{info, warn};
+use rust_embed::Embed;
+
+use crate::{FetchedDataSet, MapEntry};
+
+/// The web interface, served from the `static` folder
+#[derive(Embed)]
+#[folder = "static"]
+pub struct Asset;
+
+/// Files of the `static` folder only used with the server
+const SERVER_ONLY_ASSETS: &[&str] = &["admin.html", "admin.js"];
+
+fn write_file(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).with_context(|| format!("Could not create dir at {parent:?}"))?;
+    }
+    fs::write(path, content).with_context(|| format!("Could not write to {path:?}"))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<usize> {
+    let mut count = 0;
+    create_dir_all(to).with_context(|| format!("Could not create dir at {to:?}"))?;
+    for dir_entry in fs::read_dir(from).with_context(|| format!("Listing {from:?}"))? {
+        let dir_entry = dir_entry?;
+        let target = to.join(dir_entry.file_name());
+        if dir_entry.file_type()?.is_dir() {
+            count += copy_dir(&dir_entry.path(), &target)?;
+        } else {
+            fs::copy(dir_entry.path(), &target)
+                .with_context(|| format!("Copying {:?} to {target:?}", dir_entry.path()))?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Make the url relative to the root of the export, so it still works when the site is not at the root of its domain
+fn relative_url(url: &str) -> String {
+    match url.strip_prefix('/') {
+        Some(path) => path.to_string(),
+        None => url.to_string(),
+    }
+}
+
+/// Copy the thumbnail of the entry (making it if needed) into the export, and make the urls of its image relative
+fn export_image(
+    fetched_data_set: &FetchedDataSet,
+    output: &Path,
+    map_entry: &mut MapEntry,
+) -> anyhow::Result<()> {
+    let Some(image) = &mut map_entry.image else {
+        return Ok(());
+    };
+    if let Some(key) = image
+        .thumbnail_url
+        .as_ref()
+        .and_then(|url| url.strip_prefix("/thumbnails/"))
+        .and_then(|file| file.strip_suffix(".jpg"))
+    {
+        let target = output.join("thumbnails").join(format!("{key}.jpg"));
+        let copied = match fetched_data_set.extra.thumbnailer.get_thumbnail(key) {
+            Ok(Some(path)) => {
+                create_dir_all(output.join("thumbnails"))?;
+                fs::copy(&path, &target)
+                    .with_context(|| format!("Copying {path:?} to {target:?}"))?;
+                true
+            }
+            Ok(None) => false,
+            Err(err) => {
+                warn!("Could not make the thumbnail of {}: {err:?}", image.url);
+                false
+            }
+        };
+        if !copied {
+            image.thumbnail_url = None;
+        }
+    }
+    image.url = relative_url(&image.url);
+    image.thumbnail_url = image.thumbnail_url.as_deref().map(relative_url);
+    Ok(())
+}
+
+/**
+ * Write the web interface with the current data in `output`, so it can be served by any static file server: `index.html`, the `static` assets, the `images` of the ressource directory, the thumbnails, and the entries and feed of each category.
+ */
+pub fn export_static_site(fetched_data_set: &FetchedDataSet, output: &Path) -> anyhow::Result<()> {
+    create_dir_all(output).with_context(|| format!("Could not create dir at {output:?}"))?;
+
+    for path in Asset::iter() {
+        if SERVER_ONLY_ASSETS.contains(&path.as_ref()) {
+            continue;
+        }
+        let Some(content) = Asset::get(&path) else {
+            continue;
+        };
+        let target = if path == "index.html" {
+            output.join("index.html")
+        } else {
+            output.join("static").join(path.as_ref())
+        };
+        write_file(&target, &content.data)?;
+    }
+
+    let images_path = &fetched_data_set.extra.images_path;
+    if images_path.is_dir() {
+        let count = copy_dir(images_path, &output.join("images"))?;
+        info!("Exported {count} images");
+    }
+
+    for category in fetched_data_set.list_all_depiction_category() {
+        let mut entries = fetched_data_set.build_data_for_depiction_category(category.clone());
+        for map_entry in &mut entries {
+            export_image(fetched_data_set, output, map_entry)?;
+        }
+        write_file(
+            &output
+                .join("depiction")
+                .join(format!("{}.json", category.0)),
+            &serde_json::to_vec(&entries)?,
+        )?;
+        write_file(
+            &output.join("feed").join(format!("{}.atom", category.0)),
+            fetched_data_set
+                .build_atom_for_depiction_category(category)
+                .as_bytes(),
+        )?;
+        info!("Exported {} entries for {:?}", entries.len(), category.0);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use image::{ImageFormat, RgbImage};
+    use serde_json::{Value, json};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        Config, DepictionCategory, FakeTimeSource, FetchDataLocal, RefreshSchedule,
+        time_source::tai_from_system_time,
+    };
+
+    #[test]
+    fn exports_into_directory() {
+        let temp = tempdir().unwrap();
+        let ressource = temp.path().join("ressource");
+        create_dir_all(ressource.join("images")).unwrap();
+        fs::write(ressource.join("overrides.json"), "{}").unwrap();
+        RgbImage::new(640, 480)
+            .save_with_format(ressource.join("images/dragon.png"), ImageFormat::Png)
+            .unwrap();
+        fs::write(
+            ressource.join("local.json"),
+            json!({
+                "paris": { "pos": [48.85, 2.35], "name": "Paris dragon", "local_image": "dragon.png" },
+                "lyon": { "pos": [45.76, 4.83], "name": "Lyon dragon" },
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let mut fetched_data_set =
+            FetchedDataSet::new(temp.path().join("save"), &ressource, Config::default()).unwrap();
+        fetched_data_set
+            .add_fetcher(
+                FetchDataLocal::new(
+                    ressource.join("local.json"),
+                    "local".into(),
+                    RefreshSchedule::every(Duration::from_secs(3600)),
+                ),
+                vec![DepictionCategory::dragon()],
+                "local.json".into(),
+            )
+            .unwrap();
+        let time_source = FakeTimeSource::new(tai_from_system_time(SystemTime::now()));
+        assert!(
+            fetched_data_set
+                .update_source(0, &time_source, true)
+                .is_some()
+        );
+
+        let output = temp.path().join("export");
+        export_static_site(&fetched_data_set, &output).unwrap();
+
+        assert!(output.join("index.html").is_file());
+        assert!(output.join("static/code.js").is_file());
+        assert!(output.join("static/safe_url.js").is_file());
+        assert!(!output.join("static/admin.html").exists());
+        assert!(!output.join("static/admin.js").exists());
+        assert!(output.join("images/dragon.png").is_file());
+        assert!(
+            fs::read_to_string(output.join("feed/dragon.atom"))
+                .unwrap()
+                .contains("<feed")
+        );
+
+        let entries: Value =
+            serde_json::from_slice(&fs::read(output.join("depiction/dragon.json")).unwrap())
+                .unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        let paris = entries
+            .iter()
+            .find(|entry| entry["name"] == "Paris dragon")
+            .unwrap();
+        // The urls are relative, and the thumbnail is in the export
+        assert_eq!(paris["image"]["url"], "images/dragon.png");
+        let thumbnail_url = paris["image"]["thumbnail_url"].as_str().unwrap();
+        assert!(thumbnail_url.starts_with("thumbnails/"));
+        let thumbnail = image::open(output.join(thumbnail_url)).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (320, 240));
+    }
+}
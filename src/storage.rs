@@ -1,17 +1,17 @@
 use std::{
-    collections::BTreeSet,
-    fs::{File, create_dir_all, rename},
-    path::PathBuf,
+    collections::{BTreeMap, BTreeSet},
     sync::Arc,
 };
 
 use anyhow::Context;
-use log::warn;
-use safe_join::SafeJoin;
-use serde::{Deserialize, Serialize};
+use log::info;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tai_time::TaiTime;
 
-use crate::{MapEntry, fetched_data_set::FetchDataExtra};
+use crate::{
+    CommonsAttribution, EntryDiff, MapEntry, PendingUpdate, fetched_data_set::FetchDataExtra,
+    storage_migration::migrate_public,
+};
 
 #[derive(Default, Serialize, Deserialize, Debug)]
 pub struct StoredData {
@@ -19,138 +19,121 @@ pub struct StoredData {
     pub public: StoredDataPublic,
 }
 
+/**
+ * The public data of a source. The entries are stored as an object keyed by their primary id (see [`keyed_entries`]), so that the file stays in the same order when an entry is modified, and each change only touches the lines of this entry.
+ */
 #[derive(Default, Serialize, Deserialize, Debug)]
 pub struct StoredDataPublic {
+    #[serde(
+        serialize_with = "serialize_entries_by_key",
+        deserialize_with = "deserialize_entries_by_key"
+    )]
     pub entries: BTreeSet<MapEntry>,
 }
 
+/// Key of the entries that do not have any id
+const UNIDENTIFIED_ENTRY_KEY: &str = "unidentified";
+
+/// Give an unique, stable key to each entry: its primary id, followed by `#2`, `#3`... if several entries share it
+pub fn keyed_entries(entries: &BTreeSet<MapEntry>) -> BTreeMap<String, &MapEntry> {
+    let mut result = BTreeMap::new();
+    for entry in entries {
+        let key = match entry.primary_id() {
+            Some(id) => id.to_string(),
+            None => UNIDENTIFIED_ENTRY_KEY.to_string(),
+        };
+        let mut unique_key = key.clone();
+        let mut count = 1;
+        while result.contains_key(&unique_key) {
+            count += 1;
+            unique_key = format!("{key}#{count}");
+        }
+        result.insert(unique_key, entry);
+    }
+    result
+}
+
+fn serialize_entries_by_key<S: Serializer>(
+    entries: &BTreeSet<MapEntry>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    keyed_entries(entries).serialize(serializer)
+}
+
+fn deserialize_entries_by_key<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeSet<MapEntry>, D::Error> {
+    let entries = BTreeMap::<String, MapEntry>::deserialize(deserializer)?;
+    Ok(entries.into_values().collect())
+}
+
+impl StoredDataPublic {
+    /// Parse the content of a public storage file, migrating it from older schema version if needed
+    pub fn from_slice(data: &[u8]) -> anyhow::Result<Self> {
+        let value = migrate_public(serde_json::de::from_slice(data)?)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
 #[derive(Default, Serialize, Deserialize, Debug)]
 pub struct StoredDataPrivate {
+    /// Last time an update was tried, even if it failed, used to schedule the next one
     pub last_updated: Option<TaiTime<0>>,
+    /// When the stored entries were fetched
+    #[serde(default)]
+    pub last_successful_update: Option<TaiTime<0>>,
+    /// What changed during the last update
+    #[serde(default)]
+    pub last_diff: Option<EntryDiff>,
+    /// Author and license of the Wikimedia Commons images of the entries, by file title
+    #[serde(default)]
+    pub commons_attributions: BTreeMap<String, CommonsAttribution>,
+    /// The update held back by the mass-deletion guard, waiting to be accepted by an admin
+    #[serde(default)]
+    pub pending_update: Option<PendingUpdate>,
 }
 
 pub struct Storage {
     pub data: StoredData,
-    storage_public_file: PathBuf,
-    storage_private_file: PathBuf,
+    name: String,
     extra: Arc<FetchDataExtra>,
 }
 
 impl Storage {
-    pub fn new(storage_file_name: String, extra: Arc<FetchDataExtra>) -> anyhow::Result<Self> {
-        let storage_public_file = extra
-            .save_storage_dir
-            .safe_join(&storage_file_name)
-            .with_context(|| {
-                format!(
-                    "Error joining directory {:?} and file name {:?}",
-                    extra.save_storage_dir, storage_file_name
-                )
-            })?;
-        let storage_private_file = extra
-            .save_storage_dir
-            .safe_join(format!("{storage_file_name}.private"))
-            .with_context(|| {
-                format!(
-                    "Error joining directory {:?} and file name {:?}",
-                    extra.save_storage_dir, storage_file_name
-                )
-            })?;
-        //NOTE: if that was done for security, I would need to make sure it does not override a .git files.
-        //(and most likely just make sure it’s indeed a file name with not path separator)
-
-        Ok(Self {
+    pub fn new(name: String, extra: Arc<FetchDataExtra>) -> Self {
+        Self {
             data: StoredData::default(),
-            storage_public_file,
-            storage_private_file,
+            name,
             extra,
-        })
+        }
     }
 
     pub fn get_extra(&self) -> &Arc<FetchDataExtra> {
         &self.extra
     }
 
-    pub fn get_storage_file(&self) -> &PathBuf {
-        &self.storage_public_file
-    }
-
-    fn load_private(&mut self) -> anyhow::Result<()> {
-        let mut f = File::open(&self.storage_private_file).with_context(|| {
-            format!(
-                "Trying to open private storage file {:?}",
-                &self.storage_private_file
-            )
-        })?;
-
-        self.data.private = serde_json::de::from_reader(&mut f).with_context(|| {
-            format!(
-                "Trying to read private storage at {:?}",
-                &self.storage_private_file
-            )
-        })?;
-
-        Ok(())
+    /// The name identifying the source in the storage backend
+    pub fn get_name(&self) -> &str {
+        &self.name
     }
 
+    /// Load the stored data, if any. Fail if it exists but can’t be read (or migrated), to avoid overwriting it.
     pub fn load(&mut self) -> anyhow::Result<()> {
-        let mut f: File = File::open(&self.storage_public_file).with_context(|| {
-            format!(
-                "Trying to open public storage file {:?}",
-                &self.storage_public_file
-            )
-        })?;
-
-        self.data.public = serde_json::de::from_reader(&mut f).with_context(|| {
-            format!(
-                "Trying to read public storage at {:?}",
-                &self.storage_public_file
-            )
-        })?;
-
-        match self.load_private() {
-            Ok(_) => (),
-            Err(err) => {
-                warn!("Failed to load private storage: {err:?}");
-                self.data.private = StoredDataPrivate::default();
-            }
-        };
+        match self.extra.backend.load(&self.name)? {
+            Some(data) => self.data = data,
+            None => info!(
+                "Nothing stored for {:?}, starting from empty data",
+                self.name
+            ),
+        }
         Ok(())
     }
 
-    pub fn save(&mut self) -> anyhow::Result<()> {
-        if let Some(parent) = self.storage_public_file.parent() {
-            create_dir_all(parent)
-                .with_context(|| format!("Could not create dir at {parent:?}"))?;
-        }
-
-        let mut temp_path: PathBuf = self.storage_public_file.clone();
-        temp_path.set_file_name(format!(
-            "{}.tmp",
-            self.storage_public_file
-                .file_name()
-                .with_context(|| format!(
-                    "Can’t save storage at {:?} due to issue determining file path",
-                    self.storage_public_file
-                ))?
-                .to_string_lossy()
-        ));
-
-        {
-            let mut f_out = File::create(&temp_path)
-                .with_context(|| format!("Could not create/truncate file at {:?}", &temp_path))?;
-            serde_json::ser::to_writer_pretty(&mut f_out, &self.data.public)
-                .with_context(|| format!("Could not write storage to {:?}", &temp_path))?;
-        }
-        rename(&temp_path, &self.storage_public_file)?;
-
-        {
-            let mut f_out = File::create(&temp_path)
-                .with_context(|| format!("Could not create/truncate file at {:?}", &temp_path))?;
-            serde_json::ser::to_writer_pretty(&mut f_out, &self.data.private)
-                .with_context(|| format!("Could not write storage to {:?}", &temp_path))?;
-        }
-        rename(&temp_path, &self.storage_private_file)?;
-        Ok(())
+    /// Save the data, with the message describing the change in the history
+    pub fn save(&mut self, message: &str) -> anyhow::Result<()> {
+        self.extra
+            .backend
+            .save(&self.name, &self.data, message)
+            .with_context(|| format!("Saving {:?}", self.name))
     }
 }
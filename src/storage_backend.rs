@@ -0,0 +1,126 @@
+use std::{collections::BTreeSet, fs::create_dir_all, path::Path};
+
+use anyhow::Context;
+use log::{info, warn};
+use safe_join::SafeJoin;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Config, FileCommit, MapEntry, storage::StoredData, storage_json_git::JsonGitBackend,
+    storage_sqlite::SqliteBackend,
+};
+
+/**
+ * Where and how the data of each source is persisted. Sources are identified by their name.
+ */
+pub trait StorageBackend: Send + Sync {
+    /// Return the stored data of the source, or `None` if nothing was ever stored for it
+    fn load(&self, source: &str) -> anyhow::Result<Option<StoredData>>;
+
+    /// Replace the stored data of the source, recording the change in the history with the given message
+    fn save(&self, source: &str, data: &StoredData, message: &str) -> anyhow::Result<()>;
+
+    fn list_sources(&self) -> anyhow::Result<Vec<String>>;
+
+    /// List the revisions that changed the entries of the source, newest first, up to `limit` of them. With `before`, only the revisions older than this one are listed, and `None` is returned if it does not exist.
+    fn history(
+        &self,
+        source: &str,
+        before: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Option<Vec<FileCommit>>>;
+
+    /// Return the entries of the source as of the given revision, or `None` if it did not exist then (or the revision does not exist)
+    fn entries_at(
+        &self,
+        source: &str,
+        revision: &str,
+    ) -> anyhow::Result<Option<BTreeSet<MapEntry>>>;
+
+    /// Called periodically by the update thread
+    fn maintenance(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+fn default_sqlite_file() -> String {
+    "storage.sqlite".to_string()
+}
+
+/// Which storage backend to use
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// One JSON file per source, with each change commited in a git repository
+    #[default]
+    JsonGit,
+    /// A SQLite database, with the file being relative to the save directory
+    Sqlite {
+        #[serde(default = "default_sqlite_file")]
+        file: String,
+    },
+}
+
+/// Open the storage backend described by the configuration, storing its data in `save_storage_dir`
+pub fn open_storage_backend(
+    config: &Config,
+    save_storage_dir: &Path,
+) -> anyhow::Result<Box<dyn StorageBackend>> {
+    Ok(match &config.storage {
+        StorageConfig::JsonGit => Box::new(JsonGitBackend::new(
+            save_storage_dir.to_path_buf(),
+            config.remote.clone(),
+        )?),
+        StorageConfig::Sqlite { file } => {
+            if config.remote.is_some() {
+                warn!("A git remote is configured, but it is only used with the json_git storage");
+            }
+            create_dir_all(save_storage_dir)
+                .with_context(|| format!("Could not create dir at {save_storage_dir:?}"))?;
+            let path = save_storage_dir.safe_join(file).with_context(|| {
+                format!("Error joining directory {save_storage_dir:?} and file name {file:?}")
+            })?;
+            Box::new(SqliteBackend::new(&path)?)
+        }
+    })
+}
+
+/// Open the storage backend described by the configuration without ever writing to it, to only read the current data
+pub fn open_storage_backend_read_only(
+    config: &Config,
+    save_storage_dir: &Path,
+) -> anyhow::Result<Box<dyn StorageBackend>> {
+    Ok(match &config.storage {
+        StorageConfig::JsonGit => Box::new(JsonGitBackend::open_read_only(
+            save_storage_dir.to_path_buf(),
+        )),
+        StorageConfig::Sqlite { file } => {
+            let path = save_storage_dir.safe_join(file).with_context(|| {
+                format!("Error joining directory {save_storage_dir:?} and file name {file:?}")
+            })?;
+            Box::new(SqliteBackend::open_read_only(&path)?)
+        }
+    })
+}
+
+/// Copy the current data of every source from one backend to another. Return the number of copied sources.
+pub fn copy_storage(from: &dyn StorageBackend, to: &dyn StorageBackend) -> anyhow::Result<usize> {
+    let mut count = 0;
+    for source in from.list_sources()? {
+        let Some(data) = from
+            .load(&source)
+            .with_context(|| format!("Loading {source}"))?
+        else {
+            continue;
+        };
+        info!("Copying {} ({} entries)", source, data.public.entries.len());
+        to.save(
+            &source,
+            &data,
+            &format!("Import {source} from another storage"),
+        )
+        .with_context(|| format!("Saving {source}"))?;
+        count += 1;
+    }
+    Ok(count)
+}
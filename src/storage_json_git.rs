@@ -0,0 +1,263 @@
+use std::{
+    collections::BTreeSet,
+    fs::{File, create_dir_all, read, read_dir, rename},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use anyhow::{Context, bail};
+use git2::{Repository, RepositoryInitOptions};
+use log::{info, warn};
+use pathdiff::diff_paths;
+use safe_join::SafeJoin;
+use serde::Serialize;
+
+use crate::{
+    FileCommit, GitPusher, MapEntry, RemoteConfig, StorageBackend, list_file_history, make_commit,
+    read_file_at_commit,
+    storage::{StoredData, StoredDataPrivate, StoredDataPublic},
+    storage_migration::{
+        PRIVATE_SCHEMA_VERSION, PUBLIC_SCHEMA_VERSION, Versioned, migrate_private,
+    },
+};
+
+/**
+ * Store each source as a public JSON file (commited in a git repository, that may be pushed to a remote) and a private one (ignored by git).
+ */
+pub struct JsonGitBackend {
+    save_storage_dir: PathBuf,
+    /// `None` when opened read-only
+    repo: Option<Mutex<Repository>>,
+    pusher: Option<GitPusher>,
+}
+
+impl JsonGitBackend {
+    pub fn new(save_storage_dir: PathBuf, remote: Option<RemoteConfig>) -> anyhow::Result<Self> {
+        let repo = match Repository::open(&save_storage_dir) {
+            Ok(repo) => repo,
+            Err(err) => {
+                if err.code() == git2::ErrorCode::NotFound {
+                    info!("Creating new storage repo in {save_storage_dir:?}");
+                    Repository::init_opts(
+                        &save_storage_dir,
+                        RepositoryInitOptions::new().no_reinit(true),
+                    )
+                    .with_context(|| format!("Creating new storage repo in {save_storage_dir:?}"))?
+                } else {
+                    return Err(Into::<anyhow::Error>::into(err))
+                        .with_context(|| format!("Opening storage repo in {save_storage_dir:?}"));
+                }
+            }
+        };
+
+        let gitignore_path = save_storage_dir.join(".gitignore");
+        if !gitignore_path.exists() {
+            let mut f = File::create(&gitignore_path)
+                .with_context(|| format!("Creating .gitignore in {save_storage_dir:?}"))?;
+            writeln!(f, "*.private\nthumbnails/\n")
+                .with_context(|| format!("Writing .gitignore in {save_storage_dir:?}"))?;
+            make_commit(&repo, &PathBuf::from(".gitignore"), "Add .gitignore")?;
+        }
+
+        Ok(Self {
+            pusher: remote.map(|remote| GitPusher::new(remote, save_storage_dir.clone())),
+            save_storage_dir,
+            repo: Some(Mutex::new(repo)),
+        })
+    }
+
+    /// Open the storage without ever writing to it, not even to create the repository. Only the current data can be read.
+    pub fn open_read_only(save_storage_dir: PathBuf) -> Self {
+        Self {
+            save_storage_dir,
+            repo: None,
+            pusher: None,
+        }
+    }
+
+    fn lock_repo(&self) -> anyhow::Result<MutexGuard<'_, Repository>> {
+        let Some(repo) = &self.repo else {
+            bail!(
+                "The storage at {:?} is opened read-only",
+                self.save_storage_dir
+            );
+        };
+        match repo.lock() {
+            Ok(r) => Ok(r),
+            Err(err) => bail!("Failed to get repo: {:?}", err), // This error can’t be used by anyhow directly
+        }
+    }
+
+    fn public_file(&self, source: &str) -> anyhow::Result<PathBuf> {
+        //NOTE: if that was done for security, I would need to make sure it does not override a .git files.
+        //(and most likely just make sure it’s indeed a file name with not path separator)
+        self.save_storage_dir.safe_join(source).with_context(|| {
+            format!(
+                "Error joining directory {:?} and file name {:?}",
+                self.save_storage_dir, source
+            )
+        })
+    }
+
+    fn private_file(&self, source: &str) -> anyhow::Result<PathBuf> {
+        self.save_storage_dir
+            .safe_join(format!("{source}.private"))
+            .with_context(|| {
+                format!(
+                    "Error joining directory {:?} and file name {:?}",
+                    self.save_storage_dir, source
+                )
+            })
+    }
+
+    /// Path of the public file of the source, relative to the repository
+    fn relative_public_file(&self, source: &str) -> anyhow::Result<PathBuf> {
+        diff_paths(self.public_file(source)?, &self.save_storage_dir)
+            .context("Could not diff paths for indexing with git")
+    }
+
+    fn load_private(path: &Path) -> anyhow::Result<StoredDataPrivate> {
+        let mut f = File::open(path)
+            .with_context(|| format!("Trying to open private storage file {path:?}"))?;
+
+        let value = serde_json::de::from_reader(&mut f)
+            .with_context(|| format!("Trying to read private storage at {path:?}"))?;
+        serde_json::from_value(migrate_private(value)?)
+            .with_context(|| format!("Trying to read private storage at {path:?}"))
+    }
+
+    /// Write the data to `path`, through a temporary file so it’s never left half-written
+    fn write_versioned<T: Serialize>(path: &Path, version: u64, data: &T) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)
+                .with_context(|| format!("Could not create dir at {parent:?}"))?;
+        }
+
+        let mut temp_path = path.to_path_buf();
+        temp_path.set_file_name(format!(
+            "{}.tmp",
+            path.file_name()
+                .with_context(|| format!(
+                    "Can’t save storage at {path:?} due to issue determining file path"
+                ))?
+                .to_string_lossy()
+        ));
+
+        {
+            let mut f_out = File::create(&temp_path)
+                .with_context(|| format!("Could not create/truncate file at {:?}", &temp_path))?;
+            serde_json::ser::to_writer_pretty(&mut f_out, &Versioned { version, data })
+                .with_context(|| format!("Could not write storage to {:?}", &temp_path))?;
+        }
+        rename(&temp_path, path)?;
+        Ok(())
+    }
+}
+
+impl StorageBackend for JsonGitBackend {
+    fn load(&self, source: &str) -> anyhow::Result<Option<StoredData>> {
+        let public_file = self.public_file(source)?;
+        if !public_file.exists() {
+            return Ok(None);
+        }
+
+        let data = read(&public_file)
+            .with_context(|| format!("Trying to open public storage file {public_file:?}"))?;
+        let public = StoredDataPublic::from_slice(&data)
+            .with_context(|| format!("Trying to read public storage at {public_file:?}"))?;
+
+        let private = match Self::load_private(&self.private_file(source)?) {
+            Ok(private) => private,
+            Err(err) => {
+                // It only contains data that can be recreated, like the time of the last update
+                warn!("Failed to load private storage: {err:?}");
+                StoredDataPrivate::default()
+            }
+        };
+
+        Ok(Some(StoredData { private, public }))
+    }
+
+    fn save(&self, source: &str, data: &StoredData, message: &str) -> anyhow::Result<()> {
+        if self.repo.is_none() {
+            bail!(
+                "The storage at {:?} is opened read-only",
+                self.save_storage_dir
+            );
+        }
+        Self::write_versioned(
+            &self.public_file(source)?,
+            PUBLIC_SCHEMA_VERSION,
+            &data.public,
+        )?;
+        Self::write_versioned(
+            &self.private_file(source)?,
+            PRIVATE_SCHEMA_VERSION,
+            &data.private,
+        )?;
+
+        {
+            let repo = self.lock_repo()?;
+            make_commit(&repo, &self.relative_public_file(source)?, message)
+                .context("Commiting changes to git")?;
+        }
+        if let Some(pusher) = &self.pusher {
+            pusher.after_commit();
+        }
+        Ok(())
+    }
+
+    fn list_sources(&self) -> anyhow::Result<Vec<String>> {
+        let mut result = Vec::new();
+        for dir_entry in read_dir(&self.save_storage_dir)
+            .with_context(|| format!("Listing {:?}", self.save_storage_dir))?
+        {
+            let file_name = dir_entry?.file_name().to_string_lossy().to_string();
+            if file_name.ends_with(".json") {
+                result.push(file_name);
+            }
+        }
+        result.sort();
+        Ok(result)
+    }
+
+    fn history(
+        &self,
+        source: &str,
+        before: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Option<Vec<FileCommit>>> {
+        let path = self.relative_public_file(source)?;
+        let repo = self.lock_repo()?;
+        list_file_history(&repo, &path, before, limit)
+    }
+
+    fn entries_at(
+        &self,
+        source: &str,
+        revision: &str,
+    ) -> anyhow::Result<Option<BTreeSet<MapEntry>>> {
+        let path = self.relative_public_file(source)?;
+        let content = {
+            let repo = self.lock_repo()?;
+            read_file_at_commit(&repo, revision, &path)?
+        };
+        match content {
+            Some(content) => Ok(Some(
+                StoredDataPublic::from_slice(&content)
+                    .with_context(|| format!("Parsing {path:?} at commit {revision}"))?
+                    .entries,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Push the repository if a batched push is due
+    fn maintenance(&self) -> anyhow::Result<()> {
+        if let Some(pusher) = &self.pusher {
+            pusher.push_if_due();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Context, bail};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{MapEntry, storage::keyed_entries};
+
+/// Upgrade the JSON of a storage file from one version to the next
+type Migration = fn(Value) -> anyhow::Result<Value>;
+
+/// The migrations of the public storage file. The n-th migration upgrades from version n to n+1.
+const PUBLIC_MIGRATIONS: &[Migration] = &[add_version_field, key_entries_by_id];
+
+/// The migrations of the private storage file. The n-th migration upgrades from version n to n+1.
+const PRIVATE_MIGRATIONS: &[Migration] = &[add_version_field, add_last_successful_update];
+
+pub const PUBLIC_SCHEMA_VERSION: u64 = PUBLIC_MIGRATIONS.len() as u64;
+pub const PRIVATE_SCHEMA_VERSION: u64 = PRIVATE_MIGRATIONS.len() as u64;
+
+/// Version 0 had no version field, and is otherwise identical to version 1
+fn add_version_field(value: Value) -> anyhow::Result<Value> {
+    Ok(value)
+}
+
+/// Version 1 stored the entries as a list sorted by position, version 2 as an object keyed by their primary id
+fn key_entries_by_id(mut value: Value) -> anyhow::Result<Value> {
+    let Some(entries) = value.get_mut("entries") else {
+        return Ok(value);
+    };
+    let entries: BTreeSet<MapEntry> =
+        serde_json::from_value(entries.take()).context("Reading the list of entries")?;
+    value["entries"] = serde_json::to_value(keyed_entries(&entries))?;
+    Ok(value)
+}
+
+/// Version 1 only had the time of the last update, even failed. It is the best guess of when the stored entries were fetched.
+fn add_last_successful_update(mut value: Value) -> anyhow::Result<Value> {
+    if let Value::Object(map) = &mut value
+        && let Some(last_updated) = map.get("last_updated").cloned()
+    {
+        map.insert("last_successful_update".to_string(), last_updated);
+    }
+    Ok(value)
+}
+
+/// Wrap some data to add its schema version when serializing
+#[derive(Serialize)]
+pub struct Versioned<'a, T: Serialize> {
+    pub version: u64,
+    #[serde(flatten)]
+    pub data: &'a T,
+}
+
+fn migrate(mut value: Value, migrations: &[Migration]) -> anyhow::Result<Value> {
+    let version = match value.get("version") {
+        Some(version) => version
+            .as_u64()
+            .with_context(|| format!("Invalid storage version {version}"))?,
+        None => 0,
+    };
+    if version > migrations.len() as u64 {
+        bail!(
+            "The storage is at version {}, but this program only supports up to version {}",
+            version,
+            migrations.len()
+        );
+    }
+
+    for (from_version, migration) in migrations.iter().enumerate().skip(version as usize) {
+        value = migration(value).with_context(|| {
+            format!(
+                "Migrating storage from version {} to {}",
+                from_version,
+                from_version + 1
+            )
+        })?;
+    }
+
+    if let Value::Object(map) = &mut value {
+        map.remove("version");
+    }
+    Ok(value)
+}
+
+/// Upgrade the content of a public storage file to the current schema version (and remove the version field)
+pub fn migrate_public(value: Value) -> anyhow::Result<Value> {
+    migrate(value, PUBLIC_MIGRATIONS)
+}
+
+/// Upgrade the content of a private storage file to the current schema version (and remove the version field)
+pub fn migrate_private(value: Value) -> anyhow::Result<Value> {
+    migrate(value, PRIVATE_MIGRATIONS)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::storage::{StoredDataPrivate, StoredDataPublic};
+
+    /// The public storage of a source with a single dragon, as written by each version
+    fn dragon_entry() -> Value {
+        json!({
+            "pos": [48.85, 2.35],
+            "name": "Paris dragon",
+            "location_name": null,
+            "image": null,
+            "source_url": null,
+            "source_text": "OpenStreetMap",
+            "is_in_exhibit": false,
+            "nature": null,
+            "element_ids": [{ "Osm": 42 }],
+        })
+    }
+
+    fn public_v0() -> Value {
+        json!({ "entries": [dragon_entry()] })
+    }
+
+    fn public_v1() -> Value {
+        json!({ "version": 1, "entries": [dragon_entry()] })
+    }
+
+    fn public_v2() -> Value {
+        json!({ "version": 2, "entries": { "osm:42": dragon_entry() } })
+    }
+
+    #[test]
+    fn migrates_public_from_every_version() {
+        let current = json!({ "entries": { "osm:42": dragon_entry() } });
+        assert_eq!(migrate_public(public_v0()).unwrap(), current);
+        assert_eq!(migrate_public(public_v1()).unwrap(), current);
+        assert_eq!(migrate_public(public_v2()).unwrap(), current);
+
+        let data: StoredDataPublic = serde_json::from_value(current).unwrap();
+        assert_eq!(data.entries.len(), 1);
+    }
+
+    #[test]
+    fn adds_version_field() {
+        assert_eq!(add_version_field(public_v0()).unwrap(), public_v0());
+    }
+
+    #[test]
+    fn keys_entries_by_id() {
+        let second_dragon = json!({
+            "pos": [45.76, 4.83],
+            "name": "Lyon dragon",
+            "location_name": null,
+            "image": null,
+            "source_url": null,
+            "source_text": "OpenStreetMap",
+            "is_in_exhibit": false,
+            "nature": null,
+            "element_ids": [{ "Osm": 42 }],
+        });
+        let migrated = key_entries_by_id(json!({
+            "version": 1,
+            "entries": [dragon_entry(), second_dragon.clone()],
+        }))
+        .unwrap();
+        assert_eq!(
+            migrated,
+            json!({
+                "version": 1,
+                "entries": { "osm:42": second_dragon, "osm:42#2": dragon_entry() },
+            })
+        );
+
+        // Nothing to migrate without entries
+        assert_eq!(
+            key_entries_by_id(json!({ "version": 1 })).unwrap(),
+            json!({ "version": 1 })
+        );
+        assert!(key_entries_by_id(json!({ "entries": [{ "name": "no position" }] })).is_err());
+    }
+
+    #[test]
+    fn migrates_private() {
+        let private_v0 = json!({ "last_updated": null, "commons_attributions": {} });
+        let private_v1 = json!({ "version": 1, "last_updated": null, "commons_attributions": {} });
+        let private_v2 = json!({
+            "version": 2,
+            "last_updated": null,
+            "last_successful_update": null,
+            "commons_attributions": {},
+        });
+        let current = json!({
+            "last_updated": null,
+            "last_successful_update": null,
+            "commons_attributions": {},
+        });
+        assert_eq!(migrate_private(private_v0).unwrap(), current);
+        assert_eq!(migrate_private(private_v1).unwrap(), current);
+        assert_eq!(migrate_private(private_v2).unwrap(), current);
+
+        let data: StoredDataPrivate = serde_json::from_value(current).unwrap();
+        assert!(data.last_updated.is_none());
+    }
+
+    #[test]
+    fn adds_last_successful_update() {
+        let last_updated = json!([1_700_000_000, 0]);
+        assert_eq!(
+            add_last_successful_update(json!({ "version": 1, "last_updated": last_updated }))
+                .unwrap(),
+            json!({
+                "version": 1,
+                "last_updated": last_updated,
+                "last_successful_update": last_updated,
+            })
+        );
+        // Never updated
+        assert_eq!(
+            add_last_successful_update(json!({ "version": 1 })).unwrap(),
+            json!({ "version": 1 })
+        );
+    }
+
+    #[test]
+    fn refuses_newer_version() {
+        let error = migrate_public(json!({ "version": PUBLIC_SCHEMA_VERSION + 1, "entries": {} }))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "The storage is at version {}, but this program only supports up to version {}",
+                PUBLIC_SCHEMA_VERSION + 1,
+                PUBLIC_SCHEMA_VERSION
+            )
+        );
+        assert!(migrate_private(json!({ "version": PRIVATE_SCHEMA_VERSION + 1 })).is_err());
+        assert!(migrate_public(json!({ "version": "two" })).is_err());
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, bail};
+use log::info;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction, params};
+use serde_json::{Value, json};
+
+use crate::{
+    FileCommit, MapEntry, StorageBackend,
+    storage::{StoredData, StoredDataPrivate, StoredDataPublic, keyed_entries},
+    storage_migration::{
+        PRIVATE_SCHEMA_VERSION, PUBLIC_SCHEMA_VERSION, Versioned, migrate_private, migrate_public,
+    },
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS entries (
+    source TEXT NOT NULL,
+    element_id TEXT NOT NULL,
+    entry TEXT NOT NULL,
+    PRIMARY KEY (source, element_id)
+);
+CREATE TABLE IF NOT EXISTS private (
+    source TEXT PRIMARY KEY NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS fetch_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    entries_count INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS changes (
+    fetch_id INTEGER NOT NULL REFERENCES fetch_log(id),
+    source TEXT NOT NULL,
+    element_id TEXT NOT NULL,
+    old_entry TEXT,
+    new_entry TEXT
+);
+CREATE INDEX IF NOT EXISTS changes_by_source ON changes (source, fetch_id);
+";
+
+/**
+ * Store every source in a single SQLite database.
+ *
+ * Entries are keyed like in the public JSON files, by their primary `ElementId`. Each save is recorded in `fetch_log`, and the entries it added, removed or modified in `changes`, so older states can be rebuilt.
+ * The database `user_version` is the schema version of the stored entries (the same as the public JSON files).
+ */
+pub struct SqliteBackend {
+    connection: Mutex<Connection>,
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn read_entries(connection: &Connection, source: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut statement =
+        connection.prepare("SELECT element_id, entry FROM entries WHERE source = ?1")?;
+    let rows = statement.query_map(params![source], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Return the id of the fetch of the source with the given revision, or `None` if there is no such fetch
+fn fetch_id_in_log(
+    connection: &Connection,
+    source: &str,
+    revision: &str,
+) -> anyhow::Result<Option<i64>> {
+    let Ok(fetch_id) = revision.parse::<i64>() else {
+        return Ok(None);
+    };
+    Ok(connection
+        .query_row(
+            "SELECT id FROM fetch_log WHERE id = ?1 AND source = ?2",
+            params![fetch_id, source],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// Migrate the stored entries (by key) of a source from the given version to the current one, working on their JSON so that they don’t need to be readable as the current `MapEntry`. The result is keyed like the current version.
+fn migrate_entry_rows(
+    rows: &BTreeMap<String, String>,
+    version: u64,
+) -> anyhow::Result<BTreeMap<String, Value>> {
+    // The entries are laid out like in the public JSON file of the same version
+    let entries = if version < 2 {
+        Value::Array(
+            rows.values()
+                .map(|entry| serde_json::from_str(entry))
+                .collect::<Result<_, _>>()?,
+        )
+    } else {
+        Value::Object(
+            rows.iter()
+                .map(|(key, entry)| Ok((key.clone(), serde_json::from_str(entry)?)))
+                .collect::<anyhow::Result<_>>()?,
+        )
+    };
+    let mut migrated = migrate_public(json!({ "version": version, "entries": entries }))?;
+    match migrated.get_mut("entries").map(Value::take) {
+        Some(Value::Object(entries)) => Ok(entries.into_iter().collect()),
+        _ => bail!("The migrated entries are not keyed"),
+    }
+}
+
+/// Whether two stored entries are the same, even with their fields in a different order (like after a migration)
+fn is_same_entry(old_entry: &str, new_entry: &str) -> bool {
+    old_entry == new_entry
+        || serde_json::from_str::<Value>(old_entry).is_ok_and(|old_entry| {
+            serde_json::from_str::<Value>(new_entry).is_ok_and(|new_entry| old_entry == new_entry)
+        })
+}
+
+fn parse_entries<'a>(
+    entries: impl Iterator<Item = &'a String>,
+) -> anyhow::Result<BTreeSet<MapEntry>> {
+    entries
+        .map(|entry| {
+            serde_json::from_str(entry).with_context(|| format!("Parsing stored entry {entry}"))
+        })
+        .collect()
+}
+
+impl SqliteBackend {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let mut connection = Connection::open(path)
+            .with_context(|| format!("Opening the SQLite storage at {path:?}"))?;
+        connection
+            .execute_batch(SCHEMA)
+            .with_context(|| format!("Creating the tables of {path:?}"))?;
+
+        let version: u64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version < PUBLIC_SCHEMA_VERSION {
+            let transaction = connection.transaction()?;
+            Self::migrate_entries(&transaction, version)
+                .with_context(|| format!("Migrating the entries of {path:?}"))?;
+            transaction.commit()?;
+        } else if version > PUBLIC_SCHEMA_VERSION {
+            bail!(
+                "The storage at {:?} is at version {}, but this program only supports up to version {}",
+                path,
+                version,
+                PUBLIC_SCHEMA_VERSION
+            );
+        }
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Open the storage without ever writing to it. If it does not exist, it is seen as empty. Fail if it needs to be migrated.
+    pub fn open_read_only(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            let connection = Connection::open_in_memory()?;
+            connection.execute_batch(SCHEMA)?;
+            return Ok(Self {
+                connection: Mutex::new(connection),
+            });
+        }
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Opening the SQLite storage at {path:?}"))?;
+        let version: u64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version != PUBLIC_SCHEMA_VERSION {
+            bail!(
+                "The storage at {:?} is at version {}, and needs to be opened with write access to be migrated to version {}",
+                path,
+                version,
+                PUBLIC_SCHEMA_VERSION
+            );
+        }
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Rewrite all the stored entries in the current schema version, without recording it in the history
+    fn migrate_entries(transaction: &Transaction, version: u64) -> anyhow::Result<()> {
+        let sources = {
+            let mut statement = transaction.prepare("SELECT DISTINCT source FROM entries")?;
+            let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+        for source in sources {
+            info!("Migrating the stored entries of {source} from version {version}");
+            let mut entries = read_entries(transaction, &source)?;
+            let mut migrated = migrate_entry_rows(&entries, version)?;
+            transaction.execute("DELETE FROM entries WHERE source = ?1", params![source])?;
+            for (key, entry) in &migrated {
+                transaction.execute(
+                    "INSERT INTO entries (source, element_id, entry) VALUES (?1, ?2, ?3)",
+                    params![source, key, entry.to_string()],
+                )?;
+            }
+
+            // The keys of the changes may have changed too. Undo the fetches one by one, newest first, and record the difference between the migrated entries before and after each of them.
+            let changes = {
+                let mut statement = transaction.prepare(
+                    "SELECT fetch_id, element_id, old_entry FROM changes WHERE source = ?1
+                    ORDER BY fetch_id DESC, rowid DESC",
+                )?;
+                let rows = statement.query_map(params![source], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                    ))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+            let mut migrated_changes = Vec::new();
+            for fetch_changes in changes.chunk_by(|a, b| a.0 == b.0) {
+                for (_, key, old_entry) in fetch_changes {
+                    match old_entry {
+                        Some(old_entry) => entries.insert(key.clone(), old_entry.clone()),
+                        None => entries.remove(key),
+                    };
+                }
+                let migrated_before = migrate_entry_rows(&entries, version)?;
+                let fetch_id = fetch_changes[0].0;
+                let keys: BTreeSet<&String> =
+                    migrated_before.keys().chain(migrated.keys()).collect();
+                for key in keys {
+                    let old_entry = migrated_before.get(key);
+                    let new_entry = migrated.get(key);
+                    if old_entry != new_entry {
+                        migrated_changes.push((
+                            fetch_id,
+                            key.clone(),
+                            old_entry.map(Value::to_string),
+                            new_entry.map(Value::to_string),
+                        ));
+                    }
+                }
+                migrated = migrated_before;
+            }
+            transaction.execute("DELETE FROM changes WHERE source = ?1", params![source])?;
+            for (fetch_id, key, old_entry, new_entry) in migrated_changes.into_iter().rev() {
+                transaction.execute(
+                    "INSERT INTO changes (fetch_id, source, element_id, old_entry, new_entry) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![fetch_id, source, key, old_entry, new_entry],
+                )?;
+            }
+        }
+        transaction.pragma_update(None, "user_version", PUBLIC_SCHEMA_VERSION)?;
+        Ok(())
+    }
+
+    fn lock_connection(&self) -> anyhow::Result<MutexGuard<'_, Connection>> {
+        match self.connection.lock() {
+            Ok(c) => Ok(c),
+            Err(err) => bail!("Failed to get the SQLite connection: {:?}", err),
+        }
+    }
+}
+
+impl StorageBackend for SqliteBackend {
+    fn load(&self, source: &str) -> anyhow::Result<Option<StoredData>> {
+        let connection = self.lock_connection()?;
+        let private: Option<String> = connection
+            .query_row(
+                "SELECT data FROM private WHERE source = ?1",
+                params![source],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let entries = read_entries(&connection, source)?;
+        if private.is_none() && entries.is_empty() {
+            return Ok(None);
+        }
+
+        let private: StoredDataPrivate = match private {
+            Some(private) => {
+                serde_json::from_value(migrate_private(serde_json::from_str(&private)?)?)
+                    .with_context(|| format!("Reading the private data of {source}"))?
+            }
+            None => StoredDataPrivate::default(),
+        };
+        let public = StoredDataPublic {
+            entries: parse_entries(entries.values())?,
+        };
+        Ok(Some(StoredData { private, public }))
+    }
+
+    fn save(&self, source: &str, data: &StoredData, message: &str) -> anyhow::Result<()> {
+        let mut connection = self.lock_connection()?;
+        let transaction = connection.transaction()?;
+
+        let old_entries = read_entries(&transaction, source)?;
+        let mut new_entries = BTreeMap::new();
+        for (key, entry) in keyed_entries(&data.public.entries) {
+            new_entries.insert(key, serde_json::to_string(entry)?);
+        }
+
+        transaction.execute(
+            "INSERT INTO fetch_log (source, time, message, entries_count) VALUES (?1, ?2, ?3, ?4)",
+            params![source, unix_now(), message, new_entries.len() as i64],
+        )?;
+        let fetch_id = transaction.last_insert_rowid();
+
+        for (key, new_entry) in &new_entries {
+            let old_entry = old_entries.get(key);
+            if old_entry.is_some_and(|old_entry| is_same_entry(old_entry, new_entry)) {
+                continue;
+            }
+            transaction.execute(
+                "INSERT OR REPLACE INTO entries (source, element_id, entry) VALUES (?1, ?2, ?3)",
+                params![source, key, new_entry],
+            )?;
+            transaction.execute(
+                "INSERT INTO changes (fetch_id, source, element_id, old_entry, new_entry) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![fetch_id, source, key, old_entry, new_entry],
+            )?;
+        }
+        for (key, old_entry) in &old_entries {
+            if new_entries.contains_key(key) {
+                continue;
+            }
+            transaction.execute(
+                "DELETE FROM entries WHERE source = ?1 AND element_id = ?2",
+                params![source, key],
+            )?;
+            transaction.execute(
+                "INSERT INTO changes (fetch_id, source, element_id, old_entry, new_entry) VALUES (?1, ?2, ?3, ?4, NULL)",
+                params![fetch_id, source, key, old_entry],
+            )?;
+        }
+
+        let private = serde_json::to_string(&Versioned {
+            version: PRIVATE_SCHEMA_VERSION,
+            data: &data.private,
+        })?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO private (source, data) VALUES (?1, ?2)",
+            params![source, private],
+        )?;
+
+        transaction
+            .commit()
+            .with_context(|| format!("Saving {source} in the SQLite storage"))?;
+        Ok(())
+    }
+
+    fn list_sources(&self) -> anyhow::Result<Vec<String>> {
+        let connection = self.lock_connection()?;
+        let mut statement = connection.prepare(
+            "SELECT source FROM private UNION SELECT source FROM entries ORDER BY source",
+        )?;
+        let rows = statement.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Only the fetches that changed something are listed, using the fetch log id as the revision
+    fn history(
+        &self,
+        source: &str,
+        before: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Option<Vec<FileCommit>>> {
+        let connection = self.lock_connection()?;
+        let before = match before {
+            Some(before) => match fetch_id_in_log(&connection, source, before)? {
+                Some(before) => before,
+                None => return Ok(None),
+            },
+            None => i64::MAX,
+        };
+        let mut statement = connection.prepare(
+            "SELECT id, time, message FROM fetch_log WHERE source = ?1 AND id < ?2
+            AND EXISTS (SELECT 1 FROM changes WHERE changes.fetch_id = fetch_log.id)
+            ORDER BY id DESC LIMIT ?3",
+        )?;
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let rows = statement.query_map(params![source, before, limit], |row| {
+            Ok(FileCommit {
+                id: row.get::<_, i64>(0)?.to_string(),
+                time: row.get(1)?,
+                message: row.get(2)?,
+            })
+        })?;
+        Ok(Some(rows.collect::<Result<_, _>>()?))
+    }
+
+    fn entries_at(
+        &self,
+        source: &str,
+        revision: &str,
+    ) -> anyhow::Result<Option<BTreeSet<MapEntry>>> {
+        let connection = self.lock_connection()?;
+        let Some(fetch_id) = fetch_id_in_log(&connection, source, revision)? else {
+            return Ok(None);
+        };
+
+        // Start from the current entries, and undo the later changes, newest first
+        let mut entries = read_entries(&connection, source)?;
+        let mut statement = connection.prepare(
+            "SELECT element_id, old_entry FROM changes WHERE source = ?1 AND fetch_id > ?2
+            ORDER BY fetch_id DESC, rowid DESC",
+        )?;
+        let rows = statement.query_map(params![source, fetch_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?;
+        for row in rows {
+            match row? {
+                (key, Some(old_entry)) => {
+                    entries.insert(key, old_entry);
+                }
+                (key, None) => {
+                    entries.remove(&key);
+                }
+            }
+        }
+        Ok(Some(parse_entries(entries.values())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn entry(id: u64, name: &str) -> String {
+        json!({
+            "pos": null,
+            "name": name,
+            "location_name": null,
+            "image": null,
+            "source_url": null,
+            "source_text": "test",
+            "is_in_exhibit": false,
+            "nature": null,
+            "element_ids": [{ "Osm": id }],
+        })
+        .to_string()
+    }
+
+    fn names(entries: &BTreeSet<MapEntry>) -> Vec<&str> {
+        entries
+            .iter()
+            .filter_map(|entry| entry.name.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn migrates_the_entries_and_their_changes() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("storage.sqlite");
+
+        // At version 1, the entries were not keyed by their primary id
+        {
+            let connection = Connection::open(&path).unwrap();
+            connection.execute_batch(SCHEMA).unwrap();
+            connection.pragma_update(None, "user_version", 1).unwrap();
+            let rows = [
+                (1, "0", entry(1, "a")),
+                (2, "1", entry(1, "b")),
+                (2, "2", entry(2, "c")),
+            ];
+            for fetch_id in [1, 2] {
+                connection
+                    .execute(
+                        "INSERT INTO fetch_log (id, source, time, message, entries_count) VALUES (?1, 'src.json', 0, 'update', 0)",
+                        params![fetch_id],
+                    )
+                    .unwrap();
+            }
+            for (fetch_id, key, entry) in rows {
+                connection
+                    .execute(
+                        "INSERT INTO entries (source, element_id, entry) VALUES ('src.json', ?1, ?2)",
+                        params![key, entry],
+                    )
+                    .unwrap();
+                connection
+                    .execute(
+                        "INSERT INTO changes (fetch_id, source, element_id, old_entry, new_entry) VALUES (?1, 'src.json', ?2, NULL, ?3)",
+                        params![fetch_id, key, entry],
+                    )
+                    .unwrap();
+            }
+        }
+
+        let backend = SqliteBackend::new(&path).unwrap();
+        let data = backend.load("src.json").unwrap().unwrap();
+        assert_eq!(names(&data.public.entries), ["a", "b", "c"]);
+        let stored_keys: Vec<String> = {
+            let connection = backend.lock_connection().unwrap();
+            read_entries(&connection, "src.json")
+                .unwrap()
+                .into_keys()
+                .collect()
+        };
+        let expected_keys: Vec<String> = keyed_entries(&data.public.entries).into_keys().collect();
+        assert_eq!(stored_keys, expected_keys);
+
+        let first = backend.entries_at("src.json", "1").unwrap().unwrap();
+        assert_eq!(names(&first), ["a"]);
+        let second = backend.entries_at("src.json", "2").unwrap().unwrap();
+        assert_eq!(names(&second), ["a", "b", "c"]);
+
+        // The same entries are not seen as changed, even if their fields are not in the same order
+        backend.save("src.json", &data, "same").unwrap();
+        let history = backend.history("src.json", None, 10).unwrap().unwrap();
+        assert_eq!(history.len(), 2);
+    }
+}
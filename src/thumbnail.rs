@@ -0,0 +1,392 @@
+use std::{
+    collections::HashMap,
+    fs::{File, create_dir_all, metadata, read, rename},
+    io::{BufWriter, Cursor, ErrorKind, Read},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use image::{ImageReader, Limits, codecs::jpeg::JpegEncoder};
+use log::info;
+use reqwest::blocking::Client;
+use safe_join::SafeJoin;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{USER_AGENT, commons_metadata::commons_file_name};
+
+/// Remote images bigger than this are not downloaded
+const MAX_DOWNLOAD_SIZE: u64 = 50 * 1024 * 1024;
+
+/// Images wider or higher than this are not decoded, as they could use too much memory
+const MAX_IMAGE_DIMENSION: u32 = 20_000;
+
+/// Numbers the temporary files, so that the same thumbnail can be made by several requests at once
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn default_width() -> u32 {
+    320
+}
+
+fn default_commons_pattern() -> String {
+    "https://commons.wikimedia.org/wiki/Special:FilePath/{file}?width={width}".to_string()
+}
+
+/// How the thumbnails of images that are not local are made
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum RemoteThumbnails {
+    /// Let Wikimedia Commons make them. `{file}` and `{width}` are replaced in the pattern. Other remote images have no thumbnail.
+    CommonsPattern {
+        #[serde(default = "default_commons_pattern")]
+        pattern: String,
+    },
+    /// Download the images once, and resize them like the local ones
+    Download,
+    /// Remote images have no thumbnail
+    Disabled,
+}
+
+impl Default for RemoteThumbnails {
+    fn default() -> Self {
+        Self::CommonsPattern {
+            pattern: default_commons_pattern(),
+        }
+    }
+}
+
+/**
+ * Settings of the reduced images shown in the popups.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThumbnailConfig {
+    /// Maximum width and height of a thumbnail, in pixels
+    #[serde(default = "default_width")]
+    pub width: u32,
+    #[serde(default)]
+    pub remote: RemoteThumbnails,
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            width: default_width(),
+            remote: RemoteThumbnails::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ThumbnailSource {
+    Local(PathBuf),
+    Remote(String),
+}
+
+/**
+ * Make and cache the thumbnails of the images, served at `/thumbnails/{key}.jpg`. They are only made when first requested, and only for images that were given a thumbnail url.
+ */
+pub struct Thumbnailer {
+    config: ThumbnailConfig,
+    images_path: PathBuf,
+    cache_path: PathBuf,
+    /// The image of each key given out, so only those can be requested
+    sources: Mutex<HashMap<String, ThumbnailSource>>,
+    client: Client,
+    /// Whether the remote images can be downloaded, in the `Download` mode
+    downloads: bool,
+}
+
+impl Thumbnailer {
+    pub fn new(
+        config: ThumbnailConfig,
+        images_path: PathBuf,
+        cache_path: PathBuf,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            config,
+            images_path,
+            cache_path,
+            sources: Mutex::new(HashMap::new()),
+            client: Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(Duration::from_secs(60))
+                .build()?,
+            downloads: true,
+        })
+    }
+
+    /// Never download the remote images, so the network is not used. They have no thumbnail in the `Download` mode.
+    pub fn without_downloads(mut self) -> Self {
+        self.downloads = false;
+        self
+    }
+
+    fn register(&self, image_url: &str, source: ThumbnailSource) -> Option<String> {
+        let key = Sha256::digest(image_url.as_bytes())
+            .iter()
+            .take(16)
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        self.sources.lock().ok()?.insert(key.clone(), source);
+        Some(format!("/thumbnails/{key}.jpg"))
+    }
+
+    /// Return the url of the thumbnail of the image, if one can be made
+    pub fn thumbnail_url(&self, image_url: &str) -> Option<String> {
+        if let Some(local_image) = image_url.strip_prefix("/images/") {
+            let path = self.images_path.safe_join(local_image).ok()?;
+            return self.register(image_url, ThumbnailSource::Local(path));
+        }
+        match &self.config.remote {
+            RemoteThumbnails::CommonsPattern { pattern } => {
+                let file = commons_file_name(image_url)?;
+                Some(
+                    pattern
+                        .replace("{file}", &file)
+                        .replace("{width}", &self.config.width.to_string()),
+                )
+            }
+            RemoteThumbnails::Download if self.downloads => {
+                self.register(image_url, ThumbnailSource::Remote(image_url.to_string()))
+            }
+            RemoteThumbnails::Download | RemoteThumbnails::Disabled => None,
+        }
+    }
+
+    /// Return the path of the thumbnail with the given key, making it if it is not yet in the cache. Return `None` for unknown keys.
+    pub fn get_thumbnail(&self, key: &str) -> anyhow::Result<Option<PathBuf>> {
+        let source = match self.sources.lock() {
+            Ok(sources) => sources.get(key).cloned(),
+            Err(err) => bail!("Failed to get the thumbnail sources: {:?}", err),
+        };
+        let Some(source) = source else {
+            return Ok(None);
+        };
+
+        // Remote images are assumed to never change
+        let source_modified = match &source {
+            ThumbnailSource::Local(path) => match metadata(path)
+                .and_then(|metadata| metadata.modified())
+            {
+                Ok(modified) => Some(modified),
+                // The image was removed since the entries were built
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(err) => {
+                    return Err(err).with_context(|| format!("Reading the metadata of {path:?}"));
+                }
+            },
+            ThumbnailSource::Remote(_) => None,
+        };
+        let thumbnail_path = self.cache_path.join(format!("{key}.jpg"));
+        let cached_modified = metadata(&thumbnail_path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let up_to_date = match (source_modified, cached_modified) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(source_modified), Some(cached_modified)) => source_modified <= cached_modified,
+        };
+        if !up_to_date {
+            let content = self.read_source(&source)?;
+            self.make_thumbnail(&content, &thumbnail_path)
+                .with_context(|| format!("Making the thumbnail of {source:?}"))?;
+        }
+        Ok(Some(thumbnail_path))
+    }
+
+    fn read_source(&self, source: &ThumbnailSource) -> anyhow::Result<Vec<u8>> {
+        match source {
+            ThumbnailSource::Local(path) => {
+                read(path).with_context(|| format!("Reading image at {path:?}"))
+            }
+            ThumbnailSource::Remote(url) => {
+                info!("Downloading {url} to make its thumbnail");
+                let response = self
+                    .client
+                    .get(url)
+                    .send()
+                    .and_then(|response| response.error_for_status())
+                    .with_context(|| format!("Downloading {url}"))?;
+                if let Some(length) = response.content_length()
+                    && length > MAX_DOWNLOAD_SIZE
+                {
+                    bail!("The image at {url} is too big ({length} bytes)");
+                }
+                let mut content = Vec::new();
+                response
+                    .take(MAX_DOWNLOAD_SIZE + 1)
+                    .read_to_end(&mut content)
+                    .with_context(|| format!("Downloading {url}"))?;
+                if content.len() as u64 > MAX_DOWNLOAD_SIZE {
+                    bail!("The image at {url} is bigger than {MAX_DOWNLOAD_SIZE} bytes");
+                }
+                Ok(content)
+            }
+        }
+    }
+
+    fn make_thumbnail(&self, content: &[u8], thumbnail_path: &Path) -> anyhow::Result<()> {
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+        limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+        let mut reader = ImageReader::new(Cursor::new(content))
+            .with_guessed_format()
+            .context("Reading the image format")?;
+        reader.limits(limits);
+        let mut image = reader.decode().context("Decoding the image")?;
+        if image.width() > self.config.width || image.height() > self.config.width {
+            image = image.thumbnail(self.config.width, self.config.width);
+        }
+
+        create_dir_all(&self.cache_path)
+            .with_context(|| format!("Could not create dir at {:?}", self.cache_path))?;
+        let temp_path = thumbnail_path.with_extension(format!(
+            "jpg.{}-{}.tmp",
+            process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        {
+            let f = File::create(&temp_path)
+                .with_context(|| format!("Could not create/truncate file at {temp_path:?}"))?;
+            let mut encoder = JpegEncoder::new_with_quality(BufWriter::new(f), 85);
+            encoder
+                .encode_image(&image.to_rgb8())
+                .with_context(|| format!("Could not write to {temp_path:?}"))?;
+        }
+        rename(&temp_path, thumbnail_path)
+            .with_context(|| format!("Could not replace {thumbnail_path:?}"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{read_dir, remove_file},
+        thread,
+    };
+
+    use image::{ImageFormat, RgbImage};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn local_thumbnails() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let images_path = dir.join("images");
+        create_dir_all(&images_path).unwrap();
+        RgbImage::new(640, 480)
+            .save_with_format(images_path.join("dragon.png"), ImageFormat::Png)
+            .unwrap();
+        RgbImage::new(MAX_IMAGE_DIMENSION + 1, 1)
+            .save_with_format(images_path.join("long.png"), ImageFormat::Png)
+            .unwrap();
+        let thumbnailer = Thumbnailer::new(
+            ThumbnailConfig::default(),
+            images_path.clone(),
+            dir.join("thumbnails"),
+        )
+        .unwrap();
+
+        let url = thumbnailer.thumbnail_url("/images/dragon.png").unwrap();
+        let key = url
+            .strip_prefix("/thumbnails/")
+            .and_then(|file| file.strip_suffix(".jpg"))
+            .unwrap();
+        let path = thumbnailer.get_thumbnail(key).unwrap().unwrap();
+        let thumbnail = image::open(&path).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (320, 240));
+
+        // Removed since the url was given
+        remove_file(images_path.join("dragon.png")).unwrap();
+        assert!(thumbnailer.get_thumbnail(key).unwrap().is_none());
+        assert!(thumbnailer.get_thumbnail("unknown").unwrap().is_none());
+
+        let url = thumbnailer.thumbnail_url("/images/long.png").unwrap();
+        let key = url
+            .strip_prefix("/thumbnails/")
+            .and_then(|file| file.strip_suffix(".jpg"))
+            .unwrap();
+        assert!(thumbnailer.get_thumbnail(key).is_err());
+    }
+
+    #[test]
+    fn concurrent_thumbnails() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let images_path = dir.join("images");
+        create_dir_all(&images_path).unwrap();
+        RgbImage::new(1280, 960)
+            .save_with_format(images_path.join("dragon.png"), ImageFormat::Png)
+            .unwrap();
+        let thumbnailer = Thumbnailer::new(
+            ThumbnailConfig::default(),
+            images_path,
+            dir.join("thumbnails"),
+        )
+        .unwrap();
+        let url = thumbnailer.thumbnail_url("/images/dragon.png").unwrap();
+        let key = url
+            .strip_prefix("/thumbnails/")
+            .and_then(|file| file.strip_suffix(".jpg"))
+            .unwrap();
+
+        // Each request writes its own temporary file, so none of them fails
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| thumbnailer.get_thumbnail(key)))
+                .collect();
+            for handle in handles {
+                let path = handle.join().unwrap().unwrap().unwrap();
+                assert_eq!(image::open(&path).unwrap().width(), 320);
+            }
+        });
+        let files: Vec<_> = read_dir(dir.join("thumbnails"))
+            .unwrap()
+            .map(|file| file.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(files, [format!("{key}.jpg")]);
+    }
+
+    #[test]
+    fn remote_thumbnails() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let config = ThumbnailConfig {
+            width: 320,
+            remote: RemoteThumbnails::Download,
+        };
+        let image_url = "https://example.org/dragon.jpg";
+
+        let thumbnailer =
+            Thumbnailer::new(config.clone(), dir.join("images"), dir.join("thumbnails")).unwrap();
+        assert!(thumbnailer.thumbnail_url(image_url).is_some());
+
+        let thumbnailer = Thumbnailer::new(config, dir.join("images"), dir.join("thumbnails"))
+            .unwrap()
+            .without_downloads();
+        assert!(thumbnailer.thumbnail_url(image_url).is_none());
+
+        let thumbnailer = Thumbnailer::new(
+            ThumbnailConfig::default(),
+            dir.join("images"),
+            dir.join("thumbnails"),
+        )
+        .unwrap()
+        .without_downloads();
+        assert_eq!(
+            thumbnailer
+                .thumbnail_url("https://commons.wikimedia.org/wiki/Special:FilePath/Dragon.jpg")
+                .as_deref(),
+            Some("https://commons.wikimedia.org/wiki/Special:FilePath/Dragon.jpg?width=320")
+        );
+    }
+}
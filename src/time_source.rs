@@ -0,0 +1,211 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+use tai_time::TaiTime;
+
+/// TAI-UTC offset, in seconds, effective from the given UTC Unix timestamp.
+/// To be updated when the IERS announce a new leap second.
+const LEAP_SECONDS: &[(i64, i64)] = &[
+    (63072000, 10),   // 1972-01-01
+    (78796800, 11),   // 1972-07-01
+    (94694400, 12),   // 1973-01-01
+    (126230400, 13),  // 1974-01-01
+    (157766400, 14),  // 1975-01-01
+    (189302400, 15),  // 1976-01-01
+    (220924800, 16),  // 1977-01-01
+    (252460800, 17),  // 1978-01-01
+    (283996800, 18),  // 1979-01-01
+    (315532800, 19),  // 1980-01-01
+    (362793600, 20),  // 1981-07-01
+    (394329600, 21),  // 1982-07-01
+    (425865600, 22),  // 1983-07-01
+    (489024000, 23),  // 1985-07-01
+    (567993600, 24),  // 1988-01-01
+    (631152000, 25),  // 1990-01-01
+    (662688000, 26),  // 1991-01-01
+    (709948800, 27),  // 1992-07-01
+    (741484800, 28),  // 1993-07-01
+    (773020800, 29),  // 1994-07-01
+    (820454400, 30),  // 1996-01-01
+    (867715200, 31),  // 1997-07-01
+    (915148800, 32),  // 1999-01-01
+    (1136073600, 33), // 2006-01-01
+    (1230768000, 34), // 2009-01-01
+    (1341100800, 35), // 2012-07-01
+    (1435708800, 36), // 2015-07-01
+    (1483228800, 37), // 2017-01-01
+];
+
+/// Return the TAI-UTC offset in effect at the given UTC Unix timestamp.
+pub fn leap_seconds_at(unix_secs: i64) -> i64 {
+    LEAP_SECONDS
+        .iter()
+        .rev()
+        .find(|(start, _)| *start <= unix_secs)
+        .map(|(_, offset)| *offset)
+        .unwrap_or(0)
+}
+
+/// Convert an UTC system time to TAI using the leap second table.
+pub fn tai_from_system_time(system_time: SystemTime) -> TaiTime<0> {
+    let unix_secs = match system_time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    };
+    TaiTime::from_system_time(&system_time, leap_seconds_at(unix_secs))
+}
+
+/// Convert a TAI time back to an UTC Unix timestamp (in seconds), using the leap second table.
+pub fn unix_secs_from_tai(time: TaiTime<0>) -> i64 {
+    let tai_secs = time.as_secs();
+    let offset = LEAP_SECONDS
+        .iter()
+        .rev()
+        .find(|(start, offset)| start + offset <= tai_secs)
+        .map(|(_, offset)| *offset)
+        .unwrap_or(0);
+    tai_secs - offset
+}
+
+/// Format an UTC Unix timestamp as RFC 3339 (like `2024-05-01T12:00:00Z`)
+pub fn format_rfc3339(unix_secs: i64) -> String {
+    // Based on Howard Hinnant’s civil_from_days algorithm
+    let days = unix_secs.div_euclid(86400);
+    let secs_of_day = unix_secs.rem_euclid(86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/**
+ * Something that can tell the current time. Allow to replace the system clock (for example in tests).
+ */
+pub trait TimeSource {
+    fn now(&self) -> TaiTime<0>;
+}
+
+/// Use the system TAI clock, falling back to the UTC system clock (converted with the leap second table) if it is not available.
+#[derive(Default)]
+pub struct SystemTimeSource {
+    degraded: AtomicBool,
+}
+
+impl SystemTimeSource {
+    /// True if the last reading had to fall back to the UTC system clock
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+}
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> TaiTime<0> {
+        match TaiTime::try_now() {
+            Ok(t) => {
+                if self.degraded.swap(false, Ordering::Relaxed) {
+                    info!("The TAI clock is available again, leaving degraded time mode");
+                }
+                t
+            }
+            Err(err) => {
+                if !self.degraded.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "Could not get the current TAI time ({err:?}). Entering degraded time mode, using the UTC system clock and a leap second table instead."
+                    );
+                }
+                tai_from_system_time(SystemTime::now())
+            }
+        }
+    }
+}
+
+/// A clock that only move when told to. Useful to test the update scheduling.
+pub struct FakeTimeSource {
+    time: Mutex<TaiTime<0>>,
+}
+
+impl FakeTimeSource {
+    pub fn new(time: TaiTime<0>) -> Self {
+        Self {
+            time: Mutex::new(time),
+        }
+    }
+
+    pub fn set(&self, time: TaiTime<0>) {
+        *self.time.lock().unwrap() = time;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut time = self.time.lock().unwrap();
+        *time += duration;
+    }
+}
+
+impl TimeSource for FakeTimeSource {
+    fn now(&self) -> TaiTime<0> {
+        *self.time.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leap_seconds_follow_the_table() {
+        assert_eq!(leap_seconds_at(0), 0);
+        assert_eq!(leap_seconds_at(63072000), 10);
+        // Last second before the 2017 leap second
+        assert_eq!(leap_seconds_at(1483228799), 36);
+        assert_eq!(leap_seconds_at(1483228800), 37);
+    }
+
+    #[test]
+    fn tai_round_trip() {
+        for unix_secs in [63072000, 1435708799, 1435708800, 1714521600] {
+            let tai = tai_from_system_time(UNIX_EPOCH + Duration::from_secs(unix_secs));
+            assert_eq!(
+                tai.as_secs(),
+                unix_secs as i64 + leap_seconds_at(unix_secs as i64)
+            );
+            assert_eq!(unix_secs_from_tai(tai), unix_secs as i64);
+        }
+    }
+
+    #[test]
+    fn fake_time_source_only_moves_when_told() {
+        let start = tai_from_system_time(UNIX_EPOCH + Duration::from_secs(1714521600));
+        let clock = FakeTimeSource::new(start);
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(unix_secs_from_tai(clock.now()), 1714521690);
+        clock.set(start);
+        assert_eq!(clock.now(), start);
+    }
+
+    #[test]
+    fn format_rfc3339_dates() {
+        assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_rfc3339(1714521600 + 3723), "2024-05-01T01:02:03Z");
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    sync::{
+        Arc,
+        mpsc::{Sender, channel},
+    },
+    thread::{self, sleep},
+    time::Duration,
+};
+
+use anyhow::bail;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{DepictionCategory, ElementId, EntryDiff, USER_AGENT};
+
+/// Header containing the HMAC-SHA256 of the body, when a secret is configured
+pub const SIGNATURE_HEADER: &str = "X-Depiction-Map-Signature";
+
+fn default_retries() -> u32 {
+    3
+}
+
+/**
+ * An URL to post to when the entries of a source change.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// If set, the body is signed with HMAC-SHA256 using this secret, and the signature put in the `X-Depiction-Map-Signature` header as `sha256=<hex>`
+    pub secret: Option<String>,
+    /// If set, only notify for sources depicting one of those categories
+    pub categories: Option<Vec<DepictionCategory>>,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+impl WebhookTarget {
+    fn is_interested_in(&self, payload: &WebhookPayload) -> bool {
+        match &self.categories {
+            Some(categories) => payload.categories.iter().any(|c| categories.contains(c)),
+            None => true,
+        }
+    }
+}
+
+/// The JSON body sent to the webhook targets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub source: String,
+    pub title: String,
+    pub categories: Vec<DepictionCategory>,
+    pub added_count: usize,
+    pub removed_count: usize,
+    pub changed_count: usize,
+    pub added: Vec<ElementId>,
+    pub removed: Vec<ElementId>,
+    pub changed: Vec<ElementId>,
+}
+
+impl WebhookPayload {
+    pub fn new(
+        source: String,
+        title: String,
+        categories: Vec<DepictionCategory>,
+        diff: &EntryDiff,
+    ) -> Self {
+        Self {
+            source,
+            title,
+            categories,
+            added_count: diff.added.len(),
+            removed_count: diff.removed.len(),
+            changed_count: diff.changed.len(),
+            added: diff
+                .added
+                .iter()
+                .filter_map(|e| e.primary_id().cloned())
+                .collect(),
+            removed: diff
+                .removed
+                .iter()
+                .filter_map(|e| e.primary_id().cloned())
+                .collect(),
+            changed: diff.changed.iter().map(|c| c.id.clone()).collect(),
+        }
+    }
+}
+
+/// Return the value of the signature header for the given body
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accept keys of any size");
+    mac.update(body);
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256={signature}")
+}
+
+fn post(client: &Client, target: &WebhookTarget, body: &str) -> anyhow::Result<()> {
+    let mut request = client
+        .post(&target.url)
+        .header("Content-Type", "application/json")
+        .body(body.to_string());
+    if let Some(secret) = &target.secret {
+        request = request.header(SIGNATURE_HEADER, sign_payload(secret, body.as_bytes()));
+    }
+    let response = request.send()?;
+    if !response.status().is_success() {
+        bail!("Webhook answered with status code {}", response.status());
+    }
+    Ok(())
+}
+
+fn deliver(client: &Client, target: &WebhookTarget, body: &str) {
+    for attempt in 0..=target.retries {
+        if attempt > 0 {
+            sleep(Duration::from_secs(2u64.pow(attempt)));
+        }
+        match post(client, target, body) {
+            Ok(()) => {
+                info!("Notified webhook {}", target.url);
+                return;
+            }
+            Err(err) => warn!(
+                "Webhook attempt {} of {} to {} failed: {:#}",
+                attempt + 1,
+                target.retries + 1,
+                target.url,
+                err
+            ),
+        }
+    }
+    error!("Giving up notifying webhook {}", target.url);
+}
+
+/// A target, and the queue of the bodies to send it
+struct TargetQueue {
+    target: WebhookTarget,
+    sender: Sender<Arc<String>>,
+}
+
+/**
+ * Send the payloads to the webhook targets from background threads, so slow targets don’t delay updates. Each target has its own thread, so one that is down (and retried) doesn’t delay the others either.
+ */
+pub struct WebhookNotifier {
+    queues: Vec<TargetQueue>,
+}
+
+impl WebhookNotifier {
+    pub fn new(targets: Vec<WebhookTarget>) -> anyhow::Result<Self> {
+        let client = Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        let mut queues = Vec::new();
+        for target in targets {
+            let (sender, receiver) = channel::<Arc<String>>();
+            let client = client.clone();
+            let thread_target = target.clone();
+            thread::spawn(move || {
+                for body in receiver {
+                    deliver(&client, &thread_target, &body);
+                }
+            });
+            queues.push(TargetQueue { target, sender });
+        }
+        Ok(Self { queues })
+    }
+
+    pub fn notify(&self, payload: WebhookPayload) {
+        if self.queues.is_empty() {
+            return;
+        }
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => Arc::new(body),
+            Err(err) => {
+                error!("Could not serialize webhook payload: {err:?}");
+                return;
+            }
+        };
+        for queue in &self.queues {
+            if queue.target.is_interested_in(&payload) && queue.sender.send(body.clone()).is_err() {
+                error!(
+                    "The webhook thread of {} stopped, can’t send notification",
+                    queue.target.url
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::mpsc::Receiver,
+    };
+
+    use super::*;
+
+    /// The headers (with their name in lower case) and body of a request
+    type Request = (Vec<(String, String)>, String);
+
+    /// Return the url of a listener that answers the first request made to it, and sends it to the receiver
+    fn listen_once() -> (String, Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    headers.push((name.to_lowercase(), value.trim().to_string()));
+                }
+            }
+            let length = headers
+                .iter()
+                .find(|(name, _)| name == "content-length")
+                .map(|(_, value)| value.parse().unwrap())
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            sender
+                .send((headers, String::from_utf8(body).unwrap()))
+                .unwrap();
+        });
+        (url, receiver)
+    }
+
+    fn payload(categories: Vec<DepictionCategory>) -> WebhookPayload {
+        WebhookPayload::new(
+            "osm_dragon.json".to_string(),
+            "Dragons".to_string(),
+            categories,
+            &EntryDiff::default(),
+        )
+    }
+
+    #[test]
+    fn signature_is_hmac_sha256() {
+        // From RFC 4231, test case 2
+        assert_eq!(
+            sign_payload("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn delivers_signed_payload() {
+        let (url, receiver) = listen_once();
+        let notifier = WebhookNotifier::new(vec![WebhookTarget {
+            url,
+            secret: Some("secret".to_string()),
+            categories: None,
+            retries: 0,
+        }])
+        .unwrap();
+        notifier.notify(payload(vec![DepictionCategory::dragon()]));
+
+        let (headers, body) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        let signature = headers
+            .iter()
+            .find(|(name, _)| *name == SIGNATURE_HEADER.to_lowercase())
+            .map(|(_, value)| value.as_str());
+        assert_eq!(
+            signature,
+            Some(sign_payload("secret", body.as_bytes()).as_str())
+        );
+        let received: WebhookPayload = serde_json::from_str(&body).unwrap();
+        assert_eq!(received.source, "osm_dragon.json");
+    }
+
+    #[test]
+    fn failing_target_does_not_delay_the_others() {
+        // Nothing listens on the port once this listener is dropped
+        let closed_url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/hook", listener.local_addr().unwrap())
+        };
+        let (url, receiver) = listen_once();
+        let notifier = WebhookNotifier::new(vec![
+            WebhookTarget {
+                url: closed_url,
+                secret: None,
+                categories: None,
+                retries: 10,
+            },
+            WebhookTarget {
+                url,
+                secret: None,
+                categories: None,
+                retries: 0,
+            },
+        ])
+        .unwrap();
+        notifier.notify(payload(vec![DepictionCategory::dragon()]));
+
+        let (headers, _) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(
+            !headers
+                .iter()
+                .any(|(name, _)| *name == SIGNATURE_HEADER.to_lowercase())
+        );
+    }
+}
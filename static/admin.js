@@ -0,0 +1,369 @@
+function updateStatus(content) {
+  document.getElementById("status").textContent = "status: " + content;
+}
+
+updateStatus("Loading web page");
+
+let map = L.map("map").setView([12.9, 15.3], 3);
+
+L.tileLayer("https://tile.openstreetmap.org/{z}/{x}/{y}.png", {
+  maxZoom: 19,
+  attribution:
+    '&copy; <a href="http://www.openstreetmap.org/copyright">OpenStreetMap</a>',
+}).addTo(map);
+
+let overrides = null;
+let existing_layer = null;
+let selected_marker = null;
+let selected_id = null;
+let selected_entry = null;
+// The category of the displayed entries
+let loaded_category = null;
+
+document.getElementById("token").value =
+  localStorage.getItem("admin_token") || "";
+
+function save_token() {
+  localStorage.setItem("admin_token", document.getElementById("token").value);
+  load_data();
+}
+
+function auth_headers() {
+  return {
+    Authorization: "Bearer " + localStorage.getItem("admin_token"),
+  };
+}
+
+// Same format as the Display implementation of ElementId on the server
+function element_id_to_string(element_id) {
+  const kind = Object.keys(element_id)[0];
+  if (kind == "External") {
+    return "external:" + element_id[kind].namespace + ":" + element_id[kind].id;
+  }
+  return kind.toLowerCase() + ":" + element_id[kind];
+}
+
+// The object of the set holding the overrides of this kind of id, and the key
+// of the id in it. The external ids are grouped by namespace.
+function override_section(set, id_string, create) {
+  const [kind, ...rest] = id_string.split(":");
+  const path = kind == "external" ? [kind, rest.shift()] : [kind];
+  let section = set;
+  for (const key of path) {
+    if (section[key] == null) {
+      if (!create) {
+        return [null, null];
+      }
+      section[key] = {};
+    }
+    section = section[key];
+  }
+  return [section, rest.join(":")];
+}
+
+// The overrides edited with the current scope: the global ones, or those of
+// the loaded category
+function scoped_overrides(create) {
+  if (document.getElementById("scope").value == "") {
+    return overrides;
+  }
+  if (overrides["categories"] == null) {
+    overrides["categories"] = {};
+  }
+  if (overrides["categories"][loaded_category] == null) {
+    if (!create) {
+      return {};
+    }
+    overrides["categories"][loaded_category] = {};
+  }
+  return overrides["categories"][loaded_category];
+}
+
+function get_override(id_string) {
+  const [section, key] = override_section(
+    scoped_overrides(false),
+    id_string,
+    false,
+  );
+  if (section == null) {
+    return null;
+  }
+  return section[key] || null;
+}
+
+async function check_response(response) {
+  if (!response.ok) {
+    throw new Error(response.status + ": " + (await response.text()));
+  }
+  return response;
+}
+
+async function load_data() {
+  try {
+    updateStatus("fetching datas");
+    const category = document.getElementById("category").value;
+    loaded_category = category;
+    const entries = await (
+      await check_response(
+        await fetch("/depiction/" + encodeURIComponent(category) + ".json"),
+      )
+    ).json();
+    overrides = await (
+      await check_response(
+        await fetch("/admin/overrides.json", { headers: auth_headers() }),
+      )
+    ).json();
+    updateStatus("processing...");
+    display_entries(entries);
+    display_hidden_list();
+    updateStatus("ready to use!");
+  } catch (err) {
+    updateStatus("failure: " + err.message);
+  }
+}
+
+function display_entries(entries) {
+  if (existing_layer != null) {
+    map.removeLayer(existing_layer);
+  }
+  existing_layer = L.markerClusterGroup({
+    maxClusterRadius: 30,
+  });
+
+  for (const entry of entries) {
+    if (entry["pos"] == null || entry["element_ids"].length == 0) {
+      continue;
+    }
+    const marker = L.marker(entry["pos"], {});
+    marker.on("click", function () {
+      select_entry(entry, marker);
+    });
+    existing_layer.addLayer(marker);
+  }
+
+  map.addLayer(existing_layer);
+}
+
+function display_hidden_list() {
+  const list = document.getElementById("hidden_list");
+  list.replaceChildren();
+  const sets = [overrides];
+  const categories = overrides["categories"] || {};
+  if (categories[loaded_category] != null) {
+    sets.push(categories[loaded_category]);
+  }
+  for (const set of sets) {
+    add_hidden_entries(list, set, set !== overrides);
+  }
+}
+
+function add_hidden_entries(list, set, in_category) {
+  const sections = [];
+  for (const kind of ["osm", "wikidata", "local", "commons"]) {
+    sections.push([kind + ":", set[kind] || {}]);
+  }
+  for (const [namespace, section] of Object.entries(set["external"] || {})) {
+    sections.push(["external:" + namespace + ":", section]);
+  }
+  for (const [prefix, section] of sections) {
+    for (const [id, override_entry] of Object.entries(section)) {
+      if (!override_entry["hidden"]) {
+        continue;
+      }
+      const id_string = prefix + id;
+      const item = document.createElement("li");
+      const link = document.createElement("a");
+      link.setAttribute("href", "#");
+      link.textContent =
+        (override_entry["name"] || id_string) +
+        (in_category ? " (only in " + loaded_category + ")" : "");
+      link.onclick = function () {
+        document.getElementById("scope").value = in_category ? "category" : "";
+        select_entry(null, null, id_string);
+        return false;
+      };
+      item.appendChild(link);
+      list.appendChild(item);
+    }
+  }
+}
+
+function set_field(name, value, placeholder) {
+  const field = document.getElementById("field_" + name);
+  field.value = value == null ? "" : value;
+  field.placeholder = placeholder == null ? "" : placeholder;
+}
+
+// entry is the displayed entry (with the overrides applied), if it is displayed
+function select_entry(entry, marker, id_string) {
+  if (selected_marker != null) {
+    selected_marker.dragging.disable();
+  }
+  selected_marker = marker;
+  selected_entry = entry;
+
+  if (id_string == null) {
+    // Edit the existing override if there is one for any of its ids
+    const ids = entry["element_ids"].map(element_id_to_string);
+    id_string = ids.find((id) => get_override(id) != null) || ids[0];
+  }
+  selected_id = id_string;
+  const override_entry = get_override(id_string) || {};
+  entry = entry || {};
+
+  document.getElementById("editor").hidden = false;
+  document.getElementById("editor_title").textContent =
+    entry["name"] || override_entry["name"] || "no/unknown name";
+  document.getElementById("editor_id").textContent = id_string;
+  const source_link = document.getElementById("editor_source");
+  source_link.textContent = entry["source_text"] || "";
+  source_link.setAttribute("href", safeUrl(entry["source_url"]));
+
+  set_field("name", override_entry["name"], entry["name"]);
+  set_field("nature", override_entry["nature"], entry["nature"]);
+  const pos = override_entry["pos"];
+  set_field("lat", pos && pos[0], entry["pos"] && entry["pos"][0]);
+  set_field("lon", pos && pos[1], entry["pos"] && entry["pos"][1]);
+  const is_in_exhibit = override_entry["is_in_exhibit"];
+  set_field(
+    "is_in_exhibit",
+    is_in_exhibit == null ? "" : String(is_in_exhibit),
+  );
+  set_field("local_image", override_entry["local_image"]);
+  set_field(
+    "image_source_url",
+    override_entry["image_source_url"],
+    entry["image"] && entry["image"]["credit_url"],
+  );
+  set_field(
+    "image_source_text",
+    override_entry["image_source_text"],
+    entry["image"] && entry["image"]["credit_text"],
+  );
+  document.getElementById("field_hidden").checked =
+    override_entry["hidden"] == true;
+
+  const preview = document.getElementById("preview_image");
+  if (entry["image"] != null) {
+    preview.src = safeUrl(
+      entry["image"]["thumbnail_url"] || entry["image"]["url"],
+    );
+    preview.hidden = false;
+  } else {
+    preview.hidden = true;
+  }
+
+  if (marker != null) {
+    marker.dragging.enable();
+    marker.off("dragend");
+    marker.on("dragend", function () {
+      const position = marker.getLatLng();
+      set_field("lat", position.lat);
+      set_field("lon", position.lng);
+    });
+  }
+}
+
+// Show the override of the selected entry for the newly selected scope
+function reselect_entry() {
+  if (selected_entry != null) {
+    select_entry(selected_entry, selected_marker);
+  } else if (selected_id != null) {
+    select_entry(null, null, selected_id);
+  }
+}
+
+function field_value(name) {
+  const value = document.getElementById("field_" + name).value.trim();
+  return value == "" ? null : value;
+}
+
+async function send_override(method, body) {
+  try {
+    updateStatus("saving");
+    const headers = auth_headers();
+    if (body != null) {
+      headers["Content-Type"] = "application/json";
+    }
+    let url = "/admin/overrides/" + encodeURIComponent(selected_id);
+    if (document.getElementById("scope").value != "") {
+      url += "?category=" + encodeURIComponent(loaded_category);
+    }
+    await check_response(
+      await fetch(url, {
+        method: method,
+        headers: headers,
+        body: body == null ? null : JSON.stringify(body),
+      }),
+    );
+    updateStatus("saved, the map will be updated shortly");
+    // The server only applies it a few seconds later
+    const [section, key] = override_section(
+      scoped_overrides(true),
+      selected_id,
+      true,
+    );
+    if (body == null) {
+      delete section[key];
+    } else {
+      section[key] = body;
+    }
+    display_hidden_list();
+  } catch (err) {
+    updateStatus("failure: " + err.message);
+  }
+}
+
+function save_override() {
+  const override_entry = {
+    name: field_value("name"),
+    nature: field_value("nature"),
+    local_image: field_value("local_image"),
+    image_source_url: field_value("image_source_url"),
+    image_source_text: field_value("image_source_text"),
+    hidden: document.getElementById("field_hidden").checked,
+  };
+  const lat = field_value("lat");
+  const lon = field_value("lon");
+  if (lat != null && lon != null) {
+    override_entry["pos"] = [parseFloat(lat), parseFloat(lon)];
+  }
+  const is_in_exhibit = field_value("is_in_exhibit");
+  if (is_in_exhibit != null) {
+    override_entry["is_in_exhibit"] = is_in_exhibit == "true";
+  }
+  send_override("PUT", override_entry);
+}
+
+function delete_override() {
+  if (confirm("Remove the override of " + selected_id + "?")) {
+    send_override("DELETE", null);
+  }
+}
+
+async function upload_image() {
+  const file = document.getElementById("upload_image").files[0];
+  if (file == null) {
+    return;
+  }
+  try {
+    updateStatus("uploading " + file.name);
+    await check_response(
+      await fetch("/admin/images/" + encodeURIComponent(file.name), {
+        method: "PUT",
+        headers: auth_headers(),
+        body: file,
+      }),
+    );
+    set_field("local_image", file.name);
+    updateStatus("uploaded, save to use it");
+  } catch (err) {
+    updateStatus("failure: " + err.message);
+  }
+}
+
+if (localStorage.getItem("admin_token") != null) {
+  load_data();
+} else {
+  updateStatus("log in to start");
+}
@@ -120,13 +120,18 @@ function refresh_map() {
     }
 
     // image
-    //TODO: (more server-side) some images are TIF that doesn’t display in browser
     if (entry["image"] != null) {
       imageURL = entry["image"]["url"];
       imageCreditUrl = entry["image"]["credit_url"];
+      // The thumbnail is lighter, and also displayable when the original is a TIF
+      thumbnailURL = entry["image"]["thumbnail_url"] || imageURL;
 
       popupHTML +=
-        '<img src="' + escapeHtml(imageURL) + '" class="embed-image"/><br />';
+        '<a href="' +
+        escapeHtml(safeUrl(imageURL)) +
+        '" target="_blank"><img src="' +
+        escapeHtml(safeUrl(thumbnailURL)) +
+        '" class="embed-image"/></a><br />';
       imageSourceText = entry["image"]["credit_text"];
 
       if (imageCreditUrl != null) {
@@ -135,7 +140,7 @@ function refresh_map() {
         }
         popupHTML +=
           '<p><a href="' +
-          escapeHtml(imageCreditUrl) +
+          escapeHtml(safeUrl(imageCreditUrl)) +
           '">' +
           escapeHtml(imageSourceText) +
           "</a></p>";
@@ -145,6 +150,12 @@ function refresh_map() {
         }
         popupHTML += "<p>" + escapeHtml(imageSourceText) + "</p>";
       }
+      if (entry["image"]["license_url"] != null) {
+        popupHTML +=
+          '<p><a href="' +
+          escapeHtml(safeUrl(entry["image"]["license_url"])) +
+          '">license</a></p>';
+      }
     } else {
       popupHTML += "<p><i>No image</i></p><br />";
     }
@@ -152,7 +163,7 @@ function refresh_map() {
     // link
     popupHTML +=
       '<a href="' +
-      escapeHtml(entry["source_url"]) +
+      escapeHtml(safeUrl(entry["source_url"])) +
       '">' +
       escapeHtml(entry["source_text"]) +
       "</a>";
@@ -168,7 +179,7 @@ function refresh_map() {
 }
 
 updateStatus("fetching datas");
-xhr.open("GET", "/depiction/dragon.json", true);
+xhr.open("GET", "./depiction/dragon.json", true);
 xhr.setRequestHeader("Accept", "application/json");
 xhr.send();
 
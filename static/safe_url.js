@@ -0,0 +1,13 @@
+// Only keep the http(s) urls (relative ones included), so a link can’t run a script
+function safeUrl(url) {
+  if (url == null) {
+    return "#";
+  }
+  try {
+    const protocol = new URL(url, document.baseURI).protocol;
+    if (protocol === "http:" || protocol === "https:") {
+      return url;
+    }
+  } catch (e) {}
+  return "#";
+}